@@ -0,0 +1,120 @@
+use koopa::ir::{FunctionData, Value, ValueKind};
+use std::collections::HashSet;
+
+/// The object a pointer points into.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum Base {
+    /// an `alloc` of the function
+    Local(Value),
+    /// a global variable
+    Global(Value),
+    /// memory the function did not allocate, reached through a parameter
+    /// or a pointer loaded from memory
+    Unknown,
+}
+
+/// Which memory accesses of a function may touch the same location.
+///
+/// Pointers are traced back through `getelemptr` and `getptr` to the
+/// object they point into. Distinct objects never overlap, and a local
+/// object can only be reached through an unknown pointer, or be written by
+/// a call, once its address escapes: once it is used other than as the
+/// address of a load, a store or another pointer computation.
+pub struct AliasAnalysis {
+    escaped: HashSet<Value>,
+}
+
+impl AliasAnalysis {
+    pub fn new(func: &FunctionData) -> Self {
+        let mut escaped = HashSet::new();
+        for (value, data) in func.dfg().values() {
+            if !matches!(data.kind(), ValueKind::Alloc(_)) {
+                continue;
+            }
+            // follow the pointers computed from the alloc
+            let mut worklist = vec![*value];
+            let mut seen = HashSet::new();
+            while let Some(ptr) = worklist.pop() {
+                if !seen.insert(ptr) {
+                    continue;
+                }
+                for user in func.dfg().value(ptr).used_by() {
+                    match func.dfg().value(*user).kind() {
+                        ValueKind::Load(_) => {}
+                        ValueKind::Store(s) if s.dest() == ptr && s.value() != ptr => {}
+                        ValueKind::GetPtr(p) if p.src() == ptr => worklist.push(*user),
+                        ValueKind::GetElemPtr(p) if p.src() == ptr => worklist.push(*user),
+                        _ => {
+                            escaped.insert(*value);
+                        }
+                    }
+                }
+            }
+        }
+        AliasAnalysis { escaped }
+    }
+
+    /// The object `ptr` points into.
+    pub fn base(&self, func: &FunctionData, mut ptr: Value) -> Base {
+        loop {
+            if ptr.is_global() {
+                return Base::Global(ptr);
+            }
+            match func.dfg().value(ptr).kind() {
+                ValueKind::Alloc(_) => return Base::Local(ptr),
+                ValueKind::GetPtr(p) => ptr = p.src(),
+                ValueKind::GetElemPtr(p) => ptr = p.src(),
+                _ => return Base::Unknown,
+            }
+        }
+    }
+
+    /// Whether the address of the local object `alloc` escapes.
+    pub fn escapes(&self, alloc: Value) -> bool {
+        self.escaped.contains(&alloc)
+    }
+
+    /// Whether `a` and `b` may point to overlapping locations.
+    pub fn may_alias(&self, func: &FunctionData, a: Value, b: Value) -> bool {
+        if a == b {
+            return true;
+        }
+        // different constant offsets from the same pointer
+        if let (Some((src_a, index_a)), Some((src_b, index_b))) = (offset(func, a), offset(func, b)) {
+            if src_a == src_b && index_a != index_b {
+                return false;
+            }
+        }
+        match (self.base(func, a), self.base(func, b)) {
+            (Base::Unknown, Base::Unknown) => true,
+            (Base::Unknown, Base::Local(x)) | (Base::Local(x), Base::Unknown) => self.escapes(x),
+            (Base::Unknown, Base::Global(_)) | (Base::Global(_), Base::Unknown) => true,
+            (x, y) => x == y,
+        }
+    }
+
+    /// Whether a call may write to the location `ptr` points to.
+    pub fn call_may_write(&self, func: &FunctionData, ptr: Value) -> bool {
+        match self.base(func, ptr) {
+            Base::Local(alloc) => self.escapes(alloc),
+            _ => true,
+        }
+    }
+}
+
+/// The source and constant index of `ptr` if it is a pointer computation
+/// of the same kind from a pointer with a constant index.
+fn offset(func: &FunctionData, ptr: Value) -> Option<((Value, bool), i32)> {
+    if ptr.is_global() {
+        return None;
+    }
+    let integer = |v: Value| match func.dfg().value(v).kind() {
+        ValueKind::Integer(n) => Some(n.value()),
+        _ => None,
+    };
+    match func.dfg().value(ptr).kind() {
+        ValueKind::GetElemPtr(p) => Some(((p.src(), true), integer(p.index())?)),
+        ValueKind::GetPtr(p) => Some(((p.src(), false), integer(p.index())?)),
+        _ => None,
+    }
+}
@@ -0,0 +1,116 @@
+use koopa::ir::{Function, Program, ValueKind};
+use std::collections::HashMap;
+
+/// Which functions call which, over the functions of a program.
+pub struct CallGraph {
+    /// the functions each one calls, once each, in order of first call
+    callees: HashMap<Function, Vec<Function>>,
+    /// the number of calls to each function
+    call_sites: HashMap<Function, usize>,
+    /// strongly connected components, each after every component it calls
+    sccs: Vec<Vec<Function>>,
+    scc: HashMap<Function, usize>,
+}
+
+impl CallGraph {
+    pub fn new(program: &Program) -> Self {
+        let mut callees: HashMap<Function, Vec<Function>> = HashMap::new();
+        let mut call_sites: HashMap<Function, usize> = HashMap::new();
+        for func in program.func_layout() {
+            let data = program.func(*func);
+            let list = callees.entry(*func).or_default();
+            for node in data.layout().bbs().nodes() {
+                for inst in node.insts().keys() {
+                    if let ValueKind::Call(c) = data.dfg().value(*inst).kind() {
+                        *call_sites.entry(c.callee()).or_default() += 1;
+                        if !list.contains(&c.callee()) {
+                            list.push(c.callee());
+                        }
+                    }
+                }
+            }
+        }
+        let mut graph = CallGraph {
+            callees,
+            call_sites,
+            sccs: Vec::new(),
+            scc: HashMap::new(),
+        };
+        graph.find_sccs(program.func_layout());
+        graph
+    }
+
+    /// The functions `func` calls, in order of first call.
+    pub fn callees(&self, func: Function) -> &[Function] {
+        self.callees.get(&func).map_or(&[], |c| c.as_slice())
+    }
+
+    /// The number of calls to `func` in the program.
+    pub fn call_sites(&self, func: Function) -> usize {
+        self.call_sites.get(&func).copied().unwrap_or(0)
+    }
+
+    /// The strongly connected components, callees first: each comes after
+    /// every component its functions call, but its own.
+    pub fn sccs(&self) -> &[Vec<Function>] {
+        &self.sccs
+    }
+
+    /// Whether `func` may call itself, directly or through other functions.
+    pub fn is_recursive(&self, func: Function) -> bool {
+        self.sccs[self.scc[&func]].len() > 1 || self.callees(func).contains(&func)
+    }
+
+    /// Tarjan's algorithm, which completes components callees first.
+    fn find_sccs(&mut self, funcs: &[Function]) {
+        struct State {
+            index: HashMap<Function, usize>,
+            low: HashMap<Function, usize>,
+            stack: Vec<Function>,
+            on_stack: HashMap<Function, bool>,
+        }
+
+        fn visit(graph: &mut CallGraph, state: &mut State, func: Function) {
+            let index = state.index.len();
+            state.index.insert(func, index);
+            state.low.insert(func, index);
+            state.stack.push(func);
+            state.on_stack.insert(func, true);
+            for callee in graph.callees(func).to_vec() {
+                if !state.index.contains_key(&callee) {
+                    visit(graph, state, callee);
+                    let low = state.low[&func].min(state.low[&callee]);
+                    state.low.insert(func, low);
+                } else if state.on_stack[&callee] {
+                    let low = state.low[&func].min(state.index[&callee]);
+                    state.low.insert(func, low);
+                }
+            }
+            if state.low[&func] == index {
+                let mut scc = Vec::new();
+                loop {
+                    let member = state.stack.pop().unwrap();
+                    state.on_stack.insert(member, false);
+                    graph.scc.insert(member, graph.sccs.len());
+                    scc.push(member);
+                    if member == func {
+                        break;
+                    }
+                }
+                graph.sccs.push(scc);
+            }
+        }
+
+        let mut state = State {
+            index: HashMap::new(),
+            low: HashMap::new(),
+            stack: Vec::new(),
+            on_stack: HashMap::new(),
+        };
+        for func in funcs {
+            if !state.index.contains_key(func) {
+                visit(self, &mut state, *func);
+            }
+        }
+    }
+}
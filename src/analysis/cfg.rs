@@ -0,0 +1,68 @@
+use koopa::ir::{BasicBlock, FunctionData, ValueKind};
+use std::collections::{HashMap, HashSet};
+
+/// Successors of `bb`, read from its terminator.
+pub fn successors(func: &FunctionData, bb: BasicBlock) -> Vec<BasicBlock> {
+    let node = func.layout().bbs().node(&bb).unwrap();
+    match node.insts().back_key() {
+        Some(inst) => match func.dfg().value(*inst).kind() {
+            ValueKind::Branch(b) => vec![b.true_bb(), b.false_bb()],
+            ValueKind::Jump(j) => vec![j.target()],
+            _ => Vec::new(),
+        },
+        None => Vec::new(),
+    }
+}
+
+/// Predecessors of every basic block in the layout, in layout order.
+pub fn predecessors(func: &FunctionData) -> HashMap<BasicBlock, Vec<BasicBlock>> {
+    let mut preds: HashMap<BasicBlock, Vec<BasicBlock>> =
+        func.layout().bbs().keys().map(|bb| (*bb, Vec::new())).collect();
+    for bb in func.layout().bbs().keys() {
+        for succ in successors(func, *bb) {
+            let list = preds.get_mut(&succ).unwrap();
+            // a branch with both edges to the same block counts once
+            if !list.contains(bb) {
+                list.push(*bb);
+            }
+        }
+    }
+    preds
+}
+
+/// The basic blocks reachable from the entry, in reverse postorder.
+pub fn reverse_postorder(func: &FunctionData) -> Vec<BasicBlock> {
+    match func.layout().entry_bb() {
+        Some(entry) => reverse_postorder_from(&[entry], |bb| successors(func, bb)),
+        None => Vec::new(),
+    }
+}
+
+/// The blocks reachable from `roots` along the edges given by `succs`,
+/// in reverse postorder.
+pub fn reverse_postorder_from(
+    roots: &[BasicBlock],
+    succs: impl Fn(BasicBlock) -> Vec<BasicBlock>,
+) -> Vec<BasicBlock> {
+    let mut order = Vec::new();
+    let mut visited = HashSet::new();
+    for root in roots.iter().rev() {
+        if !visited.insert(*root) {
+            continue;
+        }
+        // iterative DFS; each frame is a block and the index of its next successor
+        let mut stack = vec![(*root, 0)];
+        while let Some((bb, i)) = stack.pop() {
+            if let Some(succ) = succs(bb).get(i) {
+                stack.push((bb, i + 1));
+                if visited.insert(*succ) {
+                    stack.push((*succ, 0));
+                }
+            } else {
+                order.push(bb);
+            }
+        }
+    }
+    order.reverse();
+    order
+}
@@ -0,0 +1,107 @@
+use crate::analysis::cfg::{predecessors, reverse_postorder, successors};
+use koopa::ir::{BasicBlock, FunctionData};
+use std::collections::{HashMap, HashSet, VecDeque};
+
+/// Which way facts flow along the edges of the control flow graph.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Direction {
+    Forward,
+    Backward,
+}
+
+/// A dataflow problem over the basic blocks of a function.
+///
+/// Facts flow from block to block in `DIRECTION`: a forward problem
+/// computes the fact at the exit of a block from the one at its entry,
+/// a backward problem the other way round. Where control flow joins,
+/// the incoming facts are combined with `meet`.
+pub trait Dataflow {
+    type Fact: Clone + PartialEq;
+    const DIRECTION: Direction;
+
+    /// The fact flowing into the function: at the entry of the entry
+    /// block going forward, at the exit of every returning block going
+    /// backward.
+    fn boundary(&self, func: &FunctionData) -> Self::Fact;
+
+    /// The starting fact of every other block, the identity of `meet`.
+    fn initial(&self, func: &FunctionData) -> Self::Fact;
+
+    /// Combines the fact of one more incoming edge into `into`.
+    fn meet(&self, into: &mut Self::Fact, fact: &Self::Fact);
+
+    /// Computes the fact on the far side of `bb` from the one flowing in.
+    fn transfer(&self, func: &FunctionData, bb: BasicBlock, fact: &Self::Fact) -> Self::Fact;
+}
+
+/// The fixed point of a dataflow problem: the fact at the entry and at
+/// the exit of every basic block, whatever the direction.
+pub struct Solution<F> {
+    pub entry: HashMap<BasicBlock, F>,
+    pub exit: HashMap<BasicBlock, F>,
+}
+
+/// Solves `problem` over `func` with a worklist, visiting the blocks in
+/// reverse postorder (or postorder, going backward) so that most facts are
+/// final the first time they are read.
+///
+/// Unreachable blocks are solved too, after the reachable ones.
+pub fn solve<P: Dataflow>(func: &FunctionData, problem: &P) -> Solution<P::Fact> {
+    let mut order = reverse_postorder(func);
+    let reachable: HashSet<BasicBlock> = order.iter().copied().collect();
+    order.extend(func.layout().bbs().keys().filter(|bb| !reachable.contains(bb)));
+    let preds = predecessors(func);
+    let succs: HashMap<BasicBlock, Vec<BasicBlock>> =
+        order.iter().map(|bb| (*bb, successors(func, *bb))).collect();
+    let (sources, targets) = match P::DIRECTION {
+        Direction::Forward => (&preds, &succs),
+        Direction::Backward => {
+            order.reverse();
+            (&succs, &preds)
+        }
+    };
+    let entry_bb = func.layout().entry_bb();
+    let is_boundary = |bb: BasicBlock| match P::DIRECTION {
+        Direction::Forward => Some(bb) == entry_bb,
+        Direction::Backward => succs[&bb].is_empty(),
+    };
+
+    // `before` is the side facts flow into, `after` the side they leave by
+    let mut before: HashMap<BasicBlock, P::Fact> = HashMap::new();
+    let mut after: HashMap<BasicBlock, P::Fact> =
+        order.iter().map(|bb| (*bb, problem.initial(func))).collect();
+    let mut worklist: VecDeque<BasicBlock> = order.iter().copied().collect();
+    let mut queued: HashSet<BasicBlock> = order.iter().copied().collect();
+    while let Some(bb) = worklist.pop_front() {
+        queued.remove(&bb);
+        let mut fact = if is_boundary(bb) {
+            problem.boundary(func)
+        } else {
+            problem.initial(func)
+        };
+        for source in sources[&bb].iter() {
+            problem.meet(&mut fact, &after[source]);
+        }
+        let out = problem.transfer(func, bb, &fact);
+        before.insert(bb, fact);
+        if out != after[&bb] {
+            after.insert(bb, out);
+            for target in targets[&bb].iter() {
+                if queued.insert(*target) {
+                    worklist.push_back(*target);
+                }
+            }
+        }
+    }
+
+    match P::DIRECTION {
+        Direction::Forward => Solution {
+            entry: before,
+            exit: after,
+        },
+        Direction::Backward => Solution {
+            entry: after,
+            exit: before,
+        },
+    }
+}
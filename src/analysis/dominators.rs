@@ -0,0 +1,194 @@
+use crate::analysis::cfg::{predecessors, reverse_postorder, reverse_postorder_from, successors};
+use koopa::ir::{BasicBlock, FunctionData};
+use std::collections::HashMap;
+
+/// A dominator tree, or a post-dominator tree over the reversed control
+/// flow graph.
+///
+/// Only blocks reachable from the root are in the tree: from the entry
+/// for dominators, and backwards from the returning blocks for
+/// post-dominators. A function may return from several blocks, so a
+/// post-dominator tree can have several roots.
+pub struct DomTree {
+    idom: HashMap<BasicBlock, BasicBlock>,
+    children: HashMap<BasicBlock, Vec<BasicBlock>>,
+    roots: Vec<BasicBlock>,
+    /// predecessors in the direction of the tree, for the frontiers
+    preds: HashMap<BasicBlock, Vec<BasicBlock>>,
+    /// preorder number and the largest preorder number below each block,
+    /// so that dominance is an interval test
+    span: HashMap<BasicBlock, (usize, usize)>,
+}
+
+impl DomTree {
+    /// The dominator tree of `func`, rooted at its entry block.
+    pub fn dominators(func: &FunctionData) -> Self {
+        let roots: Vec<BasicBlock> = func.layout().entry_bb().into_iter().collect();
+        Self::build(reverse_postorder(func), predecessors(func), roots)
+    }
+
+    /// The post-dominator tree of `func`, rooted at its returning blocks.
+    pub fn post_dominators(func: &FunctionData) -> Self {
+        let succs: HashMap<BasicBlock, Vec<BasicBlock>> =
+            func.layout().bbs().keys().map(|bb| (*bb, successors(func, *bb))).collect();
+        let preds = predecessors(func);
+        let exits: Vec<BasicBlock> =
+            func.layout().bbs().keys().copied().filter(|bb| succs[bb].is_empty()).collect();
+        let order = reverse_postorder_from(&exits, |bb| preds[&bb].clone());
+        Self::build(order, succs, exits)
+    }
+
+    /// Runs the algorithm of Cooper, Harvey and Kennedy over the blocks in
+    /// `order`, a reverse postorder from `roots`, where `preds` follows the
+    /// direction of the tree. The roots hang off a virtual root, so that
+    /// there may be several of them.
+    fn build(
+        order: Vec<BasicBlock>,
+        preds: HashMap<BasicBlock, Vec<BasicBlock>>,
+        roots: Vec<BasicBlock>,
+    ) -> Self {
+        // index 0 is the virtual root, blocks follow in order
+        let index: HashMap<BasicBlock, usize> = order.iter().enumerate().map(|(i, bb)| (*bb, i + 1)).collect();
+        let mut idom: Vec<Option<usize>> = vec![None; order.len() + 1];
+        idom[0] = Some(0);
+        for root in roots.iter() {
+            idom[index[root]] = Some(0);
+        }
+
+        let intersect = |idom: &[Option<usize>], mut a: usize, mut b: usize| {
+            while a != b {
+                while a > b {
+                    a = idom[a].unwrap();
+                }
+                while b > a {
+                    b = idom[b].unwrap();
+                }
+            }
+            a
+        };
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for bb in order.iter().filter(|bb| !roots.contains(bb)) {
+                let mut new_idom = None;
+                for p in preds[bb].iter().filter_map(|p| index.get(p)) {
+                    if idom[*p].is_some() {
+                        new_idom = Some(match new_idom {
+                            None => *p,
+                            Some(cur) => intersect(&idom, *p, cur),
+                        });
+                    }
+                }
+                let i = index[bb];
+                if new_idom.is_some() && idom[i] != new_idom {
+                    idom[i] = new_idom;
+                    changed = true;
+                }
+            }
+        }
+
+        let mut tree = DomTree {
+            idom: HashMap::new(),
+            children: order.iter().map(|bb| (*bb, Vec::new())).collect(),
+            roots: Vec::new(),
+            preds,
+            span: HashMap::new(),
+        };
+        for bb in order.iter() {
+            match idom[index[bb]].unwrap() {
+                0 => tree.roots.push(*bb),
+                d => {
+                    tree.idom.insert(*bb, order[d - 1]);
+                    tree.children.get_mut(&order[d - 1]).unwrap().push(*bb);
+                }
+            }
+        }
+        tree.number();
+        tree
+    }
+
+    /// Numbers the tree in preorder.
+    fn number(&mut self) {
+        let mut next = 0;
+        let mut stack: Vec<(BasicBlock, bool)> = self.roots.iter().rev().map(|bb| (*bb, false)).collect();
+        while let Some((bb, done)) = stack.pop() {
+            if done {
+                let start = self.span[&bb].0;
+                self.span.insert(bb, (start, next - 1));
+                continue;
+            }
+            self.span.insert(bb, (next, next));
+            next += 1;
+            stack.push((bb, true));
+            for child in self.children[&bb].iter().rev() {
+                stack.push((*child, false));
+            }
+        }
+    }
+
+    /// Whether `bb` is in the tree, that is reachable from its roots.
+    pub fn contains(&self, bb: BasicBlock) -> bool {
+        self.span.contains_key(&bb)
+    }
+
+    /// The immediate dominator of `bb`, `None` for the roots.
+    pub fn idom(&self, bb: BasicBlock) -> Option<BasicBlock> {
+        self.idom.get(&bb).copied()
+    }
+
+    /// The blocks immediately dominated by `bb`, in reverse postorder.
+    pub fn children(&self, bb: BasicBlock) -> &[BasicBlock] {
+        &self.children[&bb]
+    }
+
+    pub fn roots(&self) -> &[BasicBlock] {
+        &self.roots
+    }
+
+    /// Whether `a` dominates `b`. Every block dominates itself.
+    pub fn dominates(&self, a: BasicBlock, b: BasicBlock) -> bool {
+        match (self.span.get(&a), self.span.get(&b)) {
+            (Some((start, end)), Some((pos, _))) => start <= pos && pos <= end,
+            _ => false,
+        }
+    }
+
+    pub fn strictly_dominates(&self, a: BasicBlock, b: BasicBlock) -> bool {
+        a != b && self.dominates(a, b)
+    }
+
+    /// The blocks of the tree in preorder, so that every block comes after
+    /// its dominators.
+    pub fn preorder(&self) -> Vec<BasicBlock> {
+        let mut order: Vec<BasicBlock> = self.span.keys().copied().collect();
+        order.sort_by_key(|bb| self.span[bb].0);
+        order
+    }
+
+    /// The dominance frontier of every block of the tree: the blocks where
+    /// its dominance ends. Over post-dominators, these are the branches a
+    /// block is control dependent on.
+    pub fn frontiers(&self) -> HashMap<BasicBlock, Vec<BasicBlock>> {
+        let mut frontiers: HashMap<BasicBlock, Vec<BasicBlock>> =
+            self.span.keys().map(|bb| (*bb, Vec::new())).collect();
+        for bb in self.preorder() {
+            let preds: Vec<BasicBlock> = self.preds[&bb].iter().copied().filter(|p| self.contains(*p)).collect();
+            if preds.len() < 2 {
+                continue;
+            }
+            let idom = self.idom(bb);
+            for p in preds {
+                let mut runner = Some(p);
+                while runner.is_some() && runner != idom {
+                    let r = runner.unwrap();
+                    let frontier = frontiers.get_mut(&r).unwrap();
+                    if !frontier.contains(&bb) {
+                        frontier.push(bb);
+                    }
+                    runner = self.idom(r);
+                }
+            }
+        }
+        frontiers
+    }
+}
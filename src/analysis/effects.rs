@@ -0,0 +1,85 @@
+use crate::analysis::alias::{AliasAnalysis, Base};
+use crate::analysis::callgraph::CallGraph;
+use koopa::ir::{Function, FunctionData, Program, ValueKind};
+use std::collections::HashMap;
+
+/// What calling a function may do besides computing its result, from the
+/// least to the most.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum Effect {
+    /// the result only depends on the arguments, and nothing changes
+    Pure,
+    /// memory outside the frame may be read, but nothing changes
+    ReadOnly,
+    /// memory may be written, or input and output done
+    SideEffects,
+}
+
+/// The effect of calling each function of a program.
+///
+/// Loads and stores count unless they touch a local of the function,
+/// whose frame is gone once it returns, and calls add the effect of the
+/// callee. Declarations are the runtime library, with `getint`, `getch`
+/// and `getarray` reading input, which consumes it so that no two calls
+/// return the same, and the others writing output or timing the program,
+/// so all of them have side effects.
+pub struct Effects {
+    effects: HashMap<Function, Effect>,
+}
+
+impl Effects {
+    pub fn new(program: &Program) -> Self {
+        let mut effects = HashMap::new();
+        for func in program.func_layout() {
+            let data = program.func(*func);
+            let effect = if data.layout().entry_bb().is_none() { Effect::SideEffects } else { Effect::Pure };
+            effects.insert(*func, effect);
+        }
+        // callees first, so that only recursion takes more than one round
+        let graph = CallGraph::new(program);
+        for scc in graph.sccs() {
+            loop {
+                let mut changed = false;
+                for func in scc.iter() {
+                    let data = program.func(*func);
+                    if data.layout().entry_bb().is_none() {
+                        continue;
+                    }
+                    let effect = body_effect(data, &effects);
+                    if effect != effects[func] {
+                        effects.insert(*func, effect);
+                        changed = true;
+                    }
+                }
+                if !changed {
+                    break;
+                }
+            }
+        }
+        Effects { effects }
+    }
+
+    /// The effect of calling `func`.
+    pub fn of(&self, func: Function) -> Effect {
+        self.effects[&func]
+    }
+}
+
+/// The effect of running the body of `func`, given those of its callees.
+fn body_effect(func: &FunctionData, effects: &HashMap<Function, Effect>) -> Effect {
+    let alias = AliasAnalysis::new(func);
+    let is_local = |ptr| matches!(alias.base(func, ptr), Base::Local(_));
+    let mut effect = Effect::Pure;
+    for node in func.layout().bbs().nodes() {
+        for inst in node.insts().keys() {
+            let inst_effect = match func.dfg().value(*inst).kind() {
+                ValueKind::Load(l) if !is_local(l.src()) => Effect::ReadOnly,
+                ValueKind::Store(s) if !is_local(s.dest()) => Effect::SideEffects,
+                ValueKind::Call(c) => effects[&c.callee()],
+                _ => Effect::Pure,
+            };
+            effect = effect.max(inst_effect);
+        }
+    }
+    effect
+}
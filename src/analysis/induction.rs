@@ -0,0 +1,235 @@
+use crate::analysis::cfg::successors;
+use crate::analysis::loops::Loop;
+use koopa::ir::{BasicBlock, BinaryOp, FunctionData, Value, ValueKind};
+use std::collections::HashMap;
+
+/// Loops running longer than this are not counted.
+const MAX_TRIP_COUNT: u32 = 1 << 20;
+
+/// A basic induction variable: a parameter of a loop header that the
+/// latch passes on increased by a constant.
+pub struct InductionVar {
+    /// the parameter, and its index among those of the header
+    pub param: Value,
+    pub index: usize,
+    /// the value on entering the loop
+    pub init: Value,
+    /// the increment each iteration
+    pub step: i32,
+    /// the instruction computing the value of the next iteration
+    pub next: Value,
+}
+
+/// The basic induction variables of `l`, which must have a preheader and
+/// a single latch.
+pub fn induction_vars(func: &FunctionData, l: &Loop) -> Vec<InductionVar> {
+    let (preheader, latch) = match (l.preheader, l.latches.as_slice()) {
+        (Some(preheader), [latch]) => (preheader, *latch),
+        _ => return Vec::new(),
+    };
+    let init_args = edge_args(func, preheader, l.header);
+    let next_args = edge_args(func, latch, l.header);
+    let params = func.dfg().bb(l.header).params();
+    let mut ivs = Vec::new();
+    for (index, param) in params.iter().enumerate() {
+        let next = next_args[index];
+        if next.is_global() || func.layout().parent_bb(next).is_none() {
+            continue;
+        }
+        let step = match func.dfg().value(next).kind() {
+            ValueKind::Binary(b) if b.lhs() == *param => match (b.op(), integer(func, b.rhs())) {
+                (BinaryOp::Add, Some(step)) => step,
+                (BinaryOp::Sub, Some(step)) => step.wrapping_neg(),
+                _ => continue,
+            },
+            ValueKind::Binary(b) if b.rhs() == *param && b.op() == BinaryOp::Add => match integer(func, b.lhs()) {
+                Some(step) => step,
+                None => continue,
+            },
+            _ => continue,
+        };
+        ivs.push(InductionVar {
+            param: *param,
+            index,
+            init: init_args[index],
+            step,
+            next,
+        });
+    }
+    ivs
+}
+
+/// A derived induction variable: an instruction of a loop computing a
+/// linear function of a basic one from values invariant in the loop.
+pub struct DerivedVar {
+    pub value: Value,
+    /// the parameter of the basic variable it is derived from
+    pub iv: Value,
+}
+
+/// The derived induction variables of `l` from `ivs`, its basic ones, in
+/// the order of `order`, the blocks of the loop in dominator order, so that
+/// each comes after those it is computed from.
+///
+/// Sums and differences with an invariant value, products with one, left
+/// shifts by a constant and addresses indexed by one are derived from the
+/// variable they take.
+pub fn derived_vars(func: &FunctionData, l: &Loop, ivs: &[InductionVar], order: &[BasicBlock]) -> Vec<DerivedVar> {
+    let mut from: HashMap<Value, Value> = ivs.iter().map(|iv| (iv.param, iv.param)).collect();
+    let mut derived = Vec::new();
+    for bb in order {
+        for inst in func.layout().bbs().node(bb).unwrap().insts().keys() {
+            // the operand the instruction is derived through, if the other
+            // one is invariant
+            let linear = |a: Value, b: Value| match (from.get(&a), from.get(&b)) {
+                (Some(iv), None) if is_invariant(func, l, b) => Some(*iv),
+                (None, Some(iv)) if is_invariant(func, l, a) => Some(*iv),
+                _ => None,
+            };
+            let iv = match func.dfg().value(*inst).kind() {
+                ValueKind::Binary(b) => match b.op() {
+                    BinaryOp::Add | BinaryOp::Sub | BinaryOp::Mul => linear(b.lhs(), b.rhs()),
+                    BinaryOp::Shl if integer(func, b.rhs()).is_some() => from.get(&b.lhs()).copied(),
+                    _ => None,
+                },
+                ValueKind::GetPtr(p) => linear(p.src(), p.index()),
+                ValueKind::GetElemPtr(p) => linear(p.src(), p.index()),
+                _ => None,
+            };
+            if let Some(iv) = iv {
+                from.insert(*inst, iv);
+                derived.push(DerivedVar { value: *inst, iv });
+            }
+        }
+    }
+    derived
+}
+
+/// Whether `value` is the same on every iteration of `l`: a constant, or
+/// defined outside it.
+pub fn is_invariant(func: &FunctionData, l: &Loop, value: Value) -> bool {
+    if value.is_global() {
+        return true;
+    }
+    match func.layout().parent_bb(value) {
+        Some(bb) => !l.contains(bb),
+        None => !l.blocks.iter().any(|bb| func.dfg().bb(*bb).params().contains(&value)),
+    }
+}
+
+/// How many times a counted loop runs its body.
+pub struct TripCount {
+    pub count: u32,
+    /// the variable the exit condition tests
+    pub iv: InductionVar,
+}
+
+/// The number of times a counted loop runs its body, if it is constant.
+///
+/// The loop must only leave from its header, by a branch on comparing a
+/// basic induction variable with a constant, and the variable must start
+/// from a constant.
+pub fn trip_count(func: &FunctionData, l: &Loop) -> Option<TripCount> {
+    let (in_loop, _) = header_exit(func, l)?;
+    let term = *func.layout().bbs().node(&l.header).unwrap().insts().back_key().unwrap();
+    let cond = match func.dfg().value(term).kind() {
+        ValueKind::Branch(b) => b.cond(),
+        _ => return None,
+    };
+    let (mut op, lhs, rhs) = match func.dfg().value(cond).kind() {
+        ValueKind::Binary(b) => (b.op(), b.lhs(), b.rhs()),
+        _ => return None,
+    };
+    let stays_on_true = in_loop == taken(func, term, true);
+
+    let mut ivs = induction_vars(func, l);
+    let (index, bound, swapped) = match (ivs.iter().position(|iv| iv.param == lhs), ivs.iter().position(|iv| iv.param == rhs)) {
+        (Some(index), None) => (index, integer(func, rhs)?, false),
+        (None, Some(index)) => (index, integer(func, lhs)?, true),
+        _ => return None,
+    };
+    let iv = ivs.swap_remove(index);
+    if swapped {
+        op = mirrored(op)?;
+    }
+    let mut i = integer(func, iv.init)?;
+    let mut count = 0;
+    while compare(op, i, bound)? == stays_on_true {
+        count += 1;
+        if count > MAX_TRIP_COUNT {
+            return None;
+        }
+        i = i.wrapping_add(iv.step);
+    }
+    Some(TripCount { count, iv })
+}
+
+/// The successor of the header in the loop and the exit, if the loop only
+/// leaves from its header and every block of it has a successor in it.
+pub fn header_exit(func: &FunctionData, l: &Loop) -> Option<(BasicBlock, BasicBlock)> {
+    for bb in l.blocks.iter().skip(1) {
+        let succs = successors(func, *bb);
+        if succs.is_empty() || succs.iter().any(|s| !l.contains(*s)) {
+            return None;
+        }
+    }
+    match successors(func, l.header).as_slice() {
+        [t, f] if l.contains(*t) && !l.contains(*f) => Some((*t, *f)),
+        [t, f] if !l.contains(*t) && l.contains(*f) => Some((*f, *t)),
+        _ => None,
+    }
+}
+
+/// The arguments the terminator of `from` passes to `to`.
+pub fn edge_args(func: &FunctionData, from: BasicBlock, to: BasicBlock) -> Vec<Value> {
+    let term = *func.layout().bbs().node(&from).unwrap().insts().back_key().unwrap();
+    match func.dfg().value(term).kind() {
+        ValueKind::Jump(j) if j.target() == to => j.args().to_vec(),
+        ValueKind::Branch(b) if b.true_bb() == to => b.true_args().to_vec(),
+        ValueKind::Branch(b) if b.false_bb() == to => b.false_args().to_vec(),
+        _ => Vec::new(),
+    }
+}
+
+/// Where the branch `term` goes when its condition is `cond`.
+fn taken(func: &FunctionData, term: Value, cond: bool) -> BasicBlock {
+    match func.dfg().value(term).kind() {
+        ValueKind::Branch(b) if cond => b.true_bb(),
+        ValueKind::Branch(b) => b.false_bb(),
+        _ => unreachable!(),
+    }
+}
+
+fn integer(func: &FunctionData, value: Value) -> Option<i32> {
+    if value.is_global() {
+        return None;
+    }
+    match func.dfg().value(value).kind() {
+        ValueKind::Integer(n) => Some(n.value()),
+        _ => None,
+    }
+}
+
+/// The comparison giving the same result with its operands swapped.
+fn mirrored(op: BinaryOp) -> Option<BinaryOp> {
+    match op {
+        BinaryOp::Eq | BinaryOp::NotEq => Some(op),
+        BinaryOp::Lt => Some(BinaryOp::Gt),
+        BinaryOp::Gt => Some(BinaryOp::Lt),
+        BinaryOp::Le => Some(BinaryOp::Ge),
+        BinaryOp::Ge => Some(BinaryOp::Le),
+        _ => None,
+    }
+}
+
+fn compare(op: BinaryOp, lhs: i32, rhs: i32) -> Option<bool> {
+    match op {
+        BinaryOp::Eq => Some(lhs == rhs),
+        BinaryOp::NotEq => Some(lhs != rhs),
+        BinaryOp::Lt => Some(lhs < rhs),
+        BinaryOp::Gt => Some(lhs > rhs),
+        BinaryOp::Le => Some(lhs <= rhs),
+        BinaryOp::Ge => Some(lhs >= rhs),
+        _ => None,
+    }
+}
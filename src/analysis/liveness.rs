@@ -0,0 +1,88 @@
+use crate::analysis::dataflow::{solve, Dataflow, Direction};
+use koopa::ir::{BasicBlock, FunctionData, Value, ValueKind};
+use std::collections::{HashMap, HashSet};
+
+/// Whether `value` is a local variable: a parameter, or the result of an
+/// instruction. Constants and globals are never live or dead.
+pub fn is_variable(func: &FunctionData, value: Value) -> bool {
+    if value.is_global() {
+        return false;
+    }
+    let data = func.dfg().value(value);
+    match data.kind() {
+        ValueKind::Integer(_) | ValueKind::ZeroInit(_) | ValueKind::Undef(_) | ValueKind::Aggregate(_) => false,
+        ValueKind::FuncArgRef(_) | ValueKind::BlockArgRef(_) => true,
+        _ => !data.ty().is_unit(),
+    }
+}
+
+/// Live variables as a backward dataflow problem, with the variables each
+/// block reads before writing and the ones it writes.
+struct LiveVariables {
+    uses: HashMap<BasicBlock, HashSet<Value>>,
+    defs: HashMap<BasicBlock, HashSet<Value>>,
+}
+
+impl Dataflow for LiveVariables {
+    type Fact = HashSet<Value>;
+    const DIRECTION: Direction = Direction::Backward;
+
+    fn boundary(&self, _: &FunctionData) -> Self::Fact {
+        HashSet::new()
+    }
+
+    fn initial(&self, _: &FunctionData) -> Self::Fact {
+        HashSet::new()
+    }
+
+    fn meet(&self, into: &mut Self::Fact, fact: &Self::Fact) {
+        into.extend(fact.iter().copied());
+    }
+
+    fn transfer(&self, _: &FunctionData, bb: BasicBlock, live_out: &Self::Fact) -> Self::Fact {
+        let mut live_in: HashSet<Value> = live_out.difference(&self.defs[&bb]).copied().collect();
+        live_in.extend(self.uses[&bb].iter().copied());
+        live_in
+    }
+}
+
+/// Values live on entry to and exit from each basic block.
+pub struct Liveness {
+    pub live_in: HashMap<BasicBlock, HashSet<Value>>,
+    pub live_out: HashMap<BasicBlock, HashSet<Value>>,
+}
+
+impl Liveness {
+    /// Liveness of every local variable of `func`.
+    pub fn new(func: &FunctionData) -> Self {
+        Self::filtered(func, |v| is_variable(func, v))
+    }
+
+    /// Liveness of the variables for which `track` holds, which must be a
+    /// subset of `is_variable`.
+    pub fn filtered(func: &FunctionData, track: impl Fn(Value) -> bool) -> Self {
+        let mut uses = HashMap::new();
+        let mut defs = HashMap::new();
+        for (bb, node) in func.layout().bbs() {
+            let mut bb_uses = HashSet::new();
+            let mut bb_defs: HashSet<Value> = func.dfg().bb(*bb).params().iter().copied().collect();
+            for inst in node.insts().keys() {
+                for u in func.dfg().value(*inst).kind().value_uses() {
+                    if track(u) && !bb_defs.contains(&u) {
+                        bb_uses.insert(u);
+                    }
+                }
+                if track(*inst) {
+                    bb_defs.insert(*inst);
+                }
+            }
+            uses.insert(*bb, bb_uses);
+            defs.insert(*bb, bb_defs);
+        }
+        let solution = solve(func, &LiveVariables { uses, defs });
+        Liveness {
+            live_in: solution.entry,
+            live_out: solution.exit,
+        }
+    }
+}
@@ -0,0 +1,134 @@
+use crate::analysis::cfg::{predecessors, successors};
+use crate::analysis::dominators::DomTree;
+use koopa::ir::{BasicBlock, FunctionData};
+use std::collections::{HashMap, HashSet};
+
+/// A natural loop: a header and every block that reaches one of its back
+/// edges without going through the header.
+pub struct Loop {
+    pub header: BasicBlock,
+    /// every block of the loop, nested loops included, header first
+    pub blocks: Vec<BasicBlock>,
+    /// sources of the back edges to the header
+    pub latches: Vec<BasicBlock>,
+    /// blocks outside the loop jumped to from inside it
+    pub exits: Vec<BasicBlock>,
+    /// the only block entering the loop, if the header is its only
+    /// successor
+    pub preheader: Option<BasicBlock>,
+    /// index of the innermost enclosing loop
+    pub parent: Option<usize>,
+    pub children: Vec<usize>,
+    /// 1 for outermost loops
+    pub depth: u32,
+}
+
+impl Loop {
+    pub fn contains(&self, bb: BasicBlock) -> bool {
+        self.blocks.contains(&bb)
+    }
+}
+
+/// The loop nest of a function.
+pub struct LoopInfo {
+    /// every loop, each after the loops nested in it
+    pub loops: Vec<Loop>,
+    /// the innermost loop of each block in a loop
+    innermost: HashMap<BasicBlock, usize>,
+}
+
+impl LoopInfo {
+    /// Finds the natural loops of `func` from the back edges of `dom`, its
+    /// dominator tree. Loops sharing a header are merged into one.
+    pub fn new(func: &FunctionData, dom: &DomTree) -> Self {
+        let preds = predecessors(func);
+
+        // headers in postorder of the dominator tree, so inner loops come first
+        let mut headers: Vec<BasicBlock> = Vec::new();
+        let mut latches: HashMap<BasicBlock, Vec<BasicBlock>> = HashMap::new();
+        for bb in dom.preorder().into_iter().rev() {
+            for p in preds[&bb].iter().filter(|p| dom.contains(**p)) {
+                if dom.dominates(bb, *p) {
+                    if !latches.contains_key(&bb) {
+                        headers.push(bb);
+                    }
+                    latches.entry(bb).or_default().push(*p);
+                }
+            }
+        }
+
+        let mut loops: Vec<Loop> = Vec::new();
+        for header in headers {
+            let latches = latches.remove(&header).unwrap();
+            let mut blocks = vec![header];
+            let mut seen: HashSet<BasicBlock> = blocks.iter().copied().collect();
+            let mut stack = latches.clone();
+            while let Some(bb) = stack.pop() {
+                if seen.insert(bb) {
+                    blocks.push(bb);
+                    stack.extend(preds[&bb].iter().copied().filter(|p| dom.contains(*p)));
+                }
+            }
+
+            let mut exits = Vec::new();
+            for bb in blocks.iter() {
+                for succ in successors(func, *bb) {
+                    if !seen.contains(&succ) && !exits.contains(&succ) {
+                        exits.push(succ);
+                    }
+                }
+            }
+
+            let entering: Vec<BasicBlock> =
+                preds[&header].iter().copied().filter(|p| !seen.contains(p)).collect();
+            let preheader = match entering.as_slice() {
+                [p] if successors(func, *p).as_slice() == [header] => Some(*p),
+                _ => None,
+            };
+
+            loops.push(Loop {
+                header,
+                blocks,
+                latches,
+                exits,
+                preheader,
+                parent: None,
+                children: Vec::new(),
+                depth: 0,
+            });
+        }
+
+        // inner loops come first, so the first later loop holding a header
+        // is the innermost one around it
+        for i in 0..loops.len() {
+            let header = loops[i].header;
+            if let Some(parent) = (i + 1..loops.len()).find(|j| loops[*j].contains(header)) {
+                loops[i].parent = Some(parent);
+                loops[parent].children.push(i);
+            }
+        }
+        for i in (0..loops.len()).rev() {
+            loops[i].depth = match loops[i].parent {
+                Some(parent) => loops[parent].depth + 1,
+                None => 1,
+            };
+        }
+        let mut innermost = HashMap::new();
+        for (i, l) in loops.iter().enumerate() {
+            for bb in l.blocks.iter() {
+                innermost.entry(*bb).or_insert(i);
+            }
+        }
+        LoopInfo { loops, innermost }
+    }
+
+    /// The innermost loop containing `bb`.
+    pub fn innermost(&self, bb: BasicBlock) -> Option<usize> {
+        self.innermost.get(&bb).copied()
+    }
+
+    /// Number of loops around `bb`, 0 outside of any.
+    pub fn depth(&self, bb: BasicBlock) -> u32 {
+        self.innermost(bb).map_or(0, |l| self.loops[l].depth)
+    }
+}
@@ -0,0 +1,9 @@
+pub mod alias;
+pub mod callgraph;
+pub mod cfg;
+pub mod dataflow;
+pub mod dominators;
+pub mod effects;
+pub mod induction;
+pub mod liveness;
+pub mod loops;
@@ -0,0 +1,485 @@
+use crate::analysis::liveness::Liveness;
+use crate::asm::liveness::{has_location, intervals, liveness, Numbering};
+use crate::asm::reg::*;
+use crate::asm::regalloc::{access_weight, block_depths, Allocation};
+use koopa::ir::{FunctionData, Value, ValueKind};
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+/// Number of physical registers, which take the first node indices.
+const PHYS: usize = 32;
+
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum NodeState {
+    Precolored,
+    Simplify,
+    Freeze,
+    Spill,
+    Selected,
+    Coalesced,
+    Colored,
+    Spilled,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum MoveState {
+    Worklist,
+    Active,
+    Coalesced,
+    Constrained,
+    Frozen,
+}
+
+/// Iterated register coalescing (George and Appel) over the interference
+/// graph of one function.
+///
+/// Nodes are the physical registers, precolored, followed by every value
+/// that needs a location. Moves are the copies the backend emits for
+/// block arguments, call arguments and results, incoming parameters and
+/// return values; coalescing them lets those copies disappear.
+struct Coloring {
+    values: Vec<Value>,
+    adj_set: HashSet<(usize, usize)>,
+    adj_list: Vec<Vec<usize>>,
+    degree: Vec<usize>,
+    /// loop-weighted number of accesses, summed over coalesced nodes
+    cost: Vec<f64>,
+    moves: Vec<(usize, usize)>,
+    move_list: Vec<Vec<usize>>,
+    move_state: Vec<MoveState>,
+    worklist_moves: BTreeSet<usize>,
+    state: Vec<NodeState>,
+    simplify_worklist: BTreeSet<usize>,
+    freeze_worklist: BTreeSet<usize>,
+    spill_worklist: BTreeSet<usize>,
+    select_stack: Vec<usize>,
+    alias: Vec<usize>,
+    color: Vec<Option<Reg>>,
+    /// registers a node may be given, caller-saved first as they cost
+    /// nothing to use
+    pool: Vec<Reg>,
+}
+
+impl Coloring {
+    fn new(func: &FunctionData) -> Self {
+        let numbering = Numbering::new(func);
+        let liveness = liveness(func);
+        let depths = block_depths(func, &numbering);
+        let intervals = intervals(func, &numbering, &liveness);
+
+        let pool: Vec<Reg> = CALLER_SAVED.iter().chain(CALLEE_SAVED.iter()).copied().collect();
+        let n = PHYS + intervals.len();
+        let mut coloring = Coloring {
+            values: intervals.iter().map(|i| i.value).collect(),
+            adj_set: HashSet::new(),
+            adj_list: vec![Vec::new(); n],
+            degree: vec![0; n],
+            cost: vec![0.0; n],
+            moves: Vec::new(),
+            move_list: vec![Vec::new(); n],
+            move_state: Vec::new(),
+            worklist_moves: BTreeSet::new(),
+            state: vec![NodeState::Simplify; n],
+            simplify_worklist: BTreeSet::new(),
+            freeze_worklist: BTreeSet::new(),
+            spill_worklist: BTreeSet::new(),
+            select_stack: Vec::new(),
+            alias: (0..n).collect(),
+            color: vec![None; n],
+            pool,
+        };
+        for r in 0..PHYS {
+            coloring.state[r] = NodeState::Precolored;
+            coloring.degree[r] = usize::MAX / 2;
+            coloring.color[r] = Some(r as Reg);
+        }
+        for (i, interval) in intervals.iter().enumerate() {
+            coloring.cost[PHYS + i] = access_weight(&interval.uses, &depths);
+        }
+        coloring.build(func, &liveness);
+        coloring
+    }
+
+    fn k(&self) -> usize {
+        self.pool.len()
+    }
+
+    /// Builds the interference graph and the move list by walking every
+    /// basic block backwards from the values live on exit.
+    fn build(&mut self, func: &FunctionData, liveness: &Liveness) {
+        let index: HashMap<Value, usize> =
+            self.values.iter().enumerate().map(|(i, v)| (*v, PHYS + i)).collect();
+        let located = |v: Value| has_location(func, v);
+
+        for (bb, node) in func.layout().bbs() {
+            let mut live: HashSet<Value> = liveness.live_out[bb].clone();
+            let insts: Vec<Value> = node.insts().keys().copied().collect();
+            for inst in insts.into_iter().rev() {
+                let kind = func.dfg().value(inst).kind();
+                match kind {
+                    ValueKind::Call(c) => {
+                        // whatever survives the call must not be in a register it clobbers
+                        for v in live.iter().filter(|v| **v != inst) {
+                            for r in CALLER_SAVED.iter() {
+                                self.add_edge(index[v], *r as usize);
+                            }
+                        }
+                        if located(inst) {
+                            self.add_move(index[&inst], A0 as usize);
+                        }
+                        for (arg, r) in c.args().iter().zip(ARGS.iter()) {
+                            if located(*arg) {
+                                self.add_move(*r as usize, index[arg]);
+                            }
+                        }
+                    }
+                    ValueKind::Return(r) => {
+                        if let Some(v) = r.value().filter(|v| located(*v)) {
+                            self.add_move(A0 as usize, index[&v]);
+                        }
+                    }
+                    ValueKind::Jump(j) => self.add_edge_moves(func, &index, j.target(), j.args()),
+                    ValueKind::Branch(b) => {
+                        self.add_edge_moves(func, &index, b.true_bb(), b.true_args());
+                        self.add_edge_moves(func, &index, b.false_bb(), b.false_args());
+                    }
+                    _ => {}
+                }
+                if located(inst) {
+                    live.remove(&inst);
+                    for v in live.iter() {
+                        self.add_edge(index[&inst], index[v]);
+                    }
+                }
+                for u in kind.value_uses() {
+                    if located(u) {
+                        live.insert(u);
+                    }
+                }
+            }
+
+            // parameters are all written at once on entry to their block
+            let mut params: Vec<Value> = func.dfg().bb(*bb).params().to_vec();
+            if Some(*bb) == func.layout().entry_bb() {
+                for (param, r) in func.params().iter().zip(ARGS.iter()) {
+                    self.add_move(index[param], *r as usize);
+                }
+                params.extend(func.params().iter().copied());
+            }
+            live.extend(params.iter().copied());
+            for p in params.iter() {
+                for v in live.iter() {
+                    self.add_edge(index[p], index[v]);
+                }
+            }
+        }
+    }
+
+    fn add_edge_moves(
+        &mut self,
+        func: &FunctionData,
+        index: &HashMap<Value, usize>,
+        target: koopa::ir::BasicBlock,
+        args: &[Value],
+    ) {
+        for (param, arg) in func.dfg().bb(target).params().iter().zip(args) {
+            if has_location(func, *arg) {
+                self.add_move(index[param], index[arg]);
+            }
+        }
+    }
+
+    fn add_edge(&mut self, u: usize, v: usize) {
+        if u == v || self.adj_set.contains(&(u, v)) {
+            return;
+        }
+        self.adj_set.insert((u, v));
+        self.adj_set.insert((v, u));
+        for (a, b) in [(u, v), (v, u)] {
+            if self.state[a] != NodeState::Precolored {
+                self.adj_list[a].push(b);
+                self.degree[a] += 1;
+            }
+        }
+    }
+
+    fn add_move(&mut self, dst: usize, src: usize) {
+        if dst == src {
+            return;
+        }
+        let m = self.moves.len();
+        self.moves.push((dst, src));
+        self.move_state.push(MoveState::Worklist);
+        self.worklist_moves.insert(m);
+        self.move_list[dst].push(m);
+        self.move_list[src].push(m);
+    }
+
+    fn precolored(&self, n: usize) -> bool {
+        self.state[n] == NodeState::Precolored
+    }
+
+    fn adjacent(&self, n: usize) -> Vec<usize> {
+        self.adj_list[n]
+            .iter()
+            .copied()
+            .filter(|m| !matches!(self.state[*m], NodeState::Selected | NodeState::Coalesced))
+            .collect()
+    }
+
+    fn node_moves(&self, n: usize) -> Vec<usize> {
+        self.move_list[n]
+            .iter()
+            .copied()
+            .filter(|m| matches!(self.move_state[*m], MoveState::Worklist | MoveState::Active))
+            .collect()
+    }
+
+    fn move_related(&self, n: usize) -> bool {
+        !self.node_moves(n).is_empty()
+    }
+
+    /// Moves `n` to the worklist named by `state`, out of the one it is in.
+    fn set_state(&mut self, n: usize, state: NodeState) {
+        match self.state[n] {
+            NodeState::Simplify => self.simplify_worklist.remove(&n),
+            NodeState::Freeze => self.freeze_worklist.remove(&n),
+            NodeState::Spill => self.spill_worklist.remove(&n),
+            _ => false,
+        };
+        match state {
+            NodeState::Simplify => self.simplify_worklist.insert(n),
+            NodeState::Freeze => self.freeze_worklist.insert(n),
+            NodeState::Spill => self.spill_worklist.insert(n),
+            _ => false,
+        };
+        self.state[n] = state;
+    }
+
+    fn make_worklist(&mut self) {
+        for n in PHYS..self.state.len() {
+            let state = if self.degree[n] >= self.k() {
+                NodeState::Spill
+            } else if self.move_related(n) {
+                NodeState::Freeze
+            } else {
+                NodeState::Simplify
+            };
+            self.set_state(n, state);
+        }
+    }
+
+    fn simplify(&mut self, n: usize) {
+        self.set_state(n, NodeState::Selected);
+        self.select_stack.push(n);
+        for m in self.adjacent(n) {
+            self.decrement_degree(m);
+        }
+    }
+
+    fn decrement_degree(&mut self, m: usize) {
+        if self.precolored(m) {
+            return;
+        }
+        let d = self.degree[m];
+        self.degree[m] -= 1;
+        if d == self.k() {
+            let mut nodes = self.adjacent(m);
+            nodes.push(m);
+            self.enable_moves(&nodes);
+            if self.state[m] == NodeState::Spill {
+                let state = if self.move_related(m) { NodeState::Freeze } else { NodeState::Simplify };
+                self.set_state(m, state);
+            }
+        }
+    }
+
+    fn enable_moves(&mut self, nodes: &[usize]) {
+        for n in nodes {
+            for m in self.node_moves(*n) {
+                if self.move_state[m] == MoveState::Active {
+                    self.move_state[m] = MoveState::Worklist;
+                    self.worklist_moves.insert(m);
+                }
+            }
+        }
+    }
+
+    fn alias(&self, mut n: usize) -> usize {
+        while self.state[n] == NodeState::Coalesced {
+            n = self.alias[n];
+        }
+        n
+    }
+
+    fn add_worklist(&mut self, u: usize) {
+        if !self.precolored(u) && !self.move_related(u) && self.degree[u] < self.k() {
+            self.set_state(u, NodeState::Simplify);
+        }
+    }
+
+    /// George's test for merging `u`, precolored, into a neighbour of `t`.
+    fn ok(&self, t: usize, u: usize) -> bool {
+        self.degree[t] < self.k() || self.precolored(t) || self.adj_set.contains(&(t, u))
+    }
+
+    /// Briggs' test: the merged node has fewer than K significant neighbours.
+    fn conservative(&self, nodes: &[usize]) -> bool {
+        let mut seen = HashSet::new();
+        let significant = nodes
+            .iter()
+            .filter(|n| seen.insert(**n) && self.degree[**n] >= self.k())
+            .count();
+        significant < self.k()
+    }
+
+    fn coalesce(&mut self, m: usize) {
+        let (dst, src) = self.moves[m];
+        let (x, y) = (self.alias(dst), self.alias(src));
+        let (u, v) = if self.precolored(y) { (y, x) } else { (x, y) };
+        if u == v {
+            self.move_state[m] = MoveState::Coalesced;
+            self.add_worklist(u);
+        } else if self.precolored(v) || self.adj_set.contains(&(u, v)) {
+            self.move_state[m] = MoveState::Constrained;
+            self.add_worklist(u);
+            self.add_worklist(v);
+        } else if (self.precolored(u) && self.adjacent(v).iter().all(|t| self.ok(*t, u)))
+            || (!self.precolored(u) && {
+                let mut nodes = self.adjacent(u);
+                nodes.extend(self.adjacent(v));
+                self.conservative(&nodes)
+            })
+        {
+            self.move_state[m] = MoveState::Coalesced;
+            self.combine(u, v);
+            self.add_worklist(u);
+        } else {
+            self.move_state[m] = MoveState::Active;
+        }
+    }
+
+    fn combine(&mut self, u: usize, v: usize) {
+        self.set_state(v, NodeState::Coalesced);
+        self.alias[v] = u;
+        self.cost[u] += self.cost[v];
+        let moves = self.move_list[v].clone();
+        self.move_list[u].extend(moves);
+        self.enable_moves(&[v]);
+        for t in self.adjacent(v) {
+            self.add_edge(t, u);
+            self.decrement_degree(t);
+        }
+        if self.degree[u] >= self.k() && self.state[u] == NodeState::Freeze {
+            self.set_state(u, NodeState::Spill);
+        }
+    }
+
+    fn freeze(&mut self, u: usize) {
+        self.set_state(u, NodeState::Simplify);
+        self.freeze_moves(u);
+    }
+
+    /// Gives up on coalescing the moves of `u`.
+    fn freeze_moves(&mut self, u: usize) {
+        for m in self.node_moves(u) {
+            let (x, y) = self.moves[m];
+            let v = if self.alias(y) == self.alias(u) { self.alias(x) } else { self.alias(y) };
+            self.worklist_moves.remove(&m);
+            self.move_state[m] = MoveState::Frozen;
+            if self.state[v] == NodeState::Freeze && !self.move_related(v) && self.degree[v] < self.k() {
+                self.set_state(v, NodeState::Simplify);
+            }
+        }
+    }
+
+    /// Picks the node whose spilling costs least per neighbour relieved.
+    fn select_spill(&mut self) {
+        let m = self
+            .spill_worklist
+            .iter()
+            .copied()
+            .min_by(|a, b| {
+                let a = self.cost[*a] / self.degree[*a] as f64;
+                let b = self.cost[*b] / self.degree[*b] as f64;
+                a.partial_cmp(&b).unwrap()
+            })
+            .unwrap();
+        self.set_state(m, NodeState::Simplify);
+        self.freeze_moves(m);
+    }
+
+    fn assign_colors(&mut self) {
+        while let Some(n) = self.select_stack.pop() {
+            let mut ok: Vec<Reg> = self.pool.clone();
+            for w in self.adj_list[n].iter() {
+                let w = self.alias(*w);
+                if let Some(c) = self.color[w] {
+                    ok.retain(|r| *r != c);
+                }
+            }
+            if ok.is_empty() {
+                self.state[n] = NodeState::Spilled;
+                continue;
+            }
+            // prefer the color of a move partner, so the move becomes a no-op
+            let partner = self.move_list[n].iter().find_map(|m| {
+                let (x, y) = self.moves[*m];
+                let other = if self.alias(x) == n { self.alias(y) } else { self.alias(x) };
+                self.color[other].filter(|c| ok.contains(c))
+            });
+            self.color[n] = Some(partner.unwrap_or(ok[0]));
+            self.state[n] = NodeState::Colored;
+        }
+    }
+
+    fn run(&mut self) {
+        self.make_worklist();
+        loop {
+            if let Some(n) = self.simplify_worklist.iter().next().copied() {
+                self.simplify(n);
+            } else if let Some(m) = self.worklist_moves.iter().next().copied() {
+                self.worklist_moves.remove(&m);
+                self.coalesce(m);
+            } else if let Some(n) = self.freeze_worklist.iter().next().copied() {
+                self.freeze(n);
+            } else if !self.spill_worklist.is_empty() {
+                self.select_spill();
+            } else {
+                break;
+            }
+        }
+        self.assign_colors();
+    }
+
+    fn allocation(&self) -> Allocation {
+        let mut allocation = Allocation::default();
+        // nodes coalesced into a spilled one share its slot
+        let mut spilled: HashMap<usize, Value> = HashMap::new();
+        for (i, value) in self.values.iter().enumerate() {
+            let n = self.alias(PHYS + i);
+            match self.color[n] {
+                Some(r) => allocation.assign(*value, r),
+                None => match spilled.get(&n) {
+                    Some(other) => allocation.share_slot(*value, *other),
+                    None => {
+                        allocation.spill(*value);
+                        spilled.insert(n, *value);
+                    }
+                },
+            }
+        }
+        allocation.collect_callee_saved();
+        allocation
+    }
+}
+
+/// Allocates registers by iterated register coalescing, spilling the
+/// values with the fewest loop-weighted accesses per interference.
+///
+/// Spilled values need no rewriting: the backend reaches them through the
+/// scratch registers, so a single round of coloring is enough.
+pub fn graph_coloring(func: &FunctionData) -> Allocation {
+    let mut coloring = Coloring::new(func);
+    coloring.run();
+    coloring.allocation()
+}
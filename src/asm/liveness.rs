@@ -0,0 +1,148 @@
+use crate::analysis::liveness::Liveness;
+use koopa::ir::{BasicBlock, FunctionData, Value, ValueKind};
+use std::collections::HashMap;
+
+/// Whether `value` needs a register or spill slot of its own. Constants,
+/// `alloc`s and globals are rematerialized at every use instead.
+pub fn has_location(func: &FunctionData, value: Value) -> bool {
+    if value.is_global() {
+        return false;
+    }
+    let data = func.dfg().value(value);
+    match data.kind() {
+        ValueKind::FuncArgRef(_)
+        | ValueKind::BlockArgRef(_)
+        | ValueKind::Load(_)
+        | ValueKind::GetPtr(_)
+        | ValueKind::GetElemPtr(_)
+        | ValueKind::Binary(_) => true,
+        ValueKind::Call(_) => !data.ty().is_unit(),
+        _ => false,
+    }
+}
+
+/// Liveness of the values that need a location.
+pub fn liveness(func: &FunctionData) -> Liveness {
+    Liveness::filtered(func, |v| has_location(func, v))
+}
+
+/// The positions a value is live at, hulled into one range.
+///
+/// Instructions are numbered by two in layout order: operands are read at
+/// an instruction's position and its result is written one past it, so a
+/// value dying at an instruction can share a register with the result.
+#[derive(Debug)]
+pub struct Interval {
+    pub value: Value,
+    pub start: usize,
+    pub end: usize,
+    /// positions of the definition and of every use
+    pub uses: Vec<usize>,
+    /// live across a call, so only callee-saved registers survive
+    pub crosses_call: bool,
+}
+
+/// Linear numbering of a function in layout order.
+pub struct Numbering {
+    pub inst_pos: HashMap<Value, usize>,
+    /// first and last position of each basic block
+    pub bb_range: HashMap<BasicBlock, (usize, usize)>,
+    /// positions of every `call`
+    pub calls: Vec<usize>,
+}
+
+impl Numbering {
+    pub fn new(func: &FunctionData) -> Self {
+        let mut inst_pos = HashMap::new();
+        let mut bb_range = HashMap::new();
+        let mut calls = Vec::new();
+        // function parameters are defined at 0
+        let mut pos = 2;
+        for (bb, node) in func.layout().bbs() {
+            let start = pos;
+            pos += 2;
+            for inst in node.insts().keys() {
+                inst_pos.insert(*inst, pos);
+                if let ValueKind::Call(_) = func.dfg().value(*inst).kind() {
+                    calls.push(pos);
+                }
+                pos += 2;
+            }
+            bb_range.insert(*bb, (start, pos - 1));
+        }
+        Numbering {
+            inst_pos,
+            bb_range,
+            calls,
+        }
+    }
+}
+
+/// Builds the live interval of every value that needs a location, sorted
+/// by start position.
+pub fn intervals(func: &FunctionData, numbering: &Numbering, liveness: &Liveness) -> Vec<Interval> {
+    // definition order breaks ties between equal ranges deterministically
+    let mut order = Vec::new();
+    let mut ranges: HashMap<Value, (usize, usize)> = HashMap::new();
+    let mut uses: HashMap<Value, Vec<usize>> = HashMap::new();
+    let mut define = |v: Value, pos: usize| {
+        order.push(v);
+        ranges.insert(v, (pos, pos));
+        uses.insert(v, vec![pos]);
+    };
+
+    for param in func.params() {
+        define(*param, 0);
+    }
+    for (bb, node) in func.layout().bbs() {
+        let (start, _) = numbering.bb_range[bb];
+        for param in func.dfg().bb(*bb).params() {
+            define(*param, start);
+        }
+        for inst in node.insts().keys() {
+            if has_location(func, *inst) {
+                define(*inst, numbering.inst_pos[inst] + 1);
+            }
+        }
+    }
+
+    let mut extend = |v: Value, pos: usize| {
+        let range = ranges.get_mut(&v).unwrap();
+        range.0 = range.0.min(pos);
+        range.1 = range.1.max(pos);
+    };
+    for (bb, node) in func.layout().bbs() {
+        let (start, end) = numbering.bb_range[bb];
+        for v in &liveness.live_in[bb] {
+            extend(*v, start);
+        }
+        for v in &liveness.live_out[bb] {
+            extend(*v, end);
+        }
+        for inst in node.insts().keys() {
+            let pos = numbering.inst_pos[inst];
+            for u in func.dfg().value(*inst).kind().value_uses() {
+                if has_location(func, u) {
+                    extend(u, pos);
+                    uses.get_mut(&u).unwrap().push(pos);
+                }
+            }
+        }
+    }
+
+    let mut intervals: Vec<Interval> = order
+        .into_iter()
+        .map(|value| {
+            let (start, end) = ranges[&value];
+            Interval {
+                value,
+                start,
+                end,
+                uses: uses.remove(&value).unwrap(),
+                crosses_call: numbering.calls.iter().any(|c| start <= *c && *c < end),
+            }
+        })
+        .collect();
+    intervals.sort_by_key(|i| i.start);
+    intervals
+}
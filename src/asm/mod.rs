@@ -1,2 +1,7 @@
+pub mod coloring;
+pub mod liveness;
+pub mod reg;
+pub mod regalloc;
+pub mod relax;
 pub mod vm;
 pub mod visitor;
@@ -0,0 +1,37 @@
+/// Index into the RV32 integer register file, `x0` to `x31`.
+pub type Reg = u8;
+
+pub const ZERO: Reg = 0;
+pub const RA: Reg = 1;
+pub const SP: Reg = 2;
+pub const T0: Reg = 5;
+pub const S0: Reg = 8;
+pub const A0: Reg = 10;
+
+const NAMES: [&str; 32] = [
+    "x0", "ra", "sp", "gp", "tp", "t0", "t1", "t2", "s0", "s1", "a0", "a1", "a2", "a3", "a4",
+    "a5", "a6", "a7", "s2", "s3", "s4", "s5", "s6", "s7", "s8", "s9", "s10", "s11", "t3", "t4",
+    "t5", "t6",
+];
+
+/// argument registers, in argument order
+pub const ARGS: [Reg; 8] = [10, 11, 12, 13, 14, 15, 16, 17];
+
+/// t0-t6 then a0-a7, clobbered by every call
+pub const CALLER_SAVED: [Reg; 15] = [5, 6, 7, 28, 29, 30, 31, 10, 11, 12, 13, 14, 15, 16, 17];
+
+/// s1-s11, saved in the prologue of any function that uses them
+pub const CALLEE_SAVED: [Reg; 11] = [9, 18, 19, 20, 21, 22, 23, 24, 25, 26, 27];
+
+/// Registers kept out of allocation for spill code, address computation
+/// and breaking move cycles. `s0` is callee-saved and `ra` is dead between
+/// calls once saved, so both are saved in the prologue whenever used.
+pub const SCRATCH: [Reg; 2] = [S0, RA];
+
+pub fn name(reg: Reg) -> &'static str {
+    NAMES[reg as usize]
+}
+
+pub fn is_callee_saved(reg: Reg) -> bool {
+    reg == S0 || CALLEE_SAVED.contains(&reg)
+}
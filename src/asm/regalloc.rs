@@ -0,0 +1,166 @@
+use crate::asm::coloring::graph_coloring;
+use crate::analysis::dominators::DomTree;
+use crate::analysis::loops::LoopInfo;
+use crate::asm::liveness::{intervals, liveness, Interval, Numbering};
+use crate::asm::reg::*;
+use koopa::ir::{FunctionData, Value};
+use std::collections::{HashMap, HashSet};
+
+/// Where a value lives for its whole lifetime.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Loc {
+    Reg(Reg),
+    /// index of a 4-byte spill slot in the frame
+    Spill(u32),
+}
+
+/// The result of register allocation for one function.
+#[derive(Default)]
+pub struct Allocation {
+    pub locs: HashMap<Value, Loc>,
+    pub spill_slots: u32,
+    /// callee-saved registers handed out, to be saved in the prologue
+    pub callee_saved: Vec<Reg>,
+}
+
+impl Allocation {
+    pub fn assign(&mut self, value: Value, reg: Reg) {
+        self.locs.insert(value, Loc::Reg(reg));
+    }
+
+    pub fn spill(&mut self, value: Value) {
+        self.locs.insert(value, Loc::Spill(self.spill_slots));
+        self.spill_slots += 1;
+    }
+
+    /// Puts `value` in the same spill slot as `other`, which must be spilled.
+    pub fn share_slot(&mut self, value: Value, other: Value) {
+        self.locs.insert(value, self.locs[&other]);
+    }
+
+    pub fn collect_callee_saved(&mut self) {
+        let mut regs: Vec<Reg> = self
+            .locs
+            .values()
+            .filter_map(|loc| match loc {
+                Loc::Reg(r) if is_callee_saved(*r) => Some(*r),
+                _ => None,
+            })
+            .collect();
+        regs.sort();
+        regs.dedup();
+        self.callee_saved = regs;
+    }
+}
+
+/// The register allocation algorithm, picked by the optimization level.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum Allocator {
+    #[default]
+    LinearScan,
+    GraphColoring,
+}
+
+impl Allocator {
+    pub fn allocate(self, func: &FunctionData) -> Allocation {
+        match self {
+            Allocator::LinearScan => linear_scan(func),
+            Allocator::GraphColoring => graph_coloring(func),
+        }
+    }
+}
+
+/// Loop nesting depth of every block, as the range of positions it
+/// covers, sorted by position.
+pub fn block_depths(func: &FunctionData, numbering: &Numbering) -> Vec<((usize, usize), u32)> {
+    let loops = LoopInfo::new(func, &DomTree::dominators(func));
+    let mut depths: Vec<((usize, usize), u32)> = func
+        .layout()
+        .bbs()
+        .keys()
+        .map(|bb| (numbering.bb_range[bb], loops.depth(*bb)))
+        .collect();
+    depths.sort_by_key(|(range, _)| *range);
+    depths
+}
+
+/// How often the accesses at `uses` run, counting ten times per
+/// enclosing loop.
+pub fn access_weight(uses: &[usize], depths: &[((usize, usize), u32)]) -> f64 {
+    uses.iter()
+        .map(|pos| {
+            // parameters are defined before the first block
+            let i = depths.partition_point(|((start, _), _)| start <= pos);
+            let depth = if i == 0 { 0 } else { depths[i - 1].1 };
+            10f64.powi(depth as i32)
+        })
+        .sum()
+}
+
+/// How much keeping `interval` in a register saves: its accesses spread
+/// over the length of the interval.
+fn spill_weight(interval: &Interval, depths: &[((usize, usize), u32)]) -> f64 {
+    access_weight(&interval.uses, depths) / (interval.end - interval.start + 1) as f64
+}
+
+/// Allocates registers with linear scan over live intervals.
+///
+/// Intervals that live across a call only get callee-saved registers;
+/// others prefer the caller-saved ones, which cost nothing to use. When
+/// no register is free, the interval with the lowest spill weight among
+/// the current one and the active ones competing for the same registers
+/// is spilled to the stack.
+pub fn linear_scan(func: &FunctionData) -> Allocation {
+    let numbering = Numbering::new(func);
+    let liveness = liveness(func);
+    let intervals = intervals(func, &numbering, &liveness);
+    let depths = block_depths(func, &numbering);
+    let weights: Vec<f64> = intervals.iter().map(|i| spill_weight(i, &depths)).collect();
+
+    let any: Vec<Reg> = CALLER_SAVED.iter().chain(CALLEE_SAVED.iter()).copied().collect();
+    let mut free: HashSet<Reg> = any.iter().copied().collect();
+    let mut reg_of: HashMap<usize, Reg> = HashMap::new();
+    let mut active: Vec<usize> = Vec::new();
+    let mut allocation = Allocation::default();
+
+    for (idx, cur) in intervals.iter().enumerate() {
+        active.retain(|a| {
+            if intervals[*a].end < cur.start {
+                free.insert(reg_of[a]);
+                false
+            } else {
+                true
+            }
+        });
+
+        let pool: &[Reg] = if cur.crosses_call { &CALLEE_SAVED } else { &any };
+        if let Some(reg) = pool.iter().find(|r| free.contains(r)) {
+            free.remove(reg);
+            reg_of.insert(idx, *reg);
+            active.push(idx);
+            continue;
+        }
+
+        let victim = active
+            .iter()
+            .copied()
+            .filter(|a| pool.contains(&reg_of[a]))
+            .min_by(|a, b| weights[*a].partial_cmp(&weights[*b]).unwrap());
+        match victim {
+            Some(v) if weights[v] < weights[idx] => {
+                let reg = reg_of.remove(&v).unwrap();
+                allocation.spill(intervals[v].value);
+                active.retain(|a| *a != v);
+                reg_of.insert(idx, reg);
+                active.push(idx);
+            }
+            _ => allocation.spill(cur.value),
+        }
+    }
+
+    for (idx, reg) in reg_of {
+        allocation.assign(intervals[idx].value, reg);
+    }
+    allocation.collect_callee_saved();
+    allocation
+}
@@ -0,0 +1,75 @@
+use std::collections::HashMap;
+
+/// How far a conditional branch reaches either way, in bytes.
+const BRANCH_RANGE: usize = 1 << 12;
+
+/// An upper bound on the size of a line of generated code: pseudo
+/// instructions such as `li`, `la` and `call` take up to two instructions.
+const MAX_LINE_SIZE: usize = 8;
+
+/// Rewrites the conditional branches of `code`, the code of function
+/// `func`, that may not reach their target into the opposite branch over
+/// a `j`, which reaches much farther.
+///
+/// Distances are estimated from the largest size of every line in
+/// between, so a branch is only kept when it surely reaches. Rewriting
+/// moves code further apart, so this runs until nothing changes.
+pub fn relax_branches(func: &str, code: &str) -> String {
+    let mut lines: Vec<String> = code.lines().map(String::from).collect();
+    let mut relaxed = 0;
+    loop {
+        // the offset of each line, and of each label
+        let mut offsets = Vec::with_capacity(lines.len());
+        let mut labels = HashMap::new();
+        let mut offset = 0;
+        for line in lines.iter() {
+            offsets.push(offset);
+            if let Some(label) = line.strip_suffix(':') {
+                labels.insert(label.to_string(), offset);
+            } else if line.starts_with("  ") && !line.starts_with("  .") {
+                offset += MAX_LINE_SIZE;
+            }
+        }
+
+        let mut out = Vec::with_capacity(lines.len());
+        let mut changed = false;
+        for (line, offset) in lines.iter().zip(offsets) {
+            let far = match branch(line) {
+                Some((op, reg, target)) => match labels.get(target) {
+                    Some(to) if to.abs_diff(offset) + MAX_LINE_SIZE >= BRANCH_RANGE => Some((op, reg, target)),
+                    _ => None,
+                },
+                None => None,
+            };
+            match far {
+                Some((op, reg, target)) => {
+                    let skip = format!("{}_relax_{}", func, relaxed);
+                    relaxed += 1;
+                    let opposite = if op == "bnez" { "beqz" } else { "bnez" };
+                    out.push(format!("  {} {}, {}", opposite, reg, skip));
+                    out.push(format!("  j {}", target));
+                    out.push(format!("{}:", skip));
+                    changed = true;
+                }
+                None => out.push(line.clone()),
+            }
+        }
+        lines = out;
+        if !changed {
+            break;
+        }
+    }
+    let mut code = lines.join("\n");
+    code.push('\n');
+    code
+}
+
+/// The operator, register and target of a `bnez` or `beqz` line.
+fn branch(line: &str) -> Option<(&str, &str, &str)> {
+    let (op, operands) = line.trim_start().split_once(' ')?;
+    if op != "bnez" && op != "beqz" {
+        return None;
+    }
+    let (reg, target) = operands.split_once(", ")?;
+    Some((op, reg, target))
+}
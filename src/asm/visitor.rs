@@ -1,17 +1,34 @@
+use crate::analysis::alias::AliasAnalysis;
+use crate::asm::liveness::has_location;
+use crate::asm::reg::{self, Reg, A0, ARGS, SP, T0, ZERO};
+use crate::asm::regalloc::Allocator;
+use crate::asm::relax::relax_branches;
 use crate::asm::vm::*;
+use crate::session::Session;
 use koopa::ir::entities::FunctionData;
 use koopa::ir::layout::BasicBlockNode;
 use koopa::ir::values::*;
 use koopa::ir::{BasicBlock, Program, TypeKind, Value, ValueKind};
+use std::collections::HashMap;
 use std::io::{Result, Write};
-use std::ptr::write;
 
 /// Visitor for generating the in-memory form Koopa IR program into the riscv
 #[derive(Default)]
-pub struct Visitor;
-
+pub struct Visitor {
+    pub allocator: Allocator,
+}
 
 impl Visitor {
+    /// Creates a visitor allocating registers as the optimization level asks.
+    pub fn new(session: &Session) -> Self {
+        let allocator = if session.options.opt_level >= 2 {
+            Allocator::GraphColoring
+        } else {
+            Allocator::LinearScan
+        };
+        Visitor { allocator }
+    }
+
     pub fn visit<W: Write>(
         &mut self,
         w: &mut W,
@@ -20,9 +37,15 @@ impl Visitor {
         let mut visitor = VisitorImpl {
             w,
             program,
+            allocator: self.allocator,
             func: None,
-            vm: ValueManager::new(),
-            stack_size: 0,
+            vm: ValueManager::default(),
+            labels: HashMap::new(),
+            next_bb: None,
+            body: Vec::new(),
+            epilogues: Vec::new(),
+            frame_escapes: false,
+            edges: 0,
         };
         visitor.visit()
     }
@@ -32,61 +55,178 @@ impl Visitor {
 struct VisitorImpl<'a, W: Write> {
     w: &'a mut W,
     program: &'a Program,
+    allocator: Allocator,
     func: Option<&'a FunctionData>,
     vm: ValueManager,
-    stack_size: i32,
+    labels: HashMap<BasicBlock, String>,
+    /// the basic block laid out after the current one
+    next_bb: Option<BasicBlock>,
+    /// code of the current function, held back until the registers to
+    /// save in the prologue are known
+    body: Vec<u8>,
+    /// offsets into `body` where an epilogue goes, with the function it
+    /// jumps to for a tail call instead of returning
+    epilogues: Vec<(usize, Option<String>)>,
+    /// whether the callee may reach the frame through the address of a
+    /// local, which rules out tail calls
+    frame_escapes: bool,
+    /// number of branch edges given a label of their own
+    edges: usize,
 }
 
-impl<W: Write> VisitorImpl<'_, W> {
+impl<'a, W: Write> VisitorImpl<'a, W> {
     /// Visits the program
     fn visit(&mut self) -> Result<()> {
+        self.visit_globals()?;
         writeln!(self.w, "  .text")?;
-        writeln!(self.w, "  .global main")?;
-
         for func in self.program.func_layout().iter() {
             let func = self.program.func(*func);
-            self.func = Some(func);
-            self.visit_func(func)?;
+            // declarations are provided by the runtime
+            if func.layout().entry_bb().is_some() {
+                self.func = Some(func);
+                self.visit_func(func)?;
+            }
         }
         Ok(())
     }
 
-    /// Generates the given function
-    fn visit_func(&mut self, func: &FunctionData) -> Result<()> {
-        writeln!(self.w, "{}:", &func.name()[1..])?;
-        let mut stack_size = 0;
-        for (_i, (bb, node)) in func.layout().bbs().iter().enumerate() {
-            node.insts().iter().for_each(|(value,_)| {
-                match self.func.unwrap().dfg().value(*value).ty().kind() {
-                    TypeKind::Int32 => stack_size += 4,
-                    TypeKind::Pointer(_) => stack_size += 4,
-                    _ => {}
+    /// Generates the data section for global allocations.
+    fn visit_globals(&mut self) -> Result<()> {
+        for value in self.program.inst_layout() {
+            let data = self.program.borrow_value(*value);
+            if let ValueKind::GlobalAlloc(g) = data.kind() {
+                let name = &data.name().as_ref().unwrap()[1..];
+                writeln!(self.w, "  .data")?;
+                writeln!(self.w, "  .global {}", name)?;
+                writeln!(self.w, "{}:", name)?;
+                self.visit_init(g.init())?;
+                writeln!(self.w)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Generates the initializer of a global allocation.
+    fn visit_init(&mut self, init: Value) -> Result<()> {
+        let data = self.program.borrow_value(init);
+        match data.kind() {
+            ValueKind::Integer(i) => writeln!(self.w, "  .word {}", i.value()),
+            ValueKind::ZeroInit(_) | ValueKind::Undef(_) => {
+                writeln!(self.w, "  .zero {}", data.ty().size())
+            }
+            ValueKind::Aggregate(a) => {
+                for elem in a.elems() {
+                    self.visit_init(*elem)?;
                 }
-            });
+                Ok(())
+            }
+            _ => unreachable!(),
         }
-        stack_size = (stack_size + 15) / 16 * 16;
-        if stack_size > 2048 {
-            todo!();
+    }
+
+    /// Generates the given function
+    fn visit_func(&mut self, func: &'a FunctionData) -> Result<()> {
+        let name = &func.name()[1..];
+        self.vm = ValueManager::new(func, self.allocator.allocate(func));
+        self.labels.clear();
+        for (i, bb) in func.layout().bbs().keys().enumerate() {
+            let label = match func.dfg().bb(*bb).name() {
+                Some(bb_name) => format!("{}_{}", name, &bb_name[1..]),
+                None => format!("{}_bb{}", name, i),
+            };
+            self.labels.insert(*bb, label);
         }
-        writeln!(self.w, "  addi sp, sp, -{}", stack_size)?;
-        self.stack_size = stack_size;
+        self.body.clear();
+        self.epilogues.clear();
+        let alias = AliasAnalysis::new(func);
+        self.frame_escapes = func.dfg().values().iter().any(|(value, data)| {
+            matches!(data.kind(), ValueKind::Alloc(_)) && alias.escapes(*value)
+        });
+        self.edges = 0;
 
-        self.vm.set_max_offset(stack_size as u32);
-        for (_i, (bb, node)) in func.layout().bbs().iter().enumerate() {
-            self.visit_bb(*bb, node)?;
+        self.visit_params()?;
+        let bbs: Vec<_> = func.layout().bbs().iter().collect();
+        for (i, (bb, node)) in bbs.iter().enumerate() {
+            self.next_bb = bbs.get(i + 1).map(|(next, _)| **next);
+            self.visit_bb(**bb, node)?;
         }
+
+        writeln!(self.w, "  .global {}", name)?;
+        writeln!(self.w, "{}:", name)?;
+        let mut code = Vec::new();
+        self.write_prologue(&mut code)?;
+        let mut last = 0;
+        for (at, tail) in std::mem::take(&mut self.epilogues) {
+            code.extend_from_slice(&self.body[last..at]);
+            self.write_epilogue(&mut code, tail.as_deref())?;
+            last = at;
+        }
+        code.extend_from_slice(&self.body[last..]);
+        let code = relax_branches(name, std::str::from_utf8(&code).unwrap());
+        self.w.write_all(code.as_bytes())?;
+        writeln!(self.w)?;
         Ok(())
     }
 
+    /// Grows the frame and saves the registers the body clobbers.
+    /// `t0` is free here: arguments only occupy `a0`-`a7`.
+    fn write_prologue(&self, code: &mut Vec<u8>) -> Result<()> {
+        let size = self.vm.frame_size();
+        if size > 0 {
+            write_sp_adjust(code, -size)?;
+        }
+        for (r, offset) in self.vm.saved_regs() {
+            write_mem(code, "sw", r, SP, offset, T0)?;
+        }
+        Ok(())
+    }
+
+    /// Restores the saved registers and returns, or jumps to `tail` for a
+    /// tail call. `t0` is dead at a return, and is no argument register.
+    fn write_epilogue(&self, code: &mut Vec<u8>, tail: Option<&str>) -> Result<()> {
+        for (r, offset) in self.vm.saved_regs() {
+            write_mem(code, "lw", r, SP, offset, T0)?;
+        }
+        let size = self.vm.frame_size();
+        if size > 0 {
+            write_sp_adjust(code, size)?;
+        }
+        match tail {
+            Some(callee) => writeln!(code, "  j {}", callee),
+            None => writeln!(code, "  ret"),
+        }
+    }
+
+    /// Moves the incoming arguments to where the allocator put them.
+    fn visit_params(&mut self) -> Result<()> {
+        let func = self.func.unwrap();
+        let mut moves = Vec::new();
+        for (i, param) in func.params().iter().enumerate() {
+            if func.dfg().value(*param).used_by().is_empty() {
+                continue;
+            }
+            let src = match ARGS.get(i) {
+                Some(r) => Place::Reg(*r),
+                None => Place::Stack(self.vm.incoming_arg_offset(i)),
+            };
+            moves.push((self.vm.place(*param), src));
+        }
+        self.write_moves(&moves)
+    }
+
     /// Generates the given basic block.
     fn visit_bb(&mut self, bb: BasicBlock, node: &BasicBlockNode) -> Result<()> {
-        // calc stack size
-        let bb_data = self.func.unwrap().dfg().bb(bb);
-        if let Some(name) = bb_data.name() {
-            writeln!(self.w, "{}", &(name.clone() + ":")[1..])?;
-        }
-        for inst in node.insts().keys() {
-            self.visit_local_inst(inst)?;
+        writeln!(self.body, "{}:", self.labels[&bb])?;
+        let insts: Vec<Value> = node.insts().keys().copied().collect();
+        let mut i = 0;
+        while i < insts.len() {
+            if let Some(c) = self.tail_call(&insts[i..]) {
+                self.visit_tail_call(c)?;
+                i += 2;
+            } else {
+                self.visit_local_inst(&insts[i])?;
+                i += 1;
+            }
         }
         Ok(())
     }
@@ -95,152 +235,465 @@ impl<W: Write> VisitorImpl<'_, W> {
     fn visit_local_inst(&mut self, inst: &Value) -> Result<()> {
         let value_data = self.func.unwrap().dfg().value(*inst);
         match value_data.kind() {
-            ValueKind::Alloc(_) => {
-                self.vm.alloc(inst, 4)?;
-            }
-            ValueKind::Load(l) => {
-                self.vm.alloc(inst, 4)?;
-                // warn! must be sure the src() value is loaded from memory,
-                // or the value of l.src never use again.
-
-                // to make sure of this, we must store the value of reg to memory
-                // when the value is changed
-
-                let reg = self.vm.load_to_reg(l.src(), None, self.w)?;
-                self.vm.set_value_store(*inst, ValueStore::Reg(reg));
-                // copy value of reg to memory
-                self.vm.copy_to_mem(reg, self.w);
-            }
-            ValueKind::Store(s) => {
-                self.visit_const(s.value())?;
-                let reg = self.vm.load_to_reg(s.value(), None, self.w)?;
-                self.vm.set_value_store(s.dest(), ValueStore::Reg(reg));
-                // here we use copy to avoid load again when the value of reg is used
-                self.vm.copy_to_mem(reg, self.w);
-            }
-            ValueKind::Binary(b) => {
-                self.vm.alloc(inst, 4)?;
-                self.visit_binary(inst, b)?;
-                let reg = self.vm.load_to_reg(*inst, None, self.w)?;
-                self.vm.copy_to_mem(reg, self.w)
+            // storage is reserved in the frame, the address is rematerialized
+            ValueKind::Alloc(_) => {}
+            ValueKind::Load(l) => self.visit_load(*inst, l)?,
+            ValueKind::Store(s) => self.visit_store(s)?,
+            ValueKind::GetPtr(p) => {
+                let stride = self.pointee_size(p.src());
+                self.visit_ptr(*inst, p.src(), p.index(), stride)?;
+            }
+            ValueKind::GetElemPtr(p) => {
+                let stride = match self.value_type_kind(p.src()) {
+                    TypeKind::Pointer(base) => match base.kind() {
+                        TypeKind::Array(elem, _) => elem.size() as i32,
+                        _ => unreachable!(),
+                    },
+                    _ => unreachable!(),
+                };
+                self.visit_ptr(*inst, p.src(), p.index(), stride)?;
             }
+            ValueKind::Binary(b) => self.visit_binary(*inst, b)?,
             ValueKind::Branch(b) => self.visit_branch(b)?,
             ValueKind::Jump(j) => self.visit_jump(j)?,
+            ValueKind::Call(c) => self.visit_call(*inst, c)?,
             ValueKind::Return(v) => self.visit_return(v)?,
             _ => unimplemented!(),
         };
         Ok(())
     }
 
+    fn value_type_kind(&self, value: Value) -> TypeKind {
+        if value.is_global() {
+            self.program.borrow_value(value).ty().kind().clone()
+        } else {
+            self.func.unwrap().dfg().value(value).ty().kind().clone()
+        }
+    }
+
+    fn pointee_size(&self, ptr: Value) -> i32 {
+        match self.value_type_kind(ptr) {
+            TypeKind::Pointer(base) => base.size() as i32,
+            _ => unreachable!(),
+        }
+    }
+
+    fn global_name(&self, value: Value) -> String {
+        self.program.borrow_value(value).name().as_ref().unwrap()[1..].to_string()
+    }
+
+    /// Returns a register holding `value`, loading it into the `i`-th
+    /// scratch register when it has none of its own.
+    fn read(&mut self, value: Value, i: usize) -> Result<Reg> {
+        let func = self.func.unwrap();
+        if !value.is_global() {
+            if let ValueKind::Integer(n) = func.dfg().value(value).kind() {
+                if n.value() == 0 {
+                    return Ok(ZERO);
+                }
+            }
+            if has_location(func, value) {
+                match self.vm.place(value) {
+                    Place::Reg(r) => return Ok(r),
+                    Place::Stack(offset) => {
+                        let s = self.vm.scratch(i);
+                        write_mem(&mut self.body, "lw", s, SP, offset, s)?;
+                        return Ok(s);
+                    }
+                }
+            }
+        }
+        let s = self.vm.scratch(i);
+        self.materialize(value, s)?;
+        Ok(s)
+    }
+
+    /// Computes a value without a location (a constant, or the address of
+    /// an `alloc` or a global) into `rd`.
+    fn materialize(&mut self, value: Value, rd: Reg) -> Result<()> {
+        if value.is_global() {
+            return writeln!(self.body, "  la {}, {}", reg::name(rd), self.global_name(value));
+        }
+        match self.func.unwrap().dfg().value(value).kind() {
+            ValueKind::Integer(n) => writeln!(self.body, "  li {}, {}", reg::name(rd), n.value()),
+            ValueKind::ZeroInit(_) | ValueKind::Undef(_) => {
+                writeln!(self.body, "  li {}, 0", reg::name(rd))
+            }
+            ValueKind::Alloc(_) => {
+                let offset = self.vm.object_offset(value).unwrap();
+                write_addi(&mut self.body, rd, SP, offset)
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    /// Register to compute `value` into: its own, or the first scratch
+    /// register when it is spilled.
+    fn def_reg(&mut self, value: Value) -> Reg {
+        match self.vm.place(value) {
+            Place::Reg(r) => r,
+            Place::Stack(_) => self.vm.scratch(0),
+        }
+    }
+
+    /// Stores a result computed in `rd` back to its spill slot, if any.
+    fn write_back(&mut self, value: Value, rd: Reg) -> Result<()> {
+        if let Place::Stack(offset) = self.vm.place(value) {
+            let tmp = if IMM12.contains(&offset) { rd } else { self.vm.scratch(1) };
+            write_mem(&mut self.body, "sw", rd, SP, offset, tmp)?;
+        }
+        Ok(())
+    }
+
+    /// Returns the base register and offset addressing what `ptr` points
+    /// to, using the `i`-th scratch register if needed.
+    fn address(&mut self, ptr: Value, i: usize) -> Result<(Reg, i32)> {
+        match self.vm.object_offset(ptr) {
+            Some(offset) => Ok((SP, offset)),
+            None => Ok((self.read(ptr, i)?, 0)),
+        }
+    }
+
+    /// Emits a parallel move between places of located values.
+    fn write_moves(&mut self, moves: &[(Place, Place)]) -> Result<()> {
+        self.vm.write_parallel_move(&mut self.body, moves)
+    }
+
+    /// Assigns `values` to `dests` all at once: located values are moved
+    /// first, then constants and addresses are computed into place.
+    fn write_assign(&mut self, dests: &[Place], values: &[Value]) -> Result<()> {
+        let func = self.func.unwrap();
+        let mut moves = Vec::new();
+        let mut computed = Vec::new();
+        for (dst, value) in dests.iter().zip(values) {
+            if has_location(func, *value) {
+                moves.push((*dst, self.vm.place(*value)));
+            } else {
+                computed.push((*dst, *value));
+            }
+        }
+        if !moves.is_empty() {
+            self.write_moves(&moves)?;
+        }
+        for (dst, value) in computed {
+            match dst {
+                Place::Reg(r) => self.materialize(value, r)?,
+                Place::Stack(offset) => {
+                    let s = self.vm.scratch(0);
+                    self.materialize(value, s)?;
+                    let tmp = if IMM12.contains(&offset) { s } else { self.vm.scratch(1) };
+                    write_mem(&mut self.body, "sw", s, SP, offset, tmp)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Generates the given load.
+    fn visit_load(&mut self, value: Value, l: &Load) -> Result<()> {
+        let rd = self.def_reg(value);
+        let (base, offset) = self.address(l.src(), 1)?;
+        write_mem(&mut self.body, "lw", rd, base, offset, rd)?;
+        self.write_back(value, rd)
+    }
+
+    /// Generates the given store.
+    fn visit_store(&mut self, s: &Store) -> Result<()> {
+        let rs = self.read(s.value(), 0)?;
+        let (base, offset) = self.address(s.dest(), 1)?;
+        let tmp = if IMM12.contains(&offset) { rs } else { self.vm.scratch(1) };
+        write_mem(&mut self.body, "sw", rs, base, offset, tmp)
+    }
+
+    /// Generates `getptr`/`getelemptr`: `src + index * stride`.
+    fn visit_ptr(&mut self, value: Value, src: Value, index: Value, stride: i32) -> Result<()> {
+        let func = self.func.unwrap();
+        let rd = self.def_reg(value);
+        let constant = match func.dfg().value(index).kind() {
+            ValueKind::Integer(n) => Some(n.value()),
+            ValueKind::ZeroInit(_) | ValueKind::Undef(_) => Some(0),
+            _ => None,
+        };
+        match constant {
+            Some(n) => {
+                let (base, offset) = self.address(src, 0)?;
+                let offset = offset.wrapping_add(n.wrapping_mul(stride));
+                if offset == 0 {
+                    if rd != base {
+                        writeln!(self.body, "  mv {}, {}", reg::name(rd), reg::name(base))?;
+                    }
+                } else if IMM12.contains(&offset) {
+                    write_addi(&mut self.body, rd, base, offset)?;
+                } else {
+                    let s = self.vm.scratch(1);
+                    writeln!(self.body, "  li {}, {}", reg::name(s), offset)?;
+                    writeln!(self.body, "  add {}, {}, {}", reg::name(rd), reg::name(base), reg::name(s))?;
+                }
+            }
+            None => {
+                // the scaled index goes to the second scratch register
+                let idx = self.read(index, 1)?;
+                let s = self.vm.scratch(1);
+                if stride.count_ones() == 1 {
+                    let shift = stride.trailing_zeros();
+                    writeln!(self.body, "  slli {}, {}, {}", reg::name(s), reg::name(idx), shift)?;
+                } else {
+                    let k = if idx == s { self.vm.scratch(0) } else { s };
+                    writeln!(self.body, "  li {}, {}", reg::name(k), stride)?;
+                    writeln!(self.body, "  mul {}, {}, {}", reg::name(s), reg::name(idx), reg::name(k))?;
+                }
+                let (base, offset) = self.address(src, 0)?;
+                writeln!(self.body, "  add {}, {}, {}", reg::name(rd), reg::name(base), reg::name(s))?;
+                if IMM12.contains(&offset) {
+                    if offset != 0 {
+                        writeln!(self.body, "  addi {}, {}, {}", reg::name(rd), reg::name(rd), offset)?;
+                    }
+                } else {
+                    writeln!(self.body, "  li {}, {}", reg::name(s), offset)?;
+                    writeln!(self.body, "  add {}, {}, {}", reg::name(rd), reg::name(rd), reg::name(s))?;
+                }
+            }
+        }
+        self.write_back(value, rd)
+    }
+
     /// Generates the given branch.
     fn visit_branch(&mut self, b: &Branch) -> Result<()> {
-        self.vm.store_all_regs(self.w);
-        self.visit_const(b.cond())?;
-        let cond_reg = self.vm.load_to_reg(b.cond(), None, self.w)?;
-        let reg_name = self.vm.get_reg_name(cond_reg);
-        let true_name = self.func.unwrap().dfg().bb(b.true_bb()).name().clone();
-        let false_name = self.func.unwrap().dfg().bb(b.false_bb()).name().clone();
-        writeln!(self.w, "  bnez {}, {}", reg_name, &true_name.unwrap()[1..])?;
-        writeln!(self.w, "  j {}", &false_name.unwrap()[1..])?;
+        let cond = reg::name(self.read(b.cond(), 0)?);
+        let true_label = self.labels[&b.true_bb()].clone();
+        let false_label = self.labels[&b.false_bb()].clone();
+        match (b.true_args().is_empty(), b.false_args().is_empty()) {
+            (true, true) => {
+                if self.next_bb == Some(b.true_bb()) {
+                    writeln!(self.body, "  beqz {}, {}", cond, false_label)?;
+                } else {
+                    writeln!(self.body, "  bnez {}, {}", cond, true_label)?;
+                    self.write_jump(b.false_bb())?;
+                }
+            }
+            (true, false) => {
+                writeln!(self.body, "  bnez {}, {}", cond, true_label)?;
+                self.write_edge(b.false_bb(), b.false_args())?;
+            }
+            (false, true) => {
+                writeln!(self.body, "  beqz {}, {}", cond, false_label)?;
+                self.write_edge(b.true_bb(), b.true_args())?;
+            }
+            (false, false) => {
+                let edge = format!("{}_edge_{}", &self.func.unwrap().name()[1..], self.edges);
+                self.edges += 1;
+                writeln!(self.body, "  bnez {}, {}", cond, edge)?;
+                // the edge block follows, so the false edge cannot fall
+                // through to the next block
+                self.write_args(b.false_bb(), b.false_args())?;
+                writeln!(self.body, "  j {}", false_label)?;
+                writeln!(self.body, "{}:", edge)?;
+                self.write_edge(b.true_bb(), b.true_args())?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Passes `args` to the parameters of `target` and jumps there.
+    fn write_edge(&mut self, target: BasicBlock, args: &[Value]) -> Result<()> {
+        self.write_args(target, args)?;
+        self.write_jump(target)
+    }
+
+    /// Passes `args` to the parameters of `target`.
+    fn write_args(&mut self, target: BasicBlock, args: &[Value]) -> Result<()> {
+        let params = self.func.unwrap().dfg().bb(target).params();
+        let dests: Vec<Place> = params.iter().map(|p| self.vm.place(*p)).collect();
+        self.write_assign(&dests, args)
+    }
+
+    /// Jumps to `target`, unless it is laid out right after the current block.
+    fn write_jump(&mut self, target: BasicBlock) -> Result<()> {
+        if self.next_bb != Some(target) {
+            writeln!(self.body, "  j {}", self.labels[&target])?;
+        }
         Ok(())
     }
 
     /// Generates the given jump.
     fn visit_jump(&mut self, j: &Jump) -> Result<()> {
-        self.vm.store_all_regs(self.w);
-        let target_name = self.func.unwrap().dfg().bb(j.target()).name().clone();
-        writeln!(self.w, "  j {}", &target_name.unwrap()[1..])?;
+        self.write_edge(j.target(), j.args())
+    }
+
+    /// Generates the given call.
+    fn visit_call(&mut self, value: Value, c: &Call) -> Result<()> {
+        // arguments beyond the eighth go to the bottom of the frame
+        for (i, arg) in c.args().iter().enumerate().skip(ARGS.len()) {
+            let rs = self.read(*arg, 0)?;
+            let offset = 4 * (i - ARGS.len()) as i32;
+            let tmp = if IMM12.contains(&offset) { rs } else { self.vm.scratch(1) };
+            write_mem(&mut self.body, "sw", rs, SP, offset, tmp)?;
+        }
+        let n = c.args().len().min(ARGS.len());
+        let dests: Vec<Place> = ARGS[..n].iter().map(|r| Place::Reg(*r)).collect();
+        self.write_assign(&dests, &c.args()[..n])?;
+        writeln!(self.body, "  call {}", &self.program.func(c.callee()).name()[1..])?;
+        if has_location(self.func.unwrap(), value) {
+            self.write_moves(&[(self.vm.place(value), Place::Reg(A0))])?;
+        }
+        Ok(())
+    }
+
+    /// The call starting `insts` if the return after it returns what the
+    /// call does, with every argument in a register and no local of the
+    /// frame reachable from the callee, so that the frame can go first.
+    fn tail_call(&self, insts: &[Value]) -> Option<&'a Call> {
+        let func = self.func.unwrap();
+        let (call, ret) = match insts {
+            [call, ret, ..] => (*call, *ret),
+            _ => return None,
+        };
+        let c = match func.dfg().value(call).kind() {
+            ValueKind::Call(c) if c.args().len() <= ARGS.len() && !self.frame_escapes => c,
+            _ => return None,
+        };
+        match func.dfg().value(ret).kind() {
+            ValueKind::Return(r) if r.value().is_none() || r.value() == Some(call) => Some(c),
+            _ => None,
+        }
+    }
+
+    /// Generates a call followed by a return of its result as a jump to
+    /// the callee once the frame is torn down, so that the callee returns
+    /// straight to the caller.
+    fn visit_tail_call(&mut self, c: &Call) -> Result<()> {
+        let n = c.args().len();
+        let dests: Vec<Place> = ARGS[..n].iter().map(|r| Place::Reg(*r)).collect();
+        self.write_assign(&dests, c.args())?;
+        let callee = self.program.func(c.callee()).name()[1..].to_string();
+        self.epilogues.push((self.body.len(), Some(callee)));
         Ok(())
     }
 
     /// Generates function return.
     fn visit_return(&mut self, ret: &Return) -> Result<()> {
-        self.vm.store_all_regs(self.w);
         if let Some(val) = ret.value() {
-            self.visit_const(val)?;
-            self.vm.load_to_reg(val, Some(A0), self.w)?;
+            self.write_assign(&[Place::Reg(A0)], &[val])?;
         }
-        writeln!(self.w, "  add sp, sp, {}", self.stack_size)?;
-        writeln!(self.w, "  ret")?;
+        self.epilogues.push((self.body.len(), None));
         Ok(())
     }
 
-    /// Generates the given binary operation._
-    fn visit_binary(&mut self, value: &Value, b: &Binary) -> Result<()> {
-        self.visit_const(b.lhs())?;
-        self.visit_const(b.rhs())?;
+    /// Generates the given binary operation.
+    fn visit_binary(&mut self, value: Value, b: &Binary) -> Result<()> {
+        let func = self.func.unwrap();
+        let (mut lhs, mut rhs) = (b.lhs(), b.rhs());
+        let commutative = matches!(
+            b.op(),
+            BinaryOp::Add | BinaryOp::Mul | BinaryOp::And | BinaryOp::Or | BinaryOp::Xor | BinaryOp::Eq | BinaryOp::NotEq
+        );
+        if commutative && integer(func, lhs).is_some() && integer(func, rhs).is_none() {
+            std::mem::swap(&mut lhs, &mut rhs);
+        }
 
-        // deal reg, for now load all const to reg
-        let rd = self.vm.alloc_reg(None, self.w);
-        self.vm.set_value_store(*value, ValueStore::Reg(rd));
-        self.vm.lock_reg(rd);
-        let rd_name = self.vm.get_reg_name(rd);
+        let rd = self.def_reg(value);
+        let l = reg::name(self.read(lhs, 0)?);
+        let d = reg::name(rd);
+        if let Some(imm) = integer(func, rhs).filter(|i| fits_imm(b.op(), *i)) {
+            match b.op() {
+                BinaryOp::Add => writeln!(self.body, "  addi {}, {}, {}", d, l, imm)?,
+                BinaryOp::Sub => writeln!(self.body, "  addi {}, {}, {}", d, l, -imm)?,
+                BinaryOp::And => writeln!(self.body, "  andi {}, {}, {}", d, l, imm)?,
+                BinaryOp::Or => writeln!(self.body, "  ori {}, {}, {}", d, l, imm)?,
+                BinaryOp::Xor => writeln!(self.body, "  xori {}, {}, {}", d, l, imm)?,
+                BinaryOp::Shl => writeln!(self.body, "  slli {}, {}, {}", d, l, imm)?,
+                BinaryOp::Shr => writeln!(self.body, "  srli {}, {}, {}", d, l, imm)?,
+                BinaryOp::Sar => writeln!(self.body, "  srai {}, {}, {}", d, l, imm)?,
+                BinaryOp::Lt => writeln!(self.body, "  slti {}, {}, {}", d, l, imm)?,
+                BinaryOp::Ge => {
+                    writeln!(self.body, "  slti {}, {}, {}", d, l, imm)?;
+                    writeln!(self.body, "  xori {}, {}, 1", d, d)?;
+                }
+                BinaryOp::Eq | BinaryOp::NotEq => {
+                    let set = if b.op() == BinaryOp::Eq { "seqz" } else { "snez" };
+                    if imm == 0 {
+                        writeln!(self.body, "  {} {}, {}", set, d, l)?;
+                    } else {
+                        writeln!(self.body, "  xori {}, {}, {}", d, l, imm)?;
+                        writeln!(self.body, "  {} {}, {}", set, d, d)?;
+                    }
+                }
+                _ => unreachable!(),
+            }
+            return self.write_back(value, rd);
+        }
 
-        let lvs = self.vm.load_to_reg(b.lhs(), None, self.w)?;
-        self.vm.lock_reg(lvs);
-        let rvs = self.vm.load_to_reg(b.rhs(), None, self.w)?;
-        self.vm.unlock_reg(lvs);
-        self.vm.unlock_reg(rd);
-        let (lvs, rvs) = (self.vm.get_reg_name(lvs), self.vm.get_reg_name(rvs));
+        let r = reg::name(self.read(rhs, 1)?);
         match b.op() {
             BinaryOp::Eq => {
-                writeln!(self.w, "  sub {}, {}, {}", rd_name, lvs, rvs)?;
-                writeln!(self.w, "  seqz {}, {}", rd_name, rd_name)?;
+                writeln!(self.body, "  xor {}, {}, {}", d, l, r)?;
+                writeln!(self.body, "  seqz {}, {}", d, d)?;
             }
             BinaryOp::NotEq => {
-                writeln!(self.w, "  sub {}, {}, {}", rd_name, lvs, rvs)?;
-                writeln!(self.w, "  snez {}, {}", rd_name, rd_name)?;
-            }
-            BinaryOp::Lt => {
-                writeln!(self.w, "  slt {}, {}, {}", rd_name, lvs, rvs)?;
-            }
-            BinaryOp::Gt => {
-                writeln!(self.w, "  slt {}, {}, {}", rd_name, rvs, lvs)?;
+                writeln!(self.body, "  xor {}, {}, {}", d, l, r)?;
+                writeln!(self.body, "  snez {}, {}", d, d)?;
             }
+            BinaryOp::Lt => writeln!(self.body, "  slt {}, {}, {}", d, l, r)?,
+            BinaryOp::Gt => writeln!(self.body, "  slt {}, {}, {}", d, r, l)?,
             BinaryOp::Le => {
-                writeln!(self.w, "  slt {}, {}, {}", rd_name, rvs, lvs)?;
-                writeln!(self.w, "  seqz {}, {}", rd_name, rd_name)?;
+                writeln!(self.body, "  slt {}, {}, {}", d, r, l)?;
+                writeln!(self.body, "  xori {}, {}, 1", d, d)?;
             }
             BinaryOp::Ge => {
-                writeln!(self.w, "  slt {}, {}, {}", rd_name, lvs, rvs)?;
-                writeln!(self.w, "  seqz {}, {}", rd_name, rd_name)?;
-            }
-            BinaryOp::And => {
-                writeln!(self.w, "  and {}, {}, {}", rd_name, lvs, rvs)?;
+                writeln!(self.body, "  slt {}, {}, {}", d, l, r)?;
+                writeln!(self.body, "  xori {}, {}, 1", d, d)?;
             }
-            BinaryOp::Or => {
-                writeln!(self.w, "  or {}, {}, {}", rd_name, lvs, rvs)?;
-            }
-            BinaryOp::Add => {
-                writeln!(self.w, "  add {}, {}, {}", rd_name, lvs, rvs)?;
-            }
-            BinaryOp::Sub => {
-                writeln!(self.w, "  sub {}, {}, {}", rd_name, lvs, rvs)?;
-            }
-            BinaryOp::Mul => {
-                writeln!(self.w, "  mul {}, {}, {}", rd_name, lvs, rvs)?;
-            }
-            BinaryOp::Div => {
-                writeln!(self.w, "  div {}, {}, {}", rd_name, lvs, rvs)?;
-            }
-            BinaryOp::Mod => {
-                writeln!(self.w, "  rem {}, {}, {}", rd_name, lvs, rvs)?;
-            }
-            _ => unimplemented!("not implemented"),
+            BinaryOp::And => writeln!(self.body, "  and {}, {}, {}", d, l, r)?,
+            BinaryOp::Or => writeln!(self.body, "  or {}, {}, {}", d, l, r)?,
+            BinaryOp::Xor => writeln!(self.body, "  xor {}, {}, {}", d, l, r)?,
+            BinaryOp::Shl => writeln!(self.body, "  sll {}, {}, {}", d, l, r)?,
+            BinaryOp::Shr => writeln!(self.body, "  srl {}, {}, {}", d, l, r)?,
+            BinaryOp::Sar => writeln!(self.body, "  sra {}, {}, {}", d, l, r)?,
+            BinaryOp::Add => writeln!(self.body, "  add {}, {}, {}", d, l, r)?,
+            BinaryOp::Sub => writeln!(self.body, "  sub {}, {}, {}", d, l, r)?,
+            BinaryOp::Mul => writeln!(self.body, "  mul {}, {}, {}", d, l, r)?,
+            BinaryOp::Div => writeln!(self.body, "  div {}, {}, {}", d, l, r)?,
+            BinaryOp::Mod => writeln!(self.body, "  rem {}, {}, {}", d, l, r)?,
         }
+        self.write_back(value, rd)
+    }
+}
 
-        Ok(())
+/// Moves `sp` by `offset`, materializing it in a register when it does not
+/// fit in the 12-bit immediate of `addi`.
+fn write_sp_adjust(code: &mut Vec<u8>, offset: i32) -> Result<()> {
+    if IMM12.contains(&offset) {
+        writeln!(code, "  addi sp, sp, {}", offset)
+    } else {
+        writeln!(code, "  li t0, {}", offset)?;
+        writeln!(code, "  add sp, sp, t0")
     }
+}
 
-    /// check if const, add it to vm
-    fn visit_const(&mut self, v: Value) -> Result<()> {
-        let data = self.func.unwrap().dfg().value(v);
-        match data.kind() {
-            ValueKind::Integer(i) => {
-                self.vm.set_value_store(v, ValueStore::Mem(Mem::Const(i.value())));
-            }
-            _ => {}
-        }
-        Ok(())
+/// The value of an integer constant operand.
+fn integer(func: &FunctionData, value: Value) -> Option<i32> {
+    if value.is_global() {
+        return None;
+    }
+    match func.dfg().value(value).kind() {
+        ValueKind::Integer(n) => Some(n.value()),
+        _ => None,
+    }
+}
+
+/// Whether `op` has an immediate form taking `imm` as its right operand.
+fn fits_imm(op: BinaryOp, imm: i32) -> bool {
+    match op {
+        BinaryOp::Add
+        | BinaryOp::And
+        | BinaryOp::Or
+        | BinaryOp::Xor
+        | BinaryOp::Lt
+        | BinaryOp::Ge
+        | BinaryOp::Eq
+        | BinaryOp::NotEq => IMM12.contains(&imm),
+        BinaryOp::Sub => imm != i32::MIN && IMM12.contains(&-imm),
+        BinaryOp::Shl | BinaryOp::Shr | BinaryOp::Sar => (0..32).contains(&imm),
+        _ => false,
     }
 }
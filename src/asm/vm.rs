@@ -1,247 +1,220 @@
-use koopa::ir::Value;
-use std::collections::{HashMap, HashSet};
+use crate::asm::reg::{self, Reg, RA, S0, SCRATCH, SP};
+use crate::asm::regalloc::{Allocation, Loc};
+use koopa::ir::{FunctionData, TypeKind, Value, ValueKind};
+use std::collections::HashMap;
 use std::io::{Result, Write};
-use rand::prelude::IteratorRandom;
 
-#[derive(Copy, Clone, Debug)]
-pub enum Mem {
-    Stack(u32),
-    Const(i32)
-}
+/// the range of a 12-bit signed immediate
+pub const IMM12: std::ops::RangeInclusive<i32> = -2048..=2047;
 
-#[derive(Copy, Clone, Debug)]
-pub enum ValueStore {
+/// A register or a word of the stack frame, as the end of a move.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Place {
     Reg(Reg),
-    Mem(Mem),
-}
-// todo last store
-pub type Reg = u8;
-pub const A0: Reg = 15;
-
-#[derive(Copy, Clone, Debug)]
-pub struct RegNode {
-    pub value: Option<Value>,
-    pub name: &'static str,
+    /// offset from `sp`
+    Stack(i32),
 }
 
+/// Where every value of a function lives: the register allocation and
+/// the stack frame around it.
+///
+/// The frame is laid out from `sp` upwards as the outgoing arguments
+/// beyond the eighth, the saved registers, the spill slots and finally
+/// the storage of every `alloc`.
+#[derive(Default)]
 pub struct ValueManager {
-    cur_offset: u32,
-    max_offset: u32,
-    regs: HashMap<Reg, RegNode>,
-    avail_regs: HashSet<Reg>,
-    value_reg: HashMap<Value, Reg>,
-    value_mem: HashMap<Value, Mem>,
+    alloc: Allocation,
+    objects: HashMap<Value, i32>,
+    saved_base: i32,
+    spill_base: i32,
+    frame_size: i32,
+    has_call: bool,
+    used_scratch: Vec<Reg>,
 }
 
 impl ValueManager {
-    pub fn new() -> Self {
-        let mut regs = HashMap::new();
-        let mut avail_regs = HashSet::new();
-        regs.insert(
-            0,
-            RegNode {
-                value: None,
-                name: "x0",
-            },
-        );
-
-        let temp_regs = [
-            ("t0", 1),
-            ("t1", 2),
-            // ("t2", 3),
-            // ("t3", 4),
-            // ("t4", 5),
-            // ("t5", 6),
-            // ("t6", 7),
-        ];
-
-        for &(name, num) in &temp_regs {
-            regs.insert(num, RegNode { value: None, name });
-            avail_regs.insert(num);
+    pub fn new(func: &FunctionData, alloc: Allocation) -> Self {
+        let mut out_args = 0;
+        let mut has_call = false;
+        let mut allocs = Vec::new();
+        for (_bb, node) in func.layout().bbs() {
+            for inst in node.insts().keys() {
+                let data = func.dfg().value(*inst);
+                match data.kind() {
+                    ValueKind::Call(c) => {
+                        has_call = true;
+                        out_args = out_args.max(c.args().len().saturating_sub(8) as i32 * 4);
+                    }
+                    ValueKind::Alloc(_) => {
+                        let size = match data.ty().kind() {
+                            TypeKind::Pointer(base) => base.size() as i32,
+                            _ => unreachable!(),
+                        };
+                        allocs.push((*inst, (size + 3) / 4 * 4));
+                    }
+                    _ => {}
+                }
+            }
         }
 
-        let arg_regs = [
-            // ("a7", 8),
-            // ("a1", 9),
-            // ("a2", 10),
-            // ("a3", 11),
-            // ("a4", 12),
-            // ("a5", 13),
-            // ("a6", 14),
-            ("a0", 15), // avoid use first
-        ];
-
-        for &(name, num) in &arg_regs {
-            regs.insert(num, RegNode { value: None, name });
-            avail_regs.insert(num);
+        let saved_base = out_args;
+        // ra and s0 are always given a slot, whether or not they end up saved
+        let spill_base = saved_base + 4 * (SCRATCH.len() + alloc.callee_saved.len()) as i32;
+        let mut offset = spill_base + 4 * alloc.spill_slots as i32;
+        let mut objects = HashMap::new();
+        for (value, size) in allocs {
+            objects.insert(value, offset);
+            offset += size;
         }
-
         ValueManager {
-            cur_offset: 0,
-            max_offset: 0,
-            regs,
-            // avail_regs: vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15], // except x0
-            avail_regs, // except x0
-            value_reg: HashMap::new(),
-            value_mem: HashMap::new(),
+            alloc,
+            objects,
+            saved_base,
+            spill_base,
+            frame_size: (offset + 15) / 16 * 16,
+            has_call,
+            used_scratch: Vec::new(),
         }
     }
 
-    pub fn set_max_offset(&mut self, max_offset: u32) {
-        self.max_offset = max_offset;
+    pub fn frame_size(&self) -> i32 {
+        self.frame_size
     }
 
-    pub fn get_reg(&self, reg: Reg) -> Option<&RegNode> {
-        self.regs.get(&reg)
+    pub fn loc(&self, value: Value) -> Loc {
+        self.alloc.locs[&value]
     }
 
-    pub fn get_reg_name(&self, reg: Reg) -> &'static str {
-        self.regs.get(&reg).unwrap().name
-    }
-    pub fn get_reg_mut(&mut self, reg: Reg) -> Option<&mut RegNode> {
-        self.regs.get_mut(&reg)
+    pub fn place(&self, value: Value) -> Place {
+        match self.loc(value) {
+            Loc::Reg(r) => Place::Reg(r),
+            Loc::Spill(slot) => Place::Stack(self.spill_base + 4 * slot as i32),
+        }
     }
 
-    pub fn lock_reg(&mut self, reg: Reg) {
-        self.avail_regs.remove(&reg);
+    /// Offset from `sp` of the storage of an `alloc`.
+    pub fn object_offset(&self, value: Value) -> Option<i32> {
+        self.objects.get(&value).copied()
     }
 
-    pub fn unlock_reg(&mut self, reg: Reg) {
-        self.avail_regs.insert(reg);
+    /// Offset from `sp` of the `index`-th incoming argument passed on the stack.
+    pub fn incoming_arg_offset(&self, index: usize) -> i32 {
+        self.frame_size + 4 * (index as i32 - 8)
     }
 
-    pub fn alloc_reg<W: Write>(&mut self, specific: Option<Reg>, w: &mut W) -> Reg {
-        let reg = specific.unwrap_or({
-            let mut rng = rand::rng();
-            let mut r = *self.avail_regs.iter().choose(&mut rng).unwrap();
-            for reg in self.avail_regs.iter() {
-                let reg_node = self.regs.get(reg).unwrap();
-                if reg_node.value.is_none() {
-                   r = *reg
-                }
-            }
-            // random choose one
-           r
-        });
-
-        if self.regs.get(&reg).unwrap().value.is_some() {
-            self.store_to_mem(reg, w);
+    /// Hands out the `i`-th scratch register and remembers to save it.
+    pub fn scratch(&mut self, i: usize) -> Reg {
+        if !self.used_scratch.contains(&SCRATCH[i]) {
+            self.used_scratch.push(SCRATCH[i]);
         }
-        reg
+        SCRATCH[i]
     }
 
-    pub fn get_value_mem(&self, value: &Value) -> Option<&Mem> {
-        self.value_mem.get(value)
-    }
-
-    pub fn get_value_store(&self, value: &Value) -> Option<ValueStore> {
-        if let Some(reg) = self.value_reg.get(value) {
-            return Some(ValueStore::Reg(*reg));
-        } else if let Some(mem) = self.value_mem.get(value) {
-            return Some(ValueStore::Mem(*mem));
+    /// Registers the prologue saves and the epilogue restores, with
+    /// their offsets from `sp`.
+    pub fn saved_regs(&self) -> Vec<(Reg, i32)> {
+        let mut regs = Vec::new();
+        if self.has_call || self.used_scratch.contains(&RA) {
+            regs.push(RA);
         }
-        None
-    }
-
-    /// bind value and store reg or set mem
-    pub fn set_value_store(&mut self, value: Value, store: ValueStore) {
-        match store {
-            ValueStore::Reg(r) => {
-                let node = self.regs.get_mut(&r).unwrap();
-                // clear old value
-                if let Some(v) = node.value {
-                    self.value_reg.remove(&v);
-                }
-                self.value_reg.insert(value, r);
-                node.value = Some(value);
+        if self.used_scratch.contains(&S0) {
+            regs.push(S0);
+        }
+        regs.extend(self.alloc.callee_saved.iter().copied());
+        // slots are fixed per register so they do not shift with usage
+        regs.into_iter()
+            .map(|r| {
+                let slot = match r {
+                    RA => 0,
+                    S0 => 1,
+                    _ => 2 + self.alloc.callee_saved.iter().position(|c| *c == r).unwrap(),
+                };
+                (r, self.saved_base + 4 * slot as i32)
+            })
+            .collect()
+    }
+
+    /// Emits a single move between two places, taking the second scratch
+    /// register for memory to memory moves and far stores.
+    fn write_move<W: Write>(&mut self, w: &mut W, dst: Place, src: Place) -> Result<()> {
+        match (dst, src) {
+            (Place::Reg(d), Place::Reg(s)) => writeln!(w, "  mv {}, {}", reg::name(d), reg::name(s)),
+            (Place::Reg(d), Place::Stack(s)) => write_mem(w, "lw", d, SP, s, d),
+            (Place::Stack(d), Place::Reg(s)) => {
+                let tmp = if IMM12.contains(&d) { s } else { self.scratch(1) };
+                write_mem(w, "sw", s, SP, d, tmp)
             }
-            ValueStore::Mem(m) => {
-                self.value_mem.insert(value, m);
+            (Place::Stack(d), Place::Stack(s)) => {
+                let tmp = self.scratch(1);
+                write_mem(w, "lw", tmp, SP, s, tmp)?;
+                write_mem(w, "sw", tmp, SP, d, tmp)
             }
         }
     }
 
-    pub fn load_to_reg<W: Write>(&mut self, value: Value, specific: Option<Reg>, w: &mut W) -> Result<Reg> {
-        let store = self.get_value_store(&value).unwrap();
-        match store {
-            ValueStore::Mem(m) => {
-                let reg = self.alloc_reg(specific, w);
-                self.set_value_store(value, ValueStore::Reg(reg));
-                let name =  self.regs.get(&reg).unwrap().name;
-                match m {
-                    Mem::Const(i) => {
-                        writeln!(w, "  li {}, {}", name, i)?;
-                    }
-                    Mem::Stack(s) => {
-                        writeln!(w, "  lw {}, {}(sp)", name, s)?;
-                    }
+    /// Emits the moves as if they all happened at once: every source is
+    /// read before any destination is written. Cycles are broken through
+    /// the first scratch register.
+    pub fn write_parallel_move<W: Write>(&mut self, w: &mut W, moves: &[(Place, Place)]) -> Result<()> {
+        let mut pending: Vec<(Place, Place)> = moves.iter().copied().filter(|(d, s)| d != s).collect();
+        while !pending.is_empty() {
+            // a move is safe once no other pending move still reads its destination
+            let ready = (0..pending.len()).find(|i| !pending.iter().any(|(_, s)| *s == pending[*i].0));
+            match ready {
+                Some(i) => {
+                    let (dst, src) = pending.remove(i);
+                    self.write_move(w, dst, src)?;
                 }
-                Ok(reg)
-            }
-            ValueStore::Reg(r) => {
-                match specific {
-                    Some(_) => {
-                        let reg = self.alloc_reg(specific, w);
-                        self.set_value_store(value, ValueStore::Reg(reg));
-                        let name =  self.regs.get(&reg).unwrap().name;
-                        if r != reg {
-                            writeln!(w, "  mv {}, {}", name, self.get_reg_name(r))?;
+                None => {
+                    // every destination is still read, so they form cycles
+                    let (dst, _) = pending[0];
+                    let tmp = Place::Reg(self.scratch(0));
+                    self.write_move(w, tmp, dst)?;
+                    for (_, s) in pending.iter_mut() {
+                        if *s == dst {
+                            *s = tmp;
                         }
-                        Ok(reg)
-                    }
-                    None => {
-                        Ok(r)
                     }
                 }
-            },
+            }
         }
-    }
-
-    // alloc on stack
-    pub fn alloc(&mut self, value: &Value, size: u32) -> Result<()> {
-        self.set_value_store(*value, ValueStore::Mem(Mem::Stack(self.cur_offset)));
-        self.cur_offset += size;
         Ok(())
     }
+}
 
-    /// store value of reg to mem, and change reg
-    fn store_to_mem<W: Write>(&mut self, reg: Reg, w: &mut W) {
-        let node = self.regs.get_mut(&reg).unwrap();
-        if let Some(value) = node.value.take() {
-            self.value_reg.remove(&value);
-            let mem = self.value_mem.get(&value).unwrap();
-            match mem {
-                Mem::Const(_) => {} // do nothing
-                Mem::Stack(s) => {
-                    writeln!(w, "  sw {}, {}(sp)", node.name, s).unwrap();
-                }
-            }
+/// Emits `op reg, offset(base)`, computing the address in `tmp` when the
+/// offset does not fit in the immediate. `tmp` may be `reg` for loads; for
+/// a store with no register to spare, `sp` is walked to the slot and back.
+pub fn write_mem<W: Write>(w: &mut W, op: &str, reg: Reg, base: Reg, offset: i32, tmp: Reg) -> Result<()> {
+    if IMM12.contains(&offset) {
+        writeln!(w, "  {} {}, {}({})", op, reg::name(reg), offset, reg::name(base))
+    } else if tmp != reg || op.starts_with('l') {
+        writeln!(w, "  li {}, {}", reg::name(tmp), offset)?;
+        writeln!(w, "  add {}, {}, {}", reg::name(tmp), reg::name(base), reg::name(tmp))?;
+        writeln!(w, "  {} {}, 0({})", op, reg::name(reg), reg::name(tmp))
+    } else {
+        assert_eq!(base, SP);
+        // keep sp 16-byte aligned while it is moved
+        const STEP: i32 = 2032;
+        let steps = offset / STEP;
+        for _ in 0..steps {
+            writeln!(w, "  addi sp, sp, {}", STEP)?;
         }
-    }
-
-    pub fn copy_to_mem<W: Write>(&mut self, reg: Reg, w: &mut W) {
-        let node = self.regs.get(&reg).unwrap();
-        if let Some(value) = node.value {
-            let mem = self.value_mem.get(&value).unwrap();
-            match mem {
-                Mem::Const(_) => {} // do nothing
-                Mem::Stack(s) => {
-                    writeln!(w, "  sw {}, {}(sp)", self.get_reg_name(reg), s).unwrap();
-                }
-            }
+        writeln!(w, "  {} {}, {}(sp)", op, reg::name(reg), offset - steps * STEP)?;
+        for _ in 0..steps {
+            writeln!(w, "  addi sp, sp, -{}", STEP)?;
         }
+        Ok(())
     }
-    
-    /// store all regs to mem, for jump, return, call
-    pub fn store_all_regs<W: Write>(&mut self, w: &mut W) {
-        let mut regs = Vec::new();
-        for (reg, node) in self.regs.iter() {
-            if let Some(value) = node.value {
-                regs.push(*reg);
-            }
-        }
-        for reg in regs {
-            self.store_to_mem(reg, w);
-        }
+}
+
+/// Emits `rd = rs + imm`, going through `li` when `imm` is out of range.
+/// `rd` must differ from `rs` in that case.
+pub fn write_addi<W: Write>(w: &mut W, rd: Reg, rs: Reg, imm: i32) -> Result<()> {
+    if IMM12.contains(&imm) {
+        writeln!(w, "  addi {}, {}, {}", reg::name(rd), reg::name(rs), imm)
+    } else {
+        writeln!(w, "  li {}, {}", reg::name(rd), imm)?;
+        writeln!(w, "  add {}, {}, {}", reg::name(rd), reg::name(rs), reg::name(rd))
     }
 }
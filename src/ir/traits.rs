@@ -1,15 +1,13 @@
 use crate::ir::structs::*;
 use koopa::ir::{builder_traits::*, *};
 use core::panic;
-use std::sync::atomic::{AtomicUsize, Ordering};
 use crate::ir::vm::{self, ValueManager};
-static CNT: AtomicUsize = AtomicUsize::new(0);
+use crate::session::Session;
 
 macro_rules! next_bb_id {
-    ($prefix:expr) => {{
-        let cnt = CNT.fetch_add(1, Ordering::SeqCst);
-        Some(format!("{}_{}", $prefix, cnt))
-    }};
+    ($params:expr, $prefix:expr) => {
+        Some($params.session.next_bb_name($params.func, $prefix))
+    };
 }
 
 macro_rules! insert_op {
@@ -49,9 +47,9 @@ macro_rules! if_else {
         $exp.build($program, $params);
         let exp_v = $params.v.take().unwrap();
         let func_data = $program.func_mut($params.func);
-        let true_bb = func_data.dfg_mut().new_bb().basic_block(next_bb_id!("%if"));
-        let false_bb = func_data.dfg_mut().new_bb().basic_block(next_bb_id!("%else"));
-        let end_bb = func_data.dfg_mut().new_bb().basic_block(next_bb_id!("%if_end"));
+        let true_bb = func_data.dfg_mut().new_bb().basic_block(next_bb_id!($params, "%if"));
+        let false_bb = func_data.dfg_mut().new_bb().basic_block(next_bb_id!($params, "%else"));
+        let end_bb = func_data.dfg_mut().new_bb().basic_block(next_bb_id!($params, "%if_end"));
         func_data.layout_mut().bbs_mut().extend([true_bb, false_bb, end_bb]);
 
         let branch = func_data.dfg_mut().new_value().branch(exp_v, true_bb, false_bb);
@@ -78,6 +76,13 @@ macro_rules! if_else {
 }
 impl Into<Program> for CompUnit {
     fn into(self) -> Program {
+        self.build(&mut Session::default())
+    }
+}
+
+impl CompUnit {
+    /// Builds the Koopa IR program, numbering basic blocks through `session`.
+    pub fn build(self, session: &mut Session) -> Program {
         let mut program = Program::new();
 
         // create func
@@ -89,7 +94,7 @@ impl Into<Program> for CompUnit {
 
         // fill func
         let main_data = program.func_mut(main);
-        let bb = main_data.dfg_mut().new_bb().basic_block(next_bb_id!("%main"));
+        let bb = main_data.dfg_mut().new_bb().basic_block(Some(session.next_bb_name(main, "%main")));
         main_data.layout_mut().bbs_mut().push_key_back(bb).unwrap();
 
         let mut params = BuildParams {
@@ -97,15 +102,38 @@ impl Into<Program> for CompUnit {
             bb,
             v: None,
             vm: ValueManager::new(),
+            session,
         };
         // parse exp
         self.func_def.block.build(&mut program, &mut params);
+
+        // blocks left open, like the end of an if whose arms both return,
+        // fall off the end of the function: return 0 as main does
+        let main_data = program.func_mut(main);
+        let open: Vec<BasicBlock> = main_data
+            .layout()
+            .bbs()
+            .iter()
+            .filter(|(_, node)| match node.insts().back_key() {
+                Some(v) => !matches!(
+                    main_data.dfg().value(*v).kind(),
+                    ValueKind::Return(_) | ValueKind::Jump(_) | ValueKind::Branch(_)
+                ),
+                None => true,
+            })
+            .map(|(bb, _)| *bb)
+            .collect();
+        for bb in open {
+            let zero = main_data.dfg_mut().new_value().integer(0);
+            let ret = main_data.dfg_mut().new_value().ret(Some(zero));
+            main_data.layout_mut().bb_mut(bb).insts_mut().extend([ret]);
+        }
         program
     }
 }
 
 /// Build params.
-struct BuildParams {
+struct BuildParams<'a> {
     func: Function,
     bb: BasicBlock,
     /// last value
@@ -113,6 +141,9 @@ struct BuildParams {
 
     /// variable manager
     vm: ValueManager,
+
+    /// owner of basic block numbering
+    session: &'a mut Session,
 }
 
 impl Into<Type> for FuncType {
@@ -203,7 +234,7 @@ impl SimpleStmt {
 
                         match func_data.layout().bbs().node(&params.bb).unwrap().insts().back_key() {
                             Some(v) if matches!(func_data.dfg().value(*v).kind(), ValueKind::Return(_)) => {
-                                let ret_bb = func_data.dfg_mut().new_bb().basic_block(next_bb_id!("%ret"));
+                                let ret_bb = func_data.dfg_mut().new_bb().basic_block(next_bb_id!(params, "%ret"));
                                 func_data.layout_mut().bbs_mut().extend([ret_bb]);
                                 params.bb = ret_bb;
                             }
@@ -450,8 +481,8 @@ impl LOrExp {
                 let result = func_data.dfg_mut().new_value().alloc(Type::get_i32());
                 func_data.layout_mut().bb_mut(params.bb).insts_mut().extend([result]);
 
-                let r_bb = func_data.dfg_mut().new_bb().basic_block(next_bb_id!("%or_r"));
-                let end_bb = func_data.dfg_mut().new_bb().basic_block(next_bb_id!("%or_end"));
+                let r_bb = func_data.dfg_mut().new_bb().basic_block(next_bb_id!(params, "%or_r"));
+                let end_bb = func_data.dfg_mut().new_bb().basic_block(next_bb_id!(params, "%or_end"));
                 func_data.layout_mut().bbs_mut().extend([r_bb, end_bb]);
 
                 lor_exp.build(program, params);
@@ -500,8 +531,8 @@ impl LAndExp {
                 let result = func_data.dfg_mut().new_value().alloc(Type::get_i32());
                 func_data.layout_mut().bb_mut(params.bb).insts_mut().extend([result]);
 
-                let r_bb = func_data.dfg_mut().new_bb().basic_block(next_bb_id!("%and_r"));
-                let end_bb = func_data.dfg_mut().new_bb().basic_block(next_bb_id!("%and_end"));
+                let r_bb = func_data.dfg_mut().new_bb().basic_block(next_bb_id!(params, "%and_r"));
+                let end_bb = func_data.dfg_mut().new_bb().basic_block(next_bb_id!(params, "%and_end"));
                 func_data.layout_mut().bbs_mut().extend([r_bb, end_bb]);
 
                 land_exp.build(program, params);
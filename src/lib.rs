@@ -1,2 +1,5 @@
+pub mod analysis;
 pub mod asm;
 pub mod ir;
+pub mod opt;
+pub mod session;
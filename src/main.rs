@@ -1,4 +1,7 @@
 use compiler::asm::visitor::Visitor;
+use compiler::opt::manager::PassManager;
+use compiler::opt::verifier::verify;
+use compiler::session::{Options, Session};
 use koopa::back::KoopaGenerator;
 use lalrpop_util::lalrpop_mod;
 use std::env::args;
@@ -12,12 +15,20 @@ lalrpop_mod!(sysy);
 
 fn main() -> Result<()> {
     // 解析命令行参数
-    let (mode, input, output) = parse_args();
+    let (mode, input, output, options) = parse_args();
     // 读取输入文件
     let input = read_to_string(input)?;
     // 调用 lalrpop 生成的 parser 解析输入文件
     let ast = sysy::CompUnitParser::new().parse(&dbg!(input)).unwrap();
-    let program = ast.into();
+    let mut session = Session::new(options);
+    let mut program = ast.build(&mut session);
+    if session.options.verify {
+        if let Err(errors) = verify(&program) {
+            fail(&format!("broken IR after generation:\n{}", errors.join("\n")));
+        }
+    }
+    let mut passes = PassManager::from_options(&session.options).unwrap_or_else(|e| fail(&e));
+    passes.run(&mut program).unwrap_or_else(|e| fail(&e));
     let mut file = File::create(output)?;
     let text;
     match mode.as_str() {
@@ -28,8 +39,8 @@ fn main() -> Result<()> {
             text = std::str::from_utf8(&gen.writer()).unwrap().to_string();
             // println!("{}", text_form_ir);
         }
-        "-riscv" => {
-            let mut asm_visitor = Visitor::default();
+        "-riscv" | "-perf" => {
+            let mut asm_visitor = Visitor::new(&session);
             let mut riscv_code = Vec::new();
             asm_visitor.visit(&mut riscv_code, &program)?;
             text = String::from_utf8(riscv_code).unwrap();
@@ -43,12 +54,88 @@ fn main() -> Result<()> {
     Ok(())
 }
 
-fn parse_args() -> (String, String, String) {
-    let mut args = args();
-    args.next();
-    let mode = args.next().unwrap();
-    let input = args.next().unwrap();
-    args.next();
-    let output = args.next().unwrap();
-    (mode, input, output)
+/// Reports `message` and exits with a failure status.
+fn fail(message: &str) -> ! {
+    eprintln!("error: {}", message);
+    std::process::exit(1)
+}
+
+/// Parses `mode input -o output`, with the options of `Options` allowed
+/// anywhere.
+/// `-perf` generates riscv at `-O2` unless told otherwise.
+fn parse_args() -> (String, String, String, Options) {
+    let mut options = Options::default();
+    let mut positional = Vec::new();
+    let mut output = None;
+    let mut opt_level = None;
+    let mut args = args().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == "-o" {
+            output = args.next();
+        } else if let Some(level) = arg.strip_prefix("-O") {
+            opt_level = Some(level.parse().expect("invalid optimization level"));
+        } else if let Some(names) = arg.strip_prefix("-passes=") {
+            options.passes = Some(names.split(',').filter(|n| !n.is_empty()).map(String::from).collect());
+        } else if arg == "-print-after-all" {
+            options.print_after_all = true;
+        } else if let Some(name) = arg.strip_prefix("-print-after=") {
+            options.print_after.push(name.to_string());
+        } else if arg == "-print-changed" {
+            options.print_changed = true;
+        } else if let Some(limit) = arg.strip_prefix("-opt-bisect-limit=") {
+            options.opt_bisect_limit = Some(limit.parse().expect("invalid bisect limit"));
+        } else if arg == "-verify" {
+            options.verify = true;
+        } else if let Some(factor) = arg.strip_prefix("-unroll-factor=") {
+            options.unroll_factor = Some(factor.parse().expect("invalid unroll factor"));
+        } else {
+            positional.push(arg);
+        }
+    }
+    let mut positional = positional.into_iter();
+    let mode = positional.next().unwrap();
+    let input = positional.next().unwrap();
+    options.opt_level = opt_level.unwrap_or(if mode == "-perf" { 2 } else { 0 });
+    (mode, input, output.unwrap(), options)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The Koopa text generated for `source` in a session of its own.
+    fn koopa(source: &str) -> String {
+        let ast = sysy::CompUnitParser::new().parse(source).unwrap();
+        let program = ast.build(&mut Session::default());
+        let mut gen = KoopaGenerator::new(Vec::new());
+        gen.generate_on(&program).unwrap();
+        String::from_utf8(gen.writer()).unwrap()
+    }
+
+    #[test]
+    fn labels_do_not_depend_on_earlier_compilations() {
+        let source = "int main() { int a = 1; if (a) { a = 2; } else { a = 3; } return a; }";
+        let first = koopa(source);
+        koopa("int main() { if (1) return 1; else return 2; }");
+        assert_eq!(koopa(source), first);
+    }
+
+    #[test]
+    fn open_blocks_return_zero() {
+        for source in [
+            "int main() { int a = 3; if (a > 2) { a = 5; } }",
+            "int main() { int a = 3; if (a > 2) { return 1; } else { return 2; } }",
+        ] {
+            let ast = sysy::CompUnitParser::new().parse(source).unwrap();
+            let mut session = Session::new(Options {
+                opt_level: 2,
+                verify: true,
+                ..Options::default()
+            });
+            let mut program = ast.build(&mut session);
+            assert_eq!(verify(&program), Ok(()), "{}", source);
+            PassManager::from_options(&session.options).unwrap().run(&mut program).unwrap();
+        }
+        assert!(koopa("int main() { int a = 3; if (a > 2) { a = 5; } }").contains("ret 0"));
+    }
 }
@@ -0,0 +1,39 @@
+use crate::opt::utils::{is_pure, remove_inst, remove_unreachable_bbs};
+use crate::opt::FunctionPass;
+use koopa::ir::{Function, FunctionData, Value};
+
+/// Dead code elimination: removes the basic blocks that cannot be reached
+/// and the side-effect-free instructions whose results are never used.
+pub struct DeadCodeElimination;
+
+impl FunctionPass for DeadCodeElimination {
+    fn run_on(&mut self, _func: Function, data: &mut FunctionData) -> bool {
+        let mut changed = remove_unreachable_bbs(data);
+
+        let mut worklist: Vec<Value> = data
+            .layout()
+            .bbs()
+            .nodes()
+            .flat_map(|node| node.insts().keys().copied())
+            .collect();
+        while let Some(inst) = worklist.pop() {
+            // an operand may have gone already, or gained a use back
+            let value = match data.dfg().values().get(&inst) {
+                Some(value) => value,
+                None => continue,
+            };
+            if !value.used_by().is_empty() || !is_pure(value.kind()) {
+                continue;
+            }
+            let operands: Vec<Value> = value
+                .kind()
+                .value_uses()
+                .filter(|v| !v.is_global() && data.layout().parent_bb(*v).is_some())
+                .collect();
+            remove_inst(data, inst);
+            worklist.extend(operands);
+            changed = true;
+        }
+        changed
+    }
+}
@@ -0,0 +1,87 @@
+use crate::analysis::callgraph::CallGraph;
+use crate::opt::ModulePass;
+use koopa::ir::{Function, FunctionData, Program, Value, ValueKind};
+use std::collections::HashSet;
+
+/// Dead global elimination: functions `main` never reaches through calls,
+/// declarations included, and global variables no remaining function
+/// refers to are removed from the program.
+///
+/// Programs without a `main` are left alone.
+pub struct GlobalDeadCodeElimination;
+
+impl ModulePass for GlobalDeadCodeElimination {
+    fn run_on(&mut self, program: &mut Program) -> bool {
+        let main = match program.func_layout().iter().find(|f| program.func(**f).name() == "@main") {
+            Some(main) => *main,
+            None => return false,
+        };
+        let graph = CallGraph::new(program);
+        let mut live: HashSet<Function> = HashSet::new();
+        let mut worklist = vec![main];
+        while let Some(func) = worklist.pop() {
+            if live.insert(func) {
+                worklist.extend(graph.callees(func).iter().copied());
+            }
+        }
+
+        let mut changed = false;
+        for func in program.func_layout().to_vec() {
+            if !live.contains(&func) {
+                clear_body(program.func_mut(func));
+                program.remove_func(func);
+                changed = true;
+            }
+        }
+        // what the removed functions used no longer counts
+        let dead: Vec<Value> = program
+            .inst_layout()
+            .iter()
+            .copied()
+            .filter(|g| program.borrow_value(*g).used_by().is_empty())
+            .collect();
+        for global in dead {
+            let init = match program.borrow_value(global).kind() {
+                ValueKind::GlobalAlloc(alloc) => alloc.init(),
+                _ => unreachable!(),
+            };
+            program.remove_value(global);
+            remove_constant(program, init);
+            changed = true;
+        }
+        changed
+    }
+}
+
+/// Removes every instruction of `func` from its data flow graph, so that
+/// the globals they use lose them as users.
+fn clear_body(func: &mut FunctionData) {
+    let mut insts: Vec<Value> = func.layout().bbs().nodes().flat_map(|node| node.insts().keys().copied()).collect();
+    func.layout_mut().bbs_mut().clear();
+    // loops only go through block parameters, so each round frees at least
+    // one instruction
+    while !insts.is_empty() {
+        let (unused, used): (Vec<Value>, Vec<Value>) =
+            insts.into_iter().partition(|v| func.dfg().value(*v).used_by().is_empty());
+        for v in unused {
+            func.dfg_mut().remove_value(v);
+        }
+        insts = used;
+    }
+}
+
+/// Removes the initializer `value` of a removed global, with its elements,
+/// unless something else still uses it.
+fn remove_constant(program: &mut Program, value: Value) {
+    if !program.borrow_value(value).used_by().is_empty() {
+        return;
+    }
+    let elems = match program.borrow_value(value).kind() {
+        ValueKind::Aggregate(a) => a.elems().to_vec(),
+        _ => Vec::new(),
+    };
+    program.remove_value(value);
+    for elem in elems {
+        remove_constant(program, elem);
+    }
+}
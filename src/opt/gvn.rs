@@ -0,0 +1,153 @@
+use crate::analysis::alias::AliasAnalysis;
+use crate::analysis::cfg::predecessors;
+use crate::analysis::dominators::DomTree;
+use crate::opt::utils::{remove_inst, replace_all_uses};
+use crate::opt::FunctionPass;
+use koopa::ir::{BasicBlock, BinaryOp, Function, FunctionData, Value, ValueKind};
+use std::collections::HashMap;
+
+/// Global value numbering over the dominator tree: a `binary`, `getptr` or
+/// `getelemptr` computing the same thing as one that dominates it is
+/// replaced by that one, and so is a load from an address already loaded
+/// from with no write to it in between.
+///
+/// Loads are only reused within an extended basic block, where each block
+/// but the first has the one before it as its only predecessor, so that
+/// every write in between is seen.
+pub struct GlobalValueNumbering;
+
+impl FunctionPass for GlobalValueNumbering {
+    fn run_on(&mut self, _func: Function, data: &mut FunctionData) -> bool {
+        let entry = match data.layout().entry_bb() {
+            Some(entry) => entry,
+            None => return false,
+        };
+        let dom = DomTree::dominators(data);
+        let preds = predecessors(data);
+        let alias = AliasAnalysis::new(data);
+
+        let mut changed = false;
+        // expressions computed by the dominators of the current block, and
+        // the order they were added in, to drop them leaving a subtree
+        let mut available: HashMap<Expr, Value> = HashMap::new();
+        let mut undo: Vec<Expr> = Vec::new();
+        let mut stack = vec![Visit::Enter(entry, HashMap::new())];
+        while let Some(visit) = stack.pop() {
+            let (bb, mut loads) = match visit {
+                Visit::Enter(bb, loads) => (bb, loads),
+                Visit::Exit(len) => {
+                    for expr in undo.drain(len..) {
+                        available.remove(&expr);
+                    }
+                    continue;
+                }
+            };
+            stack.push(Visit::Exit(undo.len()));
+
+            let insts: Vec<Value> = data.layout().bbs().node(&bb).unwrap().insts().keys().copied().collect();
+            for inst in insts {
+                match data.dfg().value(inst).kind() {
+                    ValueKind::Load(l) => {
+                        let src = l.src();
+                        match loads.get(&src) {
+                            Some(leader) => {
+                                replace_all_uses(data.dfg_mut(), inst, *leader);
+                                remove_inst(data, inst);
+                                changed = true;
+                            }
+                            None => {
+                                loads.insert(src, inst);
+                            }
+                        }
+                    }
+                    ValueKind::Store(s) => {
+                        let dest = s.dest();
+                        loads.retain(|src, _| !alias.may_alias(data, *src, dest));
+                    }
+                    ValueKind::Call(_) => loads.retain(|src, _| !alias.call_may_write(data, *src)),
+                    _ => {
+                        let exprs = match expressions(data, inst) {
+                            Some(exprs) => exprs,
+                            None => continue,
+                        };
+                        match exprs.iter().find_map(|e| available.get(e)) {
+                            Some(leader) => {
+                                replace_all_uses(data.dfg_mut(), inst, *leader);
+                                remove_inst(data, inst);
+                                changed = true;
+                            }
+                            None => {
+                                let expr = exprs.into_iter().next().unwrap();
+                                available.insert(expr.clone(), inst);
+                                undo.push(expr);
+                            }
+                        }
+                    }
+                }
+            }
+
+            for child in dom.children(bb).iter().rev() {
+                let loads = if preds[child].as_slice() == [bb] { loads.clone() } else { HashMap::new() };
+                stack.push(Visit::Enter(*child, loads));
+            }
+        }
+        changed
+    }
+}
+
+enum Visit {
+    /// a block, with the loads available on entering it
+    Enter(BasicBlock, HashMap<Value, Value>),
+    /// the end of the subtree of a block, with the length of the undo log
+    /// before it
+    Exit(usize),
+}
+
+/// An operand of an expression: constants are the same whatever value
+/// holds them.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+enum Operand {
+    Const(i32),
+    Value(Value),
+}
+
+#[derive(Clone, PartialEq, Eq, Hash)]
+enum Expr {
+    Binary(BinaryOp, Operand, Operand),
+    GetPtr(Operand, Operand),
+    GetElemPtr(Operand, Operand),
+}
+
+/// The expressions `inst` computes: two for commutative operators, one
+/// with each order of the operands.
+fn expressions(func: &FunctionData, inst: Value) -> Option<Vec<Expr>> {
+    let operand = |v: Value| {
+        if !v.is_global() {
+            if let ValueKind::Integer(n) = func.dfg().value(v).kind() {
+                return Operand::Const(n.value());
+            }
+        }
+        Operand::Value(v)
+    };
+    let exprs = match func.dfg().value(inst).kind() {
+        ValueKind::Binary(b) => {
+            let (lhs, rhs) = (operand(b.lhs()), operand(b.rhs()));
+            let mut exprs = vec![Expr::Binary(b.op(), lhs, rhs)];
+            if is_commutative(b.op()) {
+                exprs.push(Expr::Binary(b.op(), rhs, lhs));
+            }
+            exprs
+        }
+        ValueKind::GetPtr(p) => vec![Expr::GetPtr(operand(p.src()), operand(p.index()))],
+        ValueKind::GetElemPtr(p) => vec![Expr::GetElemPtr(operand(p.src()), operand(p.index()))],
+        _ => return None,
+    };
+    Some(exprs)
+}
+
+fn is_commutative(op: BinaryOp) -> bool {
+    matches!(
+        op,
+        BinaryOp::Add | BinaryOp::Mul | BinaryOp::And | BinaryOp::Or | BinaryOp::Xor | BinaryOp::Eq | BinaryOp::NotEq
+    )
+}
@@ -0,0 +1,262 @@
+use crate::analysis::callgraph::CallGraph;
+use crate::analysis::dominators::DomTree;
+use crate::opt::utils::{fresh_bb_name, map_operands, remove_inst, replace_all_uses};
+use crate::opt::ModulePass;
+use koopa::ir::builder_traits::*;
+use koopa::ir::entities::ValueData;
+use koopa::ir::{BasicBlock, Function, FunctionData, Program, Type, Value, ValueKind};
+use std::collections::HashMap;
+
+/// Functions no longer than this are inlined wherever they are called.
+const INLINE_SIZE: usize = 32;
+/// Functions are not grown past this by inlining.
+const MAX_CALLER_SIZE: usize = 4096;
+
+/// A block of the copied function, with its name and typed parameters.
+type BlockHeader = (BasicBlock, Option<String>, Vec<(Value, Type)>);
+
+/// Function inlining: a call to a small function, or to one called from a
+/// single place, is replaced by a copy of its body, with its parameters
+/// replaced by the arguments and its returns by jumps to the rest of the
+/// calling block.
+///
+/// Callees are inlined into their callers bottom-up over the call graph, so
+/// that what gets copied has had its own calls inlined already. Recursive
+/// functions are never inlined.
+pub struct Inliner;
+
+impl ModulePass for Inliner {
+    fn run_on(&mut self, program: &mut Program) -> bool {
+        let graph = CallGraph::new(program);
+        let mut changed = false;
+        for scc in graph.sccs() {
+            for caller in scc.iter() {
+                if program.func(*caller).layout().entry_bb().is_none() {
+                    continue;
+                }
+                let calls: Vec<(Value, Function)> = {
+                    let data = program.func(*caller);
+                    data.layout()
+                        .bbs()
+                        .nodes()
+                        .flat_map(|node| node.insts().keys())
+                        .filter_map(|inst| match data.dfg().value(*inst).kind() {
+                            ValueKind::Call(c) => Some((*inst, c.callee())),
+                            _ => None,
+                        })
+                        .collect()
+                };
+                let mut size = function_size(program.func(*caller));
+                for (call, callee) in calls {
+                    let callee_data = program.func(callee);
+                    if callee_data.layout().entry_bb().is_none() || graph.is_recursive(callee) {
+                        continue;
+                    }
+                    let callee_size = function_size(callee_data);
+                    let small = callee_size <= INLINE_SIZE || graph.call_sites(callee) == 1;
+                    if !small || size + callee_size > MAX_CALLER_SIZE {
+                        continue;
+                    }
+                    let body = Body::new(callee_data);
+                    body.inline(program.func_mut(*caller), call);
+                    size += callee_size;
+                    changed = true;
+                }
+            }
+        }
+        changed
+    }
+}
+
+pub(crate) fn function_size(func: &FunctionData) -> usize {
+    func.layout().bbs().nodes().map(|node| node.insts().len()).sum()
+}
+
+/// A copy of the body of a function, to paste into another one.
+pub(crate) struct Body {
+    /// the name of the function, without the `@`
+    name: String,
+    /// the parameters of the function
+    params: Vec<Value>,
+    /// the reachable blocks in dominator order, with their names and
+    /// parameters, so that every value is defined before it is used
+    bbs: Vec<BlockHeader>,
+    insts: HashMap<BasicBlock, Vec<(Value, ValueData)>>,
+    /// constants the instructions use, which are local to each function
+    constants: HashMap<Value, ValueData>,
+}
+
+impl Body {
+    pub(crate) fn new(func: &FunctionData) -> Self {
+        let dom = DomTree::dominators(func);
+        let mut bbs = Vec::new();
+        let mut insts = HashMap::new();
+        let mut constants = HashMap::new();
+        for bb in dom.preorder() {
+            let data = func.dfg().bb(bb);
+            let params = data.params().iter().map(|p| (*p, func.dfg().value(*p).ty().clone())).collect();
+            bbs.push((bb, data.name().clone(), params));
+            let mut list = Vec::new();
+            for inst in func.layout().bbs().node(&bb).unwrap().insts().keys() {
+                let data = func.dfg().value(*inst).clone();
+                for v in data.kind().value_uses() {
+                    collect_constants(func, v, &mut constants);
+                }
+                list.push((*inst, data));
+            }
+            insts.insert(bb, list);
+        }
+        Body {
+            name: func.name()[1..].to_string(),
+            params: func.params().to_vec(),
+            bbs,
+            insts,
+            constants,
+        }
+    }
+
+    /// Replaces `call`, an instruction of `func`, by a copy of the body.
+    fn inline(&self, func: &mut FunctionData, call: Value) {
+        let args = match func.dfg().value(call).kind() {
+            ValueKind::Call(c) => c.args().to_vec(),
+            _ => unreachable!(),
+        };
+        let ty = func.dfg().value(call).ty().clone();
+        let bb = func.layout().parent_bb(call).unwrap();
+
+        // what follows the call moves to a block the returns jump to, with
+        // the returned value as its parameter
+        let name = fresh_bb_name(func, &format!("%{}_exit", self.name));
+        let tys = if ty.is_unit() { vec![] } else { vec![ty] };
+        let exit = func.dfg_mut().new_bb().basic_block_with_params(Some(name), tys);
+        func.layout_mut().bbs_mut().cursor_mut(bb).insert_key_after(exit).unwrap();
+        let rest: Vec<Value> = {
+            let insts = func.layout().bbs().node(&bb).unwrap().insts();
+            insts.keys().skip_while(|inst| **inst != call).skip(1).copied().collect()
+        };
+        for inst in rest {
+            func.layout_mut().bb_mut(bb).insts_mut().remove(&inst);
+            func.layout_mut().bb_mut(exit).insts_mut().push_key_back(inst).unwrap();
+        }
+        if let Some(result) = func.dfg().bb(exit).params().first().copied() {
+            replace_all_uses(func.dfg_mut(), call, result);
+        }
+        remove_inst(func, call);
+
+        let values = self.params.iter().copied().zip(args).collect();
+        let prefix = format!("{}_", self.name);
+        let (entry, allocs) = self.paste(func, values, &prefix, Some(exit));
+        let jump = func.dfg_mut().new_value().jump(entry);
+        func.layout_mut().bb_mut(bb).insts_mut().push_key_back(jump).unwrap();
+        // locals live in the frame of the caller, allocated once
+        let caller_entry = func.layout().entry_bb().unwrap();
+        for alloc in allocs.into_iter().rev() {
+            func.layout_mut().bb_mut(caller_entry).insts_mut().push_key_front(alloc).unwrap();
+        }
+    }
+
+    /// Copies the body into `func`, with the parameters and whatever else
+    /// `values` maps replaced, naming the blocks after the originals with
+    /// `prefix` in front. Returns the copy of the entry block.
+    ///
+    /// Given an `exit` block, the copy goes in front of it and its returns
+    /// jump there, and the allocs are left out of the layout and returned
+    /// for the caller to place. Otherwise it goes at the end of `func`,
+    /// returns and all.
+    pub(crate) fn paste(
+        &self,
+        func: &mut FunctionData,
+        mut values: HashMap<Value, Value>,
+        prefix: &str,
+        exit: Option<BasicBlock>,
+    ) -> (BasicBlock, Vec<Value>) {
+        let mut bbs: HashMap<BasicBlock, BasicBlock> = HashMap::new();
+        for (old, name, params) in self.bbs.iter() {
+            let name = fresh_bb_name(func, &format!("%{}{}", prefix, name.as_deref().map_or("bb", |n| &n[1..])));
+            let tys = params.iter().map(|(_, ty)| ty.clone()).collect();
+            let new = func.dfg_mut().new_bb().basic_block_with_params(Some(name), tys);
+            match exit {
+                Some(exit) => func.layout_mut().bbs_mut().cursor_mut(exit).insert_key_before(new).unwrap(),
+                None => func.layout_mut().bbs_mut().push_key_back(new).unwrap(),
+            }
+            for ((param, _), new_param) in params.iter().zip(func.dfg().bb(new).params()) {
+                values.insert(*param, *new_param);
+            }
+            bbs.insert(*old, new);
+        }
+
+        let mut allocs = Vec::new();
+        for (old, _, _) in self.bbs.iter() {
+            for (inst, data) in self.insts[old].iter() {
+                let mut data = data.clone();
+                for v in data.kind().value_uses().collect::<Vec<_>>() {
+                    if !v.is_global() && !values.contains_key(&v) {
+                        let constant = self.constant(func, v);
+                        values.insert(v, constant);
+                    }
+                }
+                map_operands(data.kind_mut(), |v| values.get(&v).copied().unwrap_or(v));
+                let new = match (data.kind_mut(), exit) {
+                    (ValueKind::Return(r), Some(exit)) => {
+                        let args = r.value().into_iter().collect();
+                        func.dfg_mut().new_value().jump_with_args(exit, args)
+                    }
+                    (kind, _) => {
+                        match kind {
+                            ValueKind::Jump(j) => *j.target_mut() = bbs[&j.target()],
+                            ValueKind::Branch(b) => {
+                                *b.true_bb_mut() = bbs[&b.true_bb()];
+                                *b.false_bb_mut() = bbs[&b.false_bb()];
+                            }
+                            _ => {}
+                        }
+                        let new = func.dfg_mut().new_value().raw(data);
+                        func.dfg_mut().set_value_name(new, None);
+                        new
+                    }
+                };
+                if exit.is_some() && matches!(func.dfg().value(new).kind(), ValueKind::Alloc(_)) {
+                    allocs.push(new);
+                } else {
+                    func.layout_mut().bb_mut(bbs[old]).insts_mut().push_key_back(new).unwrap();
+                }
+                values.insert(*inst, new);
+            }
+        }
+        (bbs[&self.bbs[0].0], allocs)
+    }
+
+    /// A copy in `func` of the constant `value` of the body.
+    fn constant(&self, func: &mut FunctionData, value: Value) -> Value {
+        let data = &self.constants[&value];
+        match data.kind() {
+            ValueKind::Integer(n) => func.dfg_mut().new_value().integer(n.value()),
+            ValueKind::ZeroInit(_) => func.dfg_mut().new_value().zero_init(data.ty().clone()),
+            ValueKind::Undef(_) => func.dfg_mut().new_value().undef(data.ty().clone()),
+            ValueKind::Aggregate(a) => {
+                let elems = a.elems().iter().map(|e| self.constant(func, *e)).collect();
+                func.dfg_mut().new_value().aggregate(elems)
+            }
+            _ => unreachable!(),
+        }
+    }
+}
+
+/// Records `value` if it is a constant of `func`, with the constants it is
+/// made of.
+fn collect_constants(func: &FunctionData, value: Value, constants: &mut HashMap<Value, ValueData>) {
+    if value.is_global() || constants.contains_key(&value) {
+        return;
+    }
+    let data = func.dfg().value(value);
+    match data.kind() {
+        ValueKind::Integer(_) | ValueKind::ZeroInit(_) | ValueKind::Undef(_) => {}
+        ValueKind::Aggregate(a) => {
+            for elem in a.elems() {
+                collect_constants(func, *elem, constants);
+            }
+        }
+        _ => return,
+    }
+    constants.insert(value, data.clone());
+}
@@ -0,0 +1,225 @@
+use crate::analysis::cfg::reverse_postorder;
+use crate::opt::utils::{fold_binary, integer, remove_inst, replace_all_uses};
+use crate::opt::FunctionPass;
+use koopa::ir::builder_traits::*;
+use koopa::ir::{BinaryOp, Function, FunctionData, Value, ValueKind};
+
+/// Instruction combining: algebraic identities such as `x + 0`, `x * 0` or
+/// `x - x`, the double negations and comparisons with zero the frontend
+/// builds for unary operators and logical values, and reassociation of
+/// constant operands, as in `(x + 1) + 2` to `x + 3`.
+///
+/// Constants are moved to the right of commutative operators and `x - c`
+/// becomes `x + -c`, so that each rule only has one form to match.
+pub struct InstCombine;
+
+impl FunctionPass for InstCombine {
+    fn run_on(&mut self, _func: Function, data: &mut FunctionData) -> bool {
+        let mut worklist: Vec<Value> = reverse_postorder(data)
+            .into_iter()
+            .flat_map(|bb| data.layout().bbs().node(&bb).unwrap().insts().keys().copied().collect::<Vec<_>>())
+            .collect();
+        worklist.reverse();
+
+        let mut changed = false;
+        while let Some(inst) = worklist.pop() {
+            // removed since it was queued
+            if data.layout().parent_bb(inst).is_none() {
+                continue;
+            }
+            match data.dfg().value(inst).kind().clone() {
+                ValueKind::Binary(b) => {
+                    let simplified = match simplify(data, b.op(), b.lhs(), b.rhs()) {
+                        Some(simplified) => simplified,
+                        None => continue,
+                    };
+                    worklist.extend(data.dfg().value(inst).used_by().iter().copied());
+                    match simplified {
+                        Simplified::Value(value) => {
+                            replace_all_uses(data.dfg_mut(), inst, value);
+                            remove_inst(data, inst);
+                        }
+                        Simplified::Const(n) => {
+                            let n = data.dfg_mut().new_value().integer(n);
+                            replace_all_uses(data.dfg_mut(), inst, n);
+                            remove_inst(data, inst);
+                        }
+                        Simplified::Binary(op, lhs, rhs) => {
+                            data.dfg_mut().replace_value_with(inst).binary(op, lhs, rhs);
+                            worklist.push(inst);
+                        }
+                    }
+                    remove_if_dead(data, b.lhs());
+                    remove_if_dead(data, b.rhs());
+                    changed = true;
+                }
+                ValueKind::Branch(br) => {
+                    // branch on `x` rather than on whether it is zero
+                    let (x, swap) = match as_binary(data, br.cond()) {
+                        Some((BinaryOp::NotEq, x, zero)) if integer(data, zero) == Some(0) => (x, false),
+                        Some((BinaryOp::Eq, x, zero)) if integer(data, zero) == Some(0) => (x, true),
+                        _ => continue,
+                    };
+                    let (mut t, mut t_args) = (br.true_bb(), br.true_args().to_vec());
+                    let (mut f, mut f_args) = (br.false_bb(), br.false_args().to_vec());
+                    if swap {
+                        std::mem::swap(&mut t, &mut f);
+                        std::mem::swap(&mut t_args, &mut f_args);
+                    }
+                    data.dfg_mut().replace_value_with(inst).branch_with_args(x, t, f, t_args, f_args);
+                    remove_if_dead(data, br.cond());
+                    changed = true;
+                }
+                _ => {}
+            }
+        }
+        changed
+    }
+}
+
+/// A simpler form of a binary instruction.
+enum Simplified {
+    /// an existing value
+    Value(Value),
+    Const(i32),
+    /// another binary instruction
+    Binary(BinaryOp, Value, Value),
+}
+
+fn simplify(func: &mut FunctionData, op: BinaryOp, lhs: Value, rhs: Value) -> Option<Simplified> {
+    use BinaryOp::*;
+    let (l, r) = (integer(func, lhs), integer(func, rhs));
+    if let (Some(l), Some(r)) = (l, r) {
+        return Some(Simplified::Const(fold_binary(op, l, r)));
+    }
+    if l.is_some() {
+        if let Some(swapped) = swapped(op) {
+            return Some(Simplified::Binary(swapped, rhs, lhs));
+        }
+    }
+
+    if let Some(c) = r {
+        match (op, c) {
+            (Add | Sub | Or | Xor | Shl | Shr | Sar, 0) | (Mul | Div, 1) => return Some(Simplified::Value(lhs)),
+            (Mul | And, 0) | (Mod, 1 | -1) => return Some(Simplified::Const(0)),
+            (Div, -1) => {
+                let zero = func.dfg_mut().new_value().integer(0);
+                return Some(Simplified::Binary(Sub, zero, lhs));
+            }
+            (Sub, _) => {
+                let c = func.dfg_mut().new_value().integer(c.wrapping_neg());
+                return Some(Simplified::Binary(Add, lhs, c));
+            }
+            _ => {}
+        }
+        match (op, c, as_binary(func, lhs)) {
+            // (x op c1) op c2 to x op (c1 op c2)
+            (Add | Mul | And | Or | Xor, _, Some((inner, x, c1))) if inner == op && integer(func, c1).is_some() => {
+                let c = fold_binary(op, integer(func, c1).unwrap(), c);
+                let c = func.dfg_mut().new_value().integer(c);
+                return Some(Simplified::Binary(op, x, c));
+            }
+            // (c1 - x) + c2 to (c1 + c2) - x
+            (Add, _, Some((Sub, c1, x))) if integer(func, c1).is_some() => {
+                let c = integer(func, c1).unwrap().wrapping_add(c);
+                let c = func.dfg_mut().new_value().integer(c);
+                return Some(Simplified::Binary(Sub, c, x));
+            }
+            // comparisons give 0 or 1, so comparing them with 0 or 1 is the
+            // comparison itself or its inverse
+            (Eq, 0, Some((cmp, a, b))) | (NotEq, 1, Some((cmp, a, b))) if inverse(cmp).is_some() => {
+                return Some(Simplified::Binary(inverse(cmp).unwrap(), a, b));
+            }
+            (NotEq, 0, Some((cmp, _, _))) | (Eq, 1, Some((cmp, _, _))) if inverse(cmp).is_some() => {
+                return Some(Simplified::Value(lhs));
+            }
+            _ => {}
+        }
+    }
+
+    if let Some(c) = l {
+        match (op, c, as_binary(func, rhs)) {
+            (Shl | Shr | Sar, 0, _) => return Some(Simplified::Const(0)),
+            // 0 - (0 - x) to x
+            (Sub, 0, Some((Sub, zero, x))) if integer(func, zero) == Some(0) => return Some(Simplified::Value(x)),
+            // c1 - (x + c2) to (c1 - c2) - x
+            (Sub, _, Some((Add, x, c2))) if integer(func, c2).is_some() => {
+                let c = c.wrapping_sub(integer(func, c2).unwrap());
+                let c = func.dfg_mut().new_value().integer(c);
+                return Some(Simplified::Binary(Sub, c, x));
+            }
+            _ => {}
+        }
+    }
+
+    if lhs == rhs {
+        return match op {
+            Sub | Xor | NotEq | Lt | Gt => Some(Simplified::Const(0)),
+            Eq | Le | Ge => Some(Simplified::Const(1)),
+            And | Or => Some(Simplified::Value(lhs)),
+            _ => None,
+        };
+    }
+    None
+}
+
+/// The operator giving the same result with the operands swapped, if any.
+fn swapped(op: BinaryOp) -> Option<BinaryOp> {
+    use BinaryOp::*;
+    match op {
+        Add | Mul | And | Or | Xor | Eq | NotEq => Some(op),
+        Lt => Some(Gt),
+        Gt => Some(Lt),
+        Le => Some(Ge),
+        Ge => Some(Le),
+        _ => None,
+    }
+}
+
+/// The comparison giving the opposite result, `None` if `op` does not
+/// compare.
+fn inverse(op: BinaryOp) -> Option<BinaryOp> {
+    use BinaryOp::*;
+    match op {
+        Eq => Some(NotEq),
+        NotEq => Some(Eq),
+        Lt => Some(Ge),
+        Ge => Some(Lt),
+        Gt => Some(Le),
+        Le => Some(Gt),
+        _ => None,
+    }
+}
+
+/// The operator and operands of `value` if a binary instruction defines it.
+fn as_binary(func: &FunctionData, value: Value) -> Option<(BinaryOp, Value, Value)> {
+    if value.is_global() {
+        return None;
+    }
+    match func.dfg().value(value).kind() {
+        ValueKind::Binary(b) => Some((b.op(), b.lhs(), b.rhs())),
+        _ => None,
+    }
+}
+
+/// Removes `value` if it is a binary instruction left without users, and
+/// then its operands in turn.
+fn remove_if_dead(func: &mut FunctionData, value: Value) {
+    let mut worklist = vec![value];
+    while let Some(value) = worklist.pop() {
+        // both operands may be the same value
+        if !value.is_global() && !func.dfg().values().contains_key(&value) {
+            continue;
+        }
+        let dead = match as_binary(func, value) {
+            Some((_, lhs, rhs)) if func.dfg().value(value).used_by().is_empty() => {
+                worklist.extend([lhs, rhs]);
+                func.layout().parent_bb(value).is_some()
+            }
+            _ => false,
+        };
+        if dead {
+            remove_inst(func, value);
+        }
+    }
+}
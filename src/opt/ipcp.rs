@@ -0,0 +1,206 @@
+use crate::analysis::dominators::DomTree;
+use crate::analysis::loops::LoopInfo;
+use crate::opt::inline::{function_size, Body};
+use crate::opt::utils::{integer, order_functions, replace_all_uses};
+use crate::opt::ModulePass;
+use koopa::ir::builder_traits::*;
+use koopa::ir::{Function, FunctionData, Program, Type, TypeKind, Value, ValueKind};
+use std::collections::{HashMap, HashSet};
+
+/// Functions longer than this are not specialized.
+const SPECIALIZE_SIZE: usize = 128;
+/// Specialized copies made of any one function at most.
+const MAX_SPECIALIZATIONS: usize = 4;
+
+/// Interprocedural constant propagation: a parameter every call passes the
+/// same constant to is replaced by that constant in the callee, which may
+/// make the arguments it passes on constant in turn.
+///
+/// Calls in loops passing constants to a function whose other calls pass
+/// something else go to a copy of it specialized for those constants
+/// instead, without the parameters they replace.
+pub struct InterproceduralConstProp;
+
+impl ModulePass for InterproceduralConstProp {
+    fn run_on(&mut self, program: &mut Program) -> bool {
+        let mut changed = false;
+        while propagate(program) {
+            changed = true;
+        }
+        if specialize(program) {
+            order_functions(program);
+            changed = true;
+        }
+        changed
+    }
+}
+
+/// What a call passes to a parameter.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Arg {
+    Const(i32),
+    /// the parameter itself, passed back by a recursive call
+    Same,
+    Other,
+}
+
+/// What the calls to each defined function pass to its parameters.
+fn call_args(program: &Program) -> HashMap<Function, Vec<Vec<Arg>>> {
+    let mut args: HashMap<Function, Vec<Vec<Arg>>> = HashMap::new();
+    for func in program.func_layout() {
+        let data = program.func(*func);
+        for node in data.layout().bbs().nodes() {
+            for inst in node.insts().keys() {
+                let c = match data.dfg().value(*inst).kind() {
+                    ValueKind::Call(c) => c,
+                    _ => continue,
+                };
+                let passed = c
+                    .args()
+                    .iter()
+                    .enumerate()
+                    .map(|(i, arg)| match integer(data, *arg) {
+                        Some(n) => Arg::Const(n),
+                        None if c.callee() == *func && data.params()[i] == *arg => Arg::Same,
+                        None => Arg::Other,
+                    })
+                    .collect();
+                args.entry(c.callee()).or_default().push(passed);
+            }
+        }
+    }
+    args
+}
+
+/// Replaces the parameters every call passes the same constant to,
+/// returning whether there were any.
+fn propagate(program: &mut Program) -> bool {
+    let args = call_args(program);
+    let mut changed = false;
+    for func in program.func_layout().to_vec() {
+        let calls = match args.get(&func) {
+            Some(calls) if program.func(func).layout().entry_bb().is_some() => calls,
+            _ => continue,
+        };
+        let data = program.func_mut(func);
+        for (i, param) in data.params().to_vec().into_iter().enumerate() {
+            if data.dfg().value(param).used_by().is_empty() {
+                continue;
+            }
+            let mut passed = calls.iter().map(|call| call[i]).filter(|arg| *arg != Arg::Same);
+            let n = match passed.next() {
+                Some(Arg::Const(n)) if passed.all(|arg| arg == Arg::Const(n)) => n,
+                _ => continue,
+            };
+            let constant = data.dfg_mut().new_value().integer(n);
+            replace_all_uses(data.dfg_mut(), param, constant);
+            changed = true;
+        }
+    }
+    changed
+}
+
+/// Sends the calls in loops passing constants to used parameters to
+/// specialized copies of their callees, returning whether there were any.
+fn specialize(program: &mut Program) -> bool {
+    let mut copies: HashMap<(Function, Vec<Option<i32>>), Function> = HashMap::new();
+    let mut count: HashMap<Function, usize> = HashMap::new();
+    let mut changed = false;
+    for caller in program.func_layout().to_vec() {
+        let hot = hot_calls(program.func(caller));
+        for call in hot {
+            let (callee, args) = match program.func(caller).dfg().value(call).kind() {
+                ValueKind::Call(c) => (c.callee(), c.args().to_vec()),
+                _ => unreachable!(),
+            };
+            let callee_data = program.func(callee);
+            if callee_data.layout().entry_bb().is_none() || function_size(callee_data) > SPECIALIZE_SIZE {
+                continue;
+            }
+            let constants: Vec<Option<i32>> = args
+                .iter()
+                .zip(callee_data.params())
+                .map(|(arg, param)| {
+                    let used = !callee_data.dfg().value(*param).used_by().is_empty();
+                    integer(program.func(caller), *arg).filter(|_| used)
+                })
+                .collect();
+            if constants.iter().all(Option::is_none) {
+                continue;
+            }
+            let key = (callee, constants);
+            let copy = match copies.get(&key) {
+                Some(copy) => *copy,
+                None if count.get(&callee).copied().unwrap_or(0) < MAX_SPECIALIZATIONS => {
+                    let copy = specialized_copy(program, callee, &key.1);
+                    *count.entry(callee).or_default() += 1;
+                    copies.insert(key.clone(), copy);
+                    copy
+                }
+                None => continue,
+            };
+            let rest: Vec<Value> = args.iter().zip(key.1.iter()).filter(|(_, c)| c.is_none()).map(|(arg, _)| *arg).collect();
+            let data = program.func_mut(caller);
+            let mut call_data = data.dfg().value(call).clone();
+            if let ValueKind::Call(c) = call_data.kind_mut() {
+                *c.callee_mut() = copy;
+                *c.args_mut() = rest;
+            }
+            data.dfg_mut().replace_value_with(call).raw(call_data);
+            changed = true;
+        }
+    }
+    changed
+}
+
+/// The calls of `func` in loops, in layout order.
+fn hot_calls(func: &FunctionData) -> Vec<Value> {
+    if func.layout().entry_bb().is_none() {
+        return Vec::new();
+    }
+    let loops = LoopInfo::new(func, &DomTree::dominators(func));
+    let mut calls = Vec::new();
+    for (bb, node) in func.layout().bbs() {
+        if loops.depth(*bb) == 0 {
+            continue;
+        }
+        calls.extend(node.insts().keys().filter(|inst| matches!(func.dfg().value(**inst).kind(), ValueKind::Call(_))));
+    }
+    calls
+}
+
+/// A copy of `func` with the parameters given a constant replaced by it.
+fn specialized_copy(program: &mut Program, func: Function, constants: &[Option<i32>]) -> Function {
+    let data = program.func(func);
+    let body = Body::new(data);
+    let params: Vec<(Option<String>, Type)> = data
+        .params()
+        .iter()
+        .zip(constants)
+        .filter(|(_, c)| c.is_none())
+        .map(|(p, _)| (data.dfg().value(*p).name().clone(), data.dfg().value(*p).ty().clone()))
+        .collect();
+    let ret = match data.ty().kind() {
+        TypeKind::Function(_, ret) => ret.clone(),
+        _ => unreachable!(),
+    };
+    let taken: HashSet<&str> = program.funcs().values().map(|f| f.name()).collect();
+    let name = (0..).map(|i| format!("{}_spec{}", data.name(), i)).find(|n| !taken.contains(n.as_str())).unwrap();
+
+    // the copy joins the program first, as the body refers to globals and
+    // other functions
+    let old_params = data.params().to_vec();
+    let copy = program.new_func(FunctionData::with_param_names(name, params, ret));
+    let copy_data = program.func_mut(copy);
+    let mut values = HashMap::new();
+    let mut fresh = copy_data.params().to_vec().into_iter();
+    for (param, c) in old_params.iter().zip(constants) {
+        let value = match c {
+            Some(n) => copy_data.dfg_mut().new_value().integer(*n),
+            None => fresh.next().unwrap(),
+        };
+        values.insert(*param, value);
+    }
+    body.paste(copy_data, values, "", None);
+    copy
+}
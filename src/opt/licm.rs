@@ -0,0 +1,150 @@
+use crate::analysis::alias::{AliasAnalysis, Base};
+use crate::analysis::cfg::successors;
+use crate::analysis::dominators::DomTree;
+use crate::analysis::loops::{Loop, LoopInfo};
+use crate::opt::utils::ensure_preheaders;
+use crate::opt::FunctionPass;
+use koopa::ir::{BasicBlock, Function, FunctionData, TypeKind, Value, ValueKind};
+use std::collections::{HashMap, HashSet};
+
+/// Loop-invariant code motion: computations whose operands do not change
+/// in a loop move to its preheader, inner loops first so that what leaves
+/// an inner loop can leave the outer ones too.
+///
+/// Arithmetic and address computations cannot fail, so they always move.
+/// A load moves when nothing in the loop may write to its address, and
+/// either it runs on every iteration or the address is surely valid.
+/// Loops get a preheader first when they lack one.
+pub struct LoopInvariantCodeMotion;
+
+impl FunctionPass for LoopInvariantCodeMotion {
+    fn run_on(&mut self, _func: Function, data: &mut FunctionData) -> bool {
+        if data.layout().entry_bb().is_none() {
+            return false;
+        }
+        let mut changed = ensure_preheaders(data);
+        let dom = DomTree::dominators(data);
+        let loops = LoopInfo::new(data, &dom);
+        let alias = AliasAnalysis::new(data);
+        let param_bb: HashMap<Value, BasicBlock> = data
+            .layout()
+            .bbs()
+            .keys()
+            .flat_map(|bb| data.dfg().bb(*bb).params().iter().map(move |p| (*p, *bb)))
+            .collect();
+        for l in loops.loops.iter() {
+            let mut hoister = Hoister {
+                func: data,
+                l,
+                blocks: l.blocks.iter().copied().collect(),
+                param_bb: &param_bb,
+                alias: &alias,
+            };
+            changed |= hoister.hoist(&dom);
+        }
+        changed
+    }
+}
+
+struct Hoister<'a> {
+    func: &'a mut FunctionData,
+    l: &'a Loop,
+    blocks: HashSet<BasicBlock>,
+    param_bb: &'a HashMap<Value, BasicBlock>,
+    alias: &'a AliasAnalysis,
+}
+
+impl Hoister<'_> {
+    fn hoist(&mut self, dom: &DomTree) -> bool {
+        let preheader = match self.l.preheader {
+            Some(preheader) => preheader,
+            None => return false,
+        };
+        let order: Vec<BasicBlock> = dom.preorder().into_iter().filter(|bb| self.blocks.contains(bb)).collect();
+
+        // what the loop writes, and the blocks a load must dominate to run
+        // on every iteration
+        let mut stores = Vec::new();
+        let mut calls = false;
+        for bb in order.iter() {
+            for inst in self.func.layout().bbs().node(bb).unwrap().insts().keys() {
+                match self.func.dfg().value(*inst).kind() {
+                    ValueKind::Store(s) => stores.push(s.dest()),
+                    ValueKind::Call(_) => calls = true,
+                    _ => {}
+                }
+            }
+        }
+        let leaving: Vec<BasicBlock> = order
+            .iter()
+            .copied()
+            .filter(|bb| {
+                let succs = successors(self.func, *bb);
+                succs.is_empty() || succs.iter().any(|s| !self.blocks.contains(s)) || self.l.latches.contains(bb)
+            })
+            .collect();
+
+        // in dominator order, operands move before their users
+        let mut changed = false;
+        for bb in order {
+            let insts: Vec<Value> = self.func.layout().bbs().node(&bb).unwrap().insts().keys().copied().collect();
+            for inst in insts {
+                let movable = match self.func.dfg().value(inst).kind() {
+                    ValueKind::Binary(_) | ValueKind::GetPtr(_) | ValueKind::GetElemPtr(_) => true,
+                    ValueKind::Load(l) => {
+                        let src = l.src();
+                        let func = &*self.func;
+                        let written = stores.iter().any(|dest| self.alias.may_alias(func, src, *dest))
+                            || (calls && self.alias.call_may_write(func, src));
+                        !written && (leaving.iter().all(|b| dom.dominates(bb, *b)) || is_valid(func, self.alias, src))
+                    }
+                    _ => false,
+                };
+                if movable && self.is_invariant(inst) {
+                    let term = *self.func.layout().bbs().node(&preheader).unwrap().insts().back_key().unwrap();
+                    self.func.layout_mut().bb_mut(bb).insts_mut().remove(&inst);
+                    self.func.layout_mut().bb_mut(preheader).insts_mut().cursor_mut(term).insert_key_before(inst).unwrap();
+                    changed = true;
+                }
+            }
+        }
+        changed
+    }
+
+    /// Whether every operand of `inst` is defined outside the loop.
+    fn is_invariant(&self, inst: Value) -> bool {
+        self.func.dfg().value(inst).kind().value_uses().all(|v| {
+            if v.is_global() {
+                return true;
+            }
+            let bb = self.func.layout().parent_bb(v).or_else(|| self.param_bb.get(&v).copied());
+            bb.is_none_or(|bb| !self.blocks.contains(&bb))
+        })
+    }
+}
+
+/// Whether `ptr` surely points into an object, so that loading from it
+/// cannot fault wherever it is.
+fn is_valid(func: &FunctionData, alias: &AliasAnalysis, ptr: Value) -> bool {
+    if ptr.is_global() {
+        return true;
+    }
+    match func.dfg().value(ptr).kind() {
+        ValueKind::Alloc(_) => true,
+        ValueKind::GetElemPtr(p) if !p.src().is_global() && matches!(alias.base(func, ptr), Base::Local(_)) => {
+            let len = match func.dfg().value(p.src()).ty().kind() {
+                TypeKind::Pointer(base) => match base.kind() {
+                    TypeKind::Array(_, len) => *len,
+                    _ => return false,
+                },
+                _ => return false,
+            };
+            let index = match func.dfg().value(p.index()).kind() {
+                ValueKind::Integer(n) => n.value(),
+                _ => return false,
+            };
+            (0..len as i64).contains(&(index as i64)) && is_valid(func, alias, p.src())
+        }
+        _ => false,
+    }
+}
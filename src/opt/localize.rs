@@ -0,0 +1,175 @@
+use crate::analysis::callgraph::CallGraph;
+use crate::analysis::dataflow::{solve, Dataflow, Direction};
+use crate::analysis::dominators::DomTree;
+use crate::analysis::loops::LoopInfo;
+use crate::opt::utils::map_operands;
+use crate::opt::ModulePass;
+use koopa::ir::builder_traits::*;
+use koopa::ir::{BasicBlock, Function, FunctionData, Program, Type, TypeKind, Value, ValueKind};
+use std::collections::HashMap;
+
+/// Global localization: an `i32` global only loaded and stored by one
+/// function becomes a local of it, starting from the initial value, so that
+/// `mem2reg` can promote it.
+///
+/// The function must not be recursive, and either run at most once, as
+/// `main` does, or store to the global before every load, so that no call
+/// sees what an earlier one left there. The unused global is left to dead
+/// global elimination.
+pub struct LocalizeGlobals;
+
+impl ModulePass for LocalizeGlobals {
+    fn run_on(&mut self, program: &mut Program) -> bool {
+        let graph = CallGraph::new(program);
+        let mut changed = false;
+        for (global, func) in candidates(program) {
+            if graph.is_recursive(func)
+                || !(runs_once(program, &graph, func) || stored_before_loads(program.func(func), global))
+            {
+                continue;
+            }
+            let init = match program.borrow_value(global).kind() {
+                ValueKind::GlobalAlloc(alloc) => match program.borrow_value(alloc.init()).kind() {
+                    ValueKind::Integer(n) => n.value(),
+                    _ => 0,
+                },
+                _ => unreachable!(),
+            };
+            localize(program.func_mut(func), global, init);
+            changed = true;
+        }
+        changed
+    }
+}
+
+/// The `i32` globals only used as the address of loads and stores, by a
+/// single function, with that function.
+fn candidates(program: &Program) -> Vec<(Value, Function)> {
+    // each global maps to the only function using it, or to `None` once it
+    // cannot be localized
+    let mut users: HashMap<Value, Option<Function>> = HashMap::new();
+    for func in program.func_layout() {
+        let data = program.func(*func);
+        for node in data.layout().bbs().nodes() {
+            for inst in node.insts().keys() {
+                let kind = data.dfg().value(*inst).kind();
+                for v in kind.value_uses().filter(|v| v.is_global()) {
+                    let address = match kind {
+                        ValueKind::Load(_) => true,
+                        ValueKind::Store(s) => s.value() != v,
+                        _ => false,
+                    };
+                    let user = users.entry(v).or_insert(Some(*func));
+                    if !address || *user != Some(*func) {
+                        *user = None;
+                    }
+                }
+            }
+        }
+    }
+    program
+        .inst_layout()
+        .iter()
+        .filter(|global| match program.borrow_value(**global).ty().kind() {
+            TypeKind::Pointer(base) => *base == Type::get_i32(),
+            _ => false,
+        })
+        .filter_map(|global| users.get(global).copied().flatten().map(|func| (*global, func)))
+        .collect()
+}
+
+/// Whether `func` is `main`, or only called once by a function running at
+/// most once, outside of loops.
+fn runs_once(program: &Program, graph: &CallGraph, func: Function) -> bool {
+    if program.func(func).name() == "@main" {
+        return graph.call_sites(func) == 0;
+    }
+    if graph.call_sites(func) != 1 || graph.is_recursive(func) {
+        return false;
+    }
+    for caller in program.func_layout() {
+        let data = program.func(*caller);
+        let call = data.layout().bbs().iter().find_map(|(bb, node)| {
+            let calls = node.insts().keys().any(|inst| {
+                matches!(data.dfg().value(*inst).kind(), ValueKind::Call(c) if c.callee() == func)
+            });
+            calls.then_some(*bb)
+        });
+        if let Some(bb) = call {
+            let loops = LoopInfo::new(data, &DomTree::dominators(data));
+            return loops.depth(bb) == 0 && runs_once(program, graph, *caller);
+        }
+    }
+    false
+}
+
+/// Whether a global has been stored to on every path so far.
+struct Stored {
+    global: Value,
+}
+
+impl Dataflow for Stored {
+    type Fact = bool;
+    const DIRECTION: Direction = Direction::Forward;
+
+    fn boundary(&self, _: &FunctionData) -> bool {
+        false
+    }
+
+    fn initial(&self, _: &FunctionData) -> bool {
+        true
+    }
+
+    fn meet(&self, into: &mut bool, fact: &bool) {
+        *into &= *fact;
+    }
+
+    fn transfer(&self, func: &FunctionData, bb: BasicBlock, stored: &bool) -> bool {
+        *stored || func.layout().bbs().node(&bb).unwrap().insts().keys().any(|inst| self.stores(func, *inst))
+    }
+}
+
+impl Stored {
+    fn stores(&self, func: &FunctionData, inst: Value) -> bool {
+        matches!(func.dfg().value(inst).kind(), ValueKind::Store(s) if s.dest() == self.global)
+    }
+}
+
+/// Whether every load of `func` from `global` comes after a store to it on
+/// every path from the entry.
+fn stored_before_loads(func: &FunctionData, global: Value) -> bool {
+    let problem = Stored { global };
+    let solution = solve(func, &problem);
+    func.layout().bbs().iter().all(|(bb, node)| {
+        let mut stored = solution.entry[bb];
+        node.insts().keys().all(|inst| {
+            stored |= problem.stores(func, *inst);
+            stored || !matches!(func.dfg().value(*inst).kind(), ValueKind::Load(l) if l.src() == global)
+        })
+    })
+}
+
+/// Makes the loads and stores of `func` to `global` go to a new local,
+/// holding `init` on entry.
+fn localize(func: &mut FunctionData, global: Value, init: i32) {
+    let users: Vec<Value> = func
+        .layout()
+        .bbs()
+        .nodes()
+        .flat_map(|node| node.insts().keys())
+        .copied()
+        .filter(|inst| func.dfg().value(*inst).kind().value_uses().any(|v| v == global))
+        .collect();
+    let alloc = func.dfg_mut().new_value().alloc(Type::get_i32());
+    let init = func.dfg_mut().new_value().integer(init);
+    let store = func.dfg_mut().new_value().store(init, alloc);
+    let entry = func.layout().entry_bb().unwrap();
+    let insts = func.layout_mut().bb_mut(entry).insts_mut();
+    insts.push_key_front(store).unwrap();
+    insts.push_key_front(alloc).unwrap();
+    for user in users {
+        let mut data = func.dfg().value(user).clone();
+        map_operands(data.kind_mut(), |v| if v == global { alloc } else { v });
+        func.dfg_mut().replace_value_with(user).raw(data);
+    }
+}
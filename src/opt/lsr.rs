@@ -0,0 +1,251 @@
+use crate::analysis::dominators::DomTree;
+use crate::analysis::induction::{derived_vars, induction_vars, InductionVar};
+use crate::analysis::loops::{Loop, LoopInfo};
+use crate::opt::utils::{add_bb_params, ensure_preheaders, for_each_edge_args, map_operands, remove_inst, replace_all_uses};
+use crate::opt::FunctionPass;
+use koopa::ir::builder_traits::*;
+use koopa::ir::{BasicBlock, BinaryOp, Function, FunctionData, TypeKind, Value, ValueKind};
+use std::collections::{HashMap, HashSet};
+
+/// Loop strength reduction: a product, or an address indexed by an
+/// induction variable, computed on every iteration becomes a parameter of
+/// the loop header, starting from its value on entering the loop and
+/// increased by a constant or invariant step on each back edge, so that the
+/// loop adds where it used to multiply.
+///
+/// Sums, differences and shifts are left alone, as they cost no more than
+/// the increments replacing them, but the products they are computed from
+/// are reduced.
+pub struct LoopStrengthReduction;
+
+impl FunctionPass for LoopStrengthReduction {
+    fn run_on(&mut self, _func: Function, data: &mut FunctionData) -> bool {
+        if data.layout().entry_bb().is_none() {
+            return false;
+        }
+        let mut changed = ensure_preheaders(data);
+        let dom = DomTree::dominators(data);
+        let loops = LoopInfo::new(data, &dom);
+        for l in loops.loops.iter() {
+            let ivs = induction_vars(data, l);
+            if ivs.is_empty() {
+                continue;
+            }
+            let order: Vec<BasicBlock> = dom.preorder().into_iter().filter(|bb| l.contains(*bb)).collect();
+            let mut reducer = Reducer {
+                func: data,
+                l,
+                vars: HashSet::new(),
+                preheader: l.preheader.unwrap(),
+                latch: l.latches[0],
+                entry: ivs.iter().map(|iv| (iv.param, iv.init)).collect(),
+                steps: ivs.iter().map(|iv| (iv.param, Step::Const(iv.step))).collect(),
+            };
+            changed |= reducer.reduce(&ivs, &order);
+        }
+        changed
+    }
+}
+
+/// What an induction variable is increased by on each iteration, in
+/// elements of the pointee for addresses.
+#[derive(Clone, Copy)]
+enum Step {
+    Const(i32),
+    /// a value computed in the preheader
+    Value(Value),
+}
+
+struct Reducer<'a> {
+    func: &'a mut FunctionData,
+    l: &'a Loop,
+    /// the induction variables, basic and derived
+    vars: HashSet<Value>,
+    preheader: BasicBlock,
+    latch: BasicBlock,
+    /// the value of each variable on entering the loop, computed in the
+    /// preheader
+    entry: HashMap<Value, Value>,
+    steps: HashMap<Value, Step>,
+}
+
+impl Reducer<'_> {
+    fn reduce(&mut self, ivs: &[InductionVar], order: &[BasicBlock]) -> bool {
+        let derived = derived_vars(self.func, self.l, ivs, order);
+        self.vars = ivs.iter().map(|iv| iv.param).chain(derived.iter().map(|var| var.value)).collect();
+
+        // users come after what they are derived from, so going backwards
+        // each variable knows whether its users are reduced already, or left
+        // unused by those
+        let mut reduced: HashSet<Value> = HashSet::new();
+        let mut unused: HashSet<Value> = HashSet::new();
+        for var in derived.iter().rev() {
+            let costly = match self.func.dfg().value(var.value).kind() {
+                ValueKind::Binary(b) => b.op() == BinaryOp::Mul,
+                ValueKind::GetPtr(p) => !self.vars.contains(&p.src()),
+                ValueKind::GetElemPtr(p) => !self.vars.contains(&p.src()),
+                _ => false,
+            };
+            let used = self.func.dfg().value(var.value).used_by().iter().any(|u| !reduced.contains(u) && !unused.contains(u));
+            if !used {
+                unused.insert(var.value);
+            } else if costly {
+                reduced.insert(var.value);
+            }
+        }
+        if reduced.is_empty() {
+            return false;
+        }
+
+        // what the reduced variables start from and step by, before any of
+        // them is replaced
+        let mut recurrences = Vec::new();
+        for var in derived.iter().filter(|var| reduced.contains(&var.value)) {
+            let init = self.entry_value(var.value);
+            let step = self.step(var.value);
+            recurrences.push((var.value, init, step));
+        }
+        for (value, init, step) in recurrences {
+            let ty = self.func.dfg().value(value).ty().clone();
+            let pointer = matches!(ty.kind(), TypeKind::Pointer(_));
+            let param = add_bb_params(self.func, self.l.header, vec![ty])[0];
+            let step = self.materialize(step);
+            let next = if pointer {
+                self.func.dfg_mut().new_value().get_ptr(param, step)
+            } else {
+                self.func.dfg_mut().new_value().binary(BinaryOp::Add, param, step)
+            };
+            let term = self.terminator(self.latch);
+            self.func.layout_mut().bb_mut(self.latch).insts_mut().cursor_mut(term).insert_key_before(next).unwrap();
+            self.pass_arg(self.preheader, init);
+            self.pass_arg(self.latch, next);
+            replace_all_uses(self.func.dfg_mut(), value, param);
+            remove_inst(self.func, value);
+        }
+
+        // what the reduced variables were computed from is left unused
+        for var in derived.iter().rev().filter(|var| unused.contains(&var.value)) {
+            if self.func.dfg().value(var.value).used_by().is_empty() {
+                remove_inst(self.func, var.value);
+            }
+        }
+        true
+    }
+
+    /// The value of a variable on entering the loop, computing it in the
+    /// preheader from the entry values of what it is derived from.
+    fn entry_value(&mut self, value: Value) -> Value {
+        if let Some(entry) = self.entry.get(&value) {
+            return *entry;
+        }
+        let mut data = self.func.dfg().value(value).clone();
+        let operands: Vec<Value> = data.kind().value_uses().collect();
+        let mut entries = HashMap::new();
+        for v in operands {
+            if self.vars.contains(&v) {
+                entries.insert(v, self.entry_value(v));
+            }
+        }
+        map_operands(data.kind_mut(), |v| entries.get(&v).copied().unwrap_or(v));
+        let entry = self.func.dfg_mut().new_value().raw(data);
+        self.func.dfg_mut().set_value_name(entry, None);
+        self.insert_before_end(self.preheader, entry);
+        self.entry.insert(value, entry);
+        entry
+    }
+
+    /// The step of a derived variable, from the step of the variable it is
+    /// derived through.
+    fn step(&mut self, value: Value) -> Step {
+        if let Some(step) = self.steps.get(&value) {
+            return *step;
+        }
+        let step = match self.func.dfg().value(value).kind().clone() {
+            ValueKind::Binary(b) => {
+                let (var, other, var_is_lhs) = if self.vars.contains(&b.lhs()) {
+                    (b.lhs(), b.rhs(), true)
+                } else {
+                    (b.rhs(), b.lhs(), false)
+                };
+                let step = self.step(var);
+                match b.op() {
+                    BinaryOp::Sub if !var_is_lhs => self.combine(BinaryOp::Sub, Step::Const(0), step),
+                    BinaryOp::Add | BinaryOp::Sub => step,
+                    BinaryOp::Mul => self.combine(BinaryOp::Mul, step, self.operand(other)),
+                    BinaryOp::Shl => self.combine(BinaryOp::Shl, step, self.operand(other)),
+                    _ => unreachable!(),
+                }
+            }
+            ValueKind::GetPtr(p) if self.vars.contains(&p.src()) => self.step(p.src()),
+            ValueKind::GetElemPtr(p) if self.vars.contains(&p.src()) => {
+                // stepping the array pointer steps its elements by its length
+                let len = match self.func.dfg().value(p.src()).ty().kind() {
+                    TypeKind::Pointer(base) => match base.kind() {
+                        TypeKind::Array(_, len) => *len as i32,
+                        _ => unreachable!(),
+                    },
+                    _ => unreachable!(),
+                };
+                let step = self.step(p.src());
+                self.combine(BinaryOp::Mul, step, Step::Const(len))
+            }
+            ValueKind::GetPtr(p) => self.step(p.index()),
+            ValueKind::GetElemPtr(p) => self.step(p.index()),
+            _ => unreachable!(),
+        };
+        self.steps.insert(value, step);
+        step
+    }
+
+    fn operand(&self, value: Value) -> Step {
+        match self.func.dfg().value(value).kind() {
+            ValueKind::Integer(n) => Step::Const(n.value()),
+            _ => Step::Value(value),
+        }
+    }
+
+    /// `lhs op rhs`, folded when both are constant, or else computed in the
+    /// preheader.
+    fn combine(&mut self, op: BinaryOp, lhs: Step, rhs: Step) -> Step {
+        match (lhs, rhs) {
+            (Step::Const(1), other) | (other, Step::Const(1)) if op == BinaryOp::Mul => other,
+            (Step::Const(l), Step::Const(r)) => Step::Const(match op {
+                BinaryOp::Sub => l.wrapping_sub(r),
+                BinaryOp::Mul => l.wrapping_mul(r),
+                BinaryOp::Shl => l.wrapping_shl(r as u32),
+                _ => unreachable!(),
+            }),
+            _ => {
+                let lhs = self.materialize(lhs);
+                let rhs = self.materialize(rhs);
+                let step = self.func.dfg_mut().new_value().binary(op, lhs, rhs);
+                self.insert_before_end(self.preheader, step);
+                Step::Value(step)
+            }
+        }
+    }
+
+    fn materialize(&mut self, step: Step) -> Value {
+        match step {
+            Step::Const(n) => self.func.dfg_mut().new_value().integer(n),
+            Step::Value(value) => value,
+        }
+    }
+
+    /// Passes `arg` on to one more parameter of the header from `bb`.
+    fn pass_arg(&mut self, bb: BasicBlock, arg: Value) {
+        let term = self.terminator(bb);
+        let mut data = self.func.dfg().value(term).clone();
+        for_each_edge_args(data.kind_mut(), self.l.header, |args| args.push(arg));
+        self.func.dfg_mut().replace_value_with(term).raw(data);
+    }
+
+    fn insert_before_end(&mut self, bb: BasicBlock, inst: Value) {
+        let term = self.terminator(bb);
+        self.func.layout_mut().bb_mut(bb).insts_mut().cursor_mut(term).insert_key_before(inst).unwrap();
+    }
+
+    fn terminator(&self, bb: BasicBlock) -> Value {
+        *self.func.layout().bbs().node(&bb).unwrap().insts().back_key().unwrap()
+    }
+}
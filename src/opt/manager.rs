@@ -0,0 +1,135 @@
+use crate::opt::verifier::verify;
+use crate::opt::{create, pass_names, pipeline, Pass};
+use crate::session::Options;
+use koopa::back::KoopaGenerator;
+use koopa::ir::Program;
+
+/// Runs a sequence of named passes over a program.
+#[derive(Default)]
+pub struct PassManager {
+    passes: Vec<(&'static str, Pass)>,
+    /// passes after which to print the program, all of them when `None`
+    print_after: Option<Vec<String>>,
+    print_changed: bool,
+    bisect: OptBisect,
+    verify: bool,
+}
+
+/// Counts pass applications against `-opt-bisect-limit`, logging each one
+/// to stderr so that the one breaking a program can be found by bisection.
+#[derive(Default)]
+struct OptBisect {
+    limit: Option<usize>,
+    count: usize,
+}
+
+impl OptBisect {
+    /// Whether the next application, of `pass` on `target`, may run.
+    fn should_run(&mut self, pass: &str, target: &str) -> bool {
+        let limit = match self.limit {
+            Some(limit) => limit,
+            None => return true,
+        };
+        self.count += 1;
+        let run = self.count <= limit;
+        eprintln!(
+            "BISECT: {}running pass ({}) {} on {}",
+            if run { "" } else { "NOT " },
+            self.count,
+            pass,
+            target
+        );
+        run
+    }
+}
+
+impl PassManager {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The pipeline asked for on the command line: the `-passes` list if
+    /// given, the passes of the `-O` level otherwise.
+    pub fn from_options(options: &Options) -> Result<Self, String> {
+        let mut manager = match &options.passes {
+            Some(names) => Self::with_names(names.iter().map(|n| n.as_str()), options)?,
+            None => Self::with_names(pipeline(options.opt_level), options)?,
+        };
+        for name in options.print_after.iter() {
+            if !pass_names().any(|n| n == name) {
+                return Err(format!("unknown pass `{}` in -print-after", name));
+            }
+        }
+        if options.print_after_all || (options.print_changed && options.print_after.is_empty()) {
+            manager.print_after = None;
+        } else {
+            manager.print_after = Some(options.print_after.clone());
+        }
+        manager.print_changed = options.print_changed;
+        manager.bisect.limit = options.opt_bisect_limit;
+        manager.verify = options.verify;
+        Ok(manager)
+    }
+
+    /// A pipeline of the registered passes `names`, in order, configured by
+    /// `options`.
+    pub fn with_names<'a>(names: impl IntoIterator<Item = &'a str>, options: &Options) -> Result<Self, String> {
+        let mut manager = Self::new();
+        for name in names {
+            // the registry hands out the 'static copy of the name
+            let name = pass_names()
+                .find(|n| *n == name)
+                .ok_or_else(|| format!("unknown pass `{}`", name))?;
+            manager.add(name, create(name, options).unwrap());
+        }
+        Ok(manager)
+    }
+
+    pub fn add(&mut self, name: &'static str, pass: Pass) {
+        self.passes.push((name, pass));
+    }
+
+    /// Runs every pass in order. Function passes visit the function
+    /// definitions in layout order, skipping declarations. Each run of a
+    /// module pass, or of a function pass on one function, counts as one
+    /// application for `-opt-bisect-limit`.
+    ///
+    /// With `-verify`, stops at the first pass leaving the IR broken.
+    pub fn run(&mut self, program: &mut Program) -> Result<(), String> {
+        for (name, pass) in self.passes.iter_mut() {
+            let changed = match pass {
+                Pass::Module(pass) => self.bisect.should_run(name, "module") && pass.run_on(program),
+                Pass::Function(pass) => {
+                    let mut changed = false;
+                    let funcs: Vec<_> = program.func_layout().to_vec();
+                    for func in funcs {
+                        let data = program.func_mut(func);
+                        if data.layout().entry_bb().is_some() && self.bisect.should_run(name, data.name()) {
+                            changed |= pass.run_on(func, data);
+                        }
+                    }
+                    changed
+                }
+            };
+            let selected = match &self.print_after {
+                Some(names) => names.iter().any(|n| n == name),
+                None => true,
+            };
+            if selected && (changed || !self.print_changed) {
+                print_program(name, program);
+            }
+            if self.verify {
+                verify(program).map_err(|errors| format!("broken IR after {}:\n{}", name, errors.join("\n")))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Dumps `program` as Koopa text to stderr, headed by the pass just run.
+fn print_program(pass: &str, program: &Program) {
+    let mut gen = KoopaGenerator::new(Vec::new());
+    gen.generate_on(program).unwrap();
+    eprintln!("// *** IR Dump After {} ***", pass);
+    eprint!("{}", std::str::from_utf8(&gen.writer()).unwrap());
+}
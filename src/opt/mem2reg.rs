@@ -0,0 +1,183 @@
+use crate::analysis::cfg::{predecessors, successors};
+use crate::analysis::dominators::DomTree;
+use crate::opt::utils::{add_bb_params, for_each_edge_args, remove_inst, remove_unreachable_bbs, replace_all_uses};
+use crate::opt::FunctionPass;
+use koopa::ir::builder_traits::*;
+use koopa::ir::{BasicBlock, Function, FunctionData, Type, TypeKind, Value, ValueKind};
+use std::collections::{HashMap, HashSet};
+
+/// Promotes local variables to SSA values: every scalar `alloc` that is
+/// only loaded from and stored to is replaced by the values stored into
+/// it, with basic block parameters where those values merge.
+///
+/// Parameters are only placed where the variable is live, and a variable
+/// read before any store reads `undef`.
+pub struct Mem2Reg;
+
+impl FunctionPass for Mem2Reg {
+    fn run_on(&mut self, _func: Function, data: &mut FunctionData) -> bool {
+        // renaming walks the dominator tree, which only covers reachable code
+        let changed = remove_unreachable_bbs(data);
+        let allocs = promotable(data);
+        if allocs.is_empty() {
+            return changed;
+        }
+        let index: HashMap<Value, usize> = allocs.iter().enumerate().map(|(i, a)| (*a, i)).collect();
+        let tys: Vec<Type> = allocs
+            .iter()
+            .map(|a| match data.dfg().value(*a).ty().kind() {
+                TypeKind::Pointer(base) => base.clone(),
+                _ => unreachable!(),
+            })
+            .collect();
+
+        let dom = DomTree::dominators(data);
+        let params = place_params(data, &dom, &index);
+        let mut block_params: HashMap<BasicBlock, Vec<(usize, Value)>> = HashMap::new();
+        for (bb, vars) in params {
+            let values = add_bb_params(data, bb, vars.iter().map(|v| tys[*v].clone()).collect());
+            block_params.insert(bb, vars.into_iter().zip(values).collect());
+        }
+
+        // rename along the dominator tree, each block starting from the
+        // values current at the end of its immediate dominator
+        let undef: Vec<Value> = tys.iter().map(|ty| data.dfg_mut().new_value().undef(ty.clone())).collect();
+        let entry = data.layout().entry_bb().unwrap();
+        let mut stack = vec![(entry, undef)];
+        while let Some((bb, mut current)) = stack.pop() {
+            for (var, param) in block_params.get(&bb).into_iter().flatten() {
+                current[*var] = *param;
+            }
+            let insts: Vec<Value> = data.layout().bbs().node(&bb).unwrap().insts().keys().copied().collect();
+            for inst in insts {
+                match data.dfg().value(inst).kind() {
+                    ValueKind::Load(l) if index.contains_key(&l.src()) => {
+                        let value = current[index[&l.src()]];
+                        replace_all_uses(data.dfg_mut(), inst, value);
+                        remove_inst(data, inst);
+                    }
+                    ValueKind::Store(s) if index.contains_key(&s.dest()) => {
+                        current[index[&s.dest()]] = s.value();
+                        remove_inst(data, inst);
+                    }
+                    _ => {}
+                }
+            }
+
+            let term = *data.layout().bbs().node(&bb).unwrap().insts().back_key().unwrap();
+            let mut succs = successors(data, bb);
+            succs.dedup();
+            for succ in succs {
+                if let Some(params) = block_params.get(&succ) {
+                    let args: Vec<Value> = params.iter().map(|(var, _)| current[*var]).collect();
+                    let mut term_data = data.dfg().value(term).clone();
+                    for_each_edge_args(term_data.kind_mut(), succ, |edge| edge.extend(args.iter().copied()));
+                    data.dfg_mut().replace_value_with(term).raw(term_data);
+                }
+            }
+            for child in dom.children(bb) {
+                stack.push((*child, current.clone()));
+            }
+        }
+
+        for alloc in allocs {
+            remove_inst(data, alloc);
+        }
+        true
+    }
+}
+
+/// The allocs of `func` holding a single integer or pointer whose address
+/// is only ever loaded from or stored to, in layout order.
+fn promotable(func: &FunctionData) -> Vec<Value> {
+    let mut allocs = Vec::new();
+    for (_, node) in func.layout().bbs() {
+        for inst in node.insts().keys() {
+            let data = func.dfg().value(*inst);
+            if !matches!(data.kind(), ValueKind::Alloc(_)) {
+                continue;
+            }
+            let scalar = match data.ty().kind() {
+                TypeKind::Pointer(base) => matches!(base.kind(), TypeKind::Int32 | TypeKind::Pointer(_)),
+                _ => false,
+            };
+            let only_accessed = data.used_by().iter().all(|user| match func.dfg().value(*user).kind() {
+                ValueKind::Load(_) => true,
+                ValueKind::Store(s) => s.dest() == *inst && s.value() != *inst,
+                _ => false,
+            });
+            if scalar && only_accessed {
+                allocs.push(*inst);
+            }
+        }
+    }
+    allocs
+}
+
+/// The variables each block needs a parameter for: those live into a block
+/// of the iterated dominance frontier of their stores.
+fn place_params(
+    func: &FunctionData,
+    dom: &DomTree,
+    index: &HashMap<Value, usize>,
+) -> Vec<(BasicBlock, Vec<usize>)> {
+    let n = index.len();
+    // blocks storing each variable, and those reading it before any store
+    let mut defs: Vec<HashSet<BasicBlock>> = vec![HashSet::new(); n];
+    let mut uses: Vec<HashSet<BasicBlock>> = vec![HashSet::new(); n];
+    for (bb, node) in func.layout().bbs() {
+        for inst in node.insts().keys() {
+            match func.dfg().value(*inst).kind() {
+                ValueKind::Load(l) if index.contains_key(&l.src()) => {
+                    let var = index[&l.src()];
+                    if !defs[var].contains(bb) {
+                        uses[var].insert(*bb);
+                    }
+                }
+                ValueKind::Store(s) if index.contains_key(&s.dest()) => {
+                    defs[index[&s.dest()]].insert(*bb);
+                }
+                _ => {}
+            }
+        }
+    }
+
+    let preds = predecessors(func);
+    let frontiers = dom.frontiers();
+    let mut params: HashMap<BasicBlock, Vec<usize>> = HashMap::new();
+    for var in 0..n {
+        // live in: reached backwards from an upward exposed use without
+        // going through a store
+        let mut live_in: HashSet<BasicBlock> = HashSet::new();
+        let mut worklist: Vec<BasicBlock> = uses[var].iter().copied().collect();
+        while let Some(bb) = worklist.pop() {
+            if live_in.insert(bb) {
+                worklist.extend(preds[&bb].iter().copied().filter(|p| !defs[var].contains(p)));
+            }
+        }
+
+        let mut placed: HashSet<BasicBlock> = HashSet::new();
+        let mut worklist: Vec<BasicBlock> = defs[var].iter().copied().collect();
+        while let Some(bb) = worklist.pop() {
+            for f in frontiers[&bb].iter() {
+                if live_in.contains(f) && placed.insert(*f) {
+                    params.entry(*f).or_default().push(var);
+                    worklist.push(*f);
+                }
+            }
+        }
+    }
+
+    // in layout order, each block's variables in order, so that the output
+    // does not depend on hashing
+    func.layout()
+        .bbs()
+        .keys()
+        .filter_map(|bb| {
+            params.remove(bb).map(|mut vars| {
+                vars.sort_unstable();
+                (*bb, vars)
+            })
+        })
+        .collect()
+}
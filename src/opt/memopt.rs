@@ -0,0 +1,207 @@
+use crate::analysis::alias::{AliasAnalysis, Base};
+use crate::analysis::dataflow::{solve, Dataflow, Direction};
+use crate::opt::utils::{remove_inst, replace_all_uses};
+use crate::opt::FunctionPass;
+use koopa::ir::{BasicBlock, Function, FunctionData, Value, ValueKind};
+use std::collections::{HashMap, HashSet};
+
+/// Store-to-load forwarding and dead store elimination, for the memory
+/// `mem2reg` leaves alone: arrays, locals whose address escapes, globals.
+///
+/// A load from an address gets the value last stored there, or loaded from
+/// there, when every path to it leaves the same one with nothing that may
+/// write the address since. A store is removed when every path from it
+/// stores to the address again before anything may read it, or returns
+/// when it writes a local.
+pub struct MemoryOptimization;
+
+impl FunctionPass for MemoryOptimization {
+    fn run_on(&mut self, _func: Function, data: &mut FunctionData) -> bool {
+        if data.layout().entry_bb().is_none() {
+            return false;
+        }
+        let alias = AliasAnalysis::new(data);
+        let forwarded = forward_stores(data, &alias);
+        let removed = remove_dead_stores(data, &alias);
+        forwarded || removed
+    }
+}
+
+/// What each address is known to hold, or `None` where nothing has been
+/// found out yet, as for blocks not reached so far.
+type Contents = Option<HashMap<Value, Value>>;
+
+/// The values addresses hold, as a forward dataflow problem: facts only
+/// hold at a join if they hold on every incoming edge.
+struct AvailableValues<'a> {
+    alias: &'a AliasAnalysis,
+}
+
+impl AvailableValues<'_> {
+    /// Updates `contents` past `inst`.
+    fn step(&self, func: &FunctionData, inst: Value, contents: &mut HashMap<Value, Value>) {
+        match func.dfg().value(inst).kind() {
+            ValueKind::Store(s) => {
+                contents.retain(|ptr, _| !self.alias.may_alias(func, *ptr, s.dest()));
+                contents.insert(s.dest(), s.value());
+            }
+            ValueKind::Load(l) => {
+                contents.entry(l.src()).or_insert(inst);
+            }
+            ValueKind::Call(_) => contents.retain(|ptr, _| !self.alias.call_may_write(func, *ptr)),
+            _ => {}
+        }
+    }
+}
+
+impl Dataflow for AvailableValues<'_> {
+    type Fact = Contents;
+    const DIRECTION: Direction = Direction::Forward;
+
+    fn boundary(&self, _: &FunctionData) -> Self::Fact {
+        Some(HashMap::new())
+    }
+
+    fn initial(&self, _: &FunctionData) -> Self::Fact {
+        None
+    }
+
+    fn meet(&self, into: &mut Self::Fact, fact: &Self::Fact) {
+        match (into.as_mut(), fact) {
+            (_, None) => {}
+            (None, Some(fact)) => *into = Some(fact.clone()),
+            (Some(into), Some(fact)) => into.retain(|ptr, value| fact.get(ptr) == Some(value)),
+        }
+    }
+
+    fn transfer(&self, func: &FunctionData, bb: BasicBlock, fact: &Self::Fact) -> Self::Fact {
+        let mut contents = fact.clone()?;
+        for inst in func.layout().bbs().node(&bb).unwrap().insts().keys() {
+            self.step(func, *inst, &mut contents);
+        }
+        Some(contents)
+    }
+}
+
+/// Replaces loads by the value their address is known to hold. As that
+/// value got there on every path to the load, its definition dominates it.
+fn forward_stores(func: &mut FunctionData, alias: &AliasAnalysis) -> bool {
+    let problem = AvailableValues { alias };
+    let solution = solve(func, &problem);
+    // the loads removed so far, with what replaced them, which the facts of
+    // the blocks still to go may name
+    let mut replaced: HashMap<Value, Value> = HashMap::new();
+    let bbs: Vec<BasicBlock> = func.layout().bbs().keys().copied().collect();
+    for bb in bbs {
+        let mut contents = match solution.entry[&bb].clone() {
+            Some(contents) => contents,
+            None => continue,
+        };
+        let insts: Vec<Value> = func.layout().bbs().node(&bb).unwrap().insts().keys().copied().collect();
+        for inst in insts {
+            let known = match func.dfg().value(inst).kind() {
+                ValueKind::Load(l) => contents.get(&l.src()).copied(),
+                _ => None,
+            };
+            match known {
+                Some(mut value) => {
+                    while let Some(v) = replaced.get(&value) {
+                        value = *v;
+                    }
+                    replace_all_uses(func.dfg_mut(), inst, value);
+                    remove_inst(func, inst);
+                    replaced.insert(inst, value);
+                }
+                None => problem.step(func, inst, &mut contents),
+            }
+        }
+    }
+    !replaced.is_empty()
+}
+
+/// The addresses written again on every path before anything may read
+/// them, as a backward dataflow problem, or `None` where nothing has been
+/// found out yet.
+struct OverwrittenAddresses<'a> {
+    alias: &'a AliasAnalysis,
+    /// the addresses stored to that point into locals, which are gone once
+    /// the function returns
+    locals: HashSet<Value>,
+}
+
+impl OverwrittenAddresses<'_> {
+    /// Updates `overwritten` back past `inst`, returning whether `inst` is
+    /// a store to an address in it.
+    fn step(&self, func: &FunctionData, inst: Value, overwritten: &mut HashSet<Value>) -> bool {
+        match func.dfg().value(inst).kind() {
+            ValueKind::Return(_) => *overwritten = self.locals.clone(),
+            ValueKind::Store(s) => return !overwritten.insert(s.dest()),
+            ValueKind::Load(l) => overwritten.retain(|ptr| !self.alias.may_alias(func, *ptr, l.src())),
+            ValueKind::Call(_) => overwritten.retain(|ptr| !self.alias.call_may_write(func, *ptr)),
+            _ => {}
+        }
+        false
+    }
+}
+
+impl Dataflow for OverwrittenAddresses<'_> {
+    type Fact = Option<HashSet<Value>>;
+    const DIRECTION: Direction = Direction::Backward;
+
+    fn boundary(&self, _: &FunctionData) -> Self::Fact {
+        Some(HashSet::new())
+    }
+
+    fn initial(&self, _: &FunctionData) -> Self::Fact {
+        None
+    }
+
+    fn meet(&self, into: &mut Self::Fact, fact: &Self::Fact) {
+        match (into.as_mut(), fact) {
+            (_, None) => {}
+            (None, Some(fact)) => *into = Some(fact.clone()),
+            (Some(into), Some(fact)) => into.retain(|ptr| fact.contains(ptr)),
+        }
+    }
+
+    fn transfer(&self, func: &FunctionData, bb: BasicBlock, fact: &Self::Fact) -> Self::Fact {
+        let mut overwritten = fact.clone()?;
+        let insts: Vec<Value> = func.layout().bbs().node(&bb).unwrap().insts().keys().copied().collect();
+        for inst in insts.into_iter().rev() {
+            self.step(func, inst, &mut overwritten);
+        }
+        Some(overwritten)
+    }
+}
+
+/// Removes the stores whose value is never read.
+fn remove_dead_stores(func: &mut FunctionData, alias: &AliasAnalysis) -> bool {
+    let locals: HashSet<Value> = func
+        .layout()
+        .bbs()
+        .nodes()
+        .flat_map(|node| node.insts().keys())
+        .filter_map(|inst| match func.dfg().value(*inst).kind() {
+            ValueKind::Store(s) if matches!(alias.base(func, s.dest()), Base::Local(_)) => Some(s.dest()),
+            _ => None,
+        })
+        .collect();
+    let problem = OverwrittenAddresses { alias, locals };
+    let solution = solve(func, &problem);
+    let mut dead = Vec::new();
+    for (bb, node) in func.layout().bbs() {
+        let mut overwritten = match solution.exit[bb].clone() {
+            Some(overwritten) => overwritten,
+            None => continue,
+        };
+        for inst in node.insts().keys().copied().collect::<Vec<_>>().into_iter().rev() {
+            if problem.step(func, inst, &mut overwritten) {
+                dead.push(inst);
+            }
+        }
+    }
+    for inst in dead.iter() {
+        remove_inst(func, *inst);
+    }
+    !dead.is_empty()
+}
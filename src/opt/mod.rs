@@ -0,0 +1,122 @@
+pub mod dce;
+pub mod globaldce;
+pub mod gvn;
+pub mod inline;
+pub mod instcombine;
+pub mod ipcp;
+pub mod licm;
+pub mod localize;
+pub mod lsr;
+pub mod manager;
+pub mod mem2reg;
+pub mod memopt;
+pub mod rce;
+pub mod rotate;
+pub mod sccp;
+pub mod tailrec;
+pub mod unroll;
+pub mod utils;
+pub mod verifier;
+
+use crate::opt::dce::DeadCodeElimination;
+use crate::opt::globaldce::GlobalDeadCodeElimination;
+use crate::opt::gvn::GlobalValueNumbering;
+use crate::opt::inline::Inliner;
+use crate::opt::instcombine::InstCombine;
+use crate::opt::ipcp::InterproceduralConstProp;
+use crate::opt::licm::LoopInvariantCodeMotion;
+use crate::opt::localize::LocalizeGlobals;
+use crate::opt::lsr::LoopStrengthReduction;
+use crate::opt::mem2reg::Mem2Reg;
+use crate::opt::memopt::MemoryOptimization;
+use crate::opt::rce::RedundantCallElimination;
+use crate::opt::rotate::LoopRotation;
+use crate::opt::sccp::SparseConditionalConstantPropagation;
+use crate::opt::tailrec::TailRecursionElimination;
+use crate::opt::unroll::LoopUnroll;
+use crate::session::Options;
+use koopa::ir::{Function, FunctionData, Program};
+
+/// A transformation over the whole program, for work that spans functions.
+pub trait ModulePass {
+    /// Runs the pass, returning whether it changed anything.
+    fn run_on(&mut self, program: &mut Program) -> bool;
+}
+
+/// A transformation over one function definition at a time.
+pub trait FunctionPass {
+    /// Runs the pass on `func`, returning whether it changed anything.
+    fn run_on(&mut self, func: Function, data: &mut FunctionData) -> bool;
+}
+
+pub enum Pass {
+    Module(Box<dyn ModulePass>),
+    Function(Box<dyn FunctionPass>),
+}
+
+type Constructor = fn(&Options) -> Pass;
+
+/// Every pass that `-passes` can name, with how to create it.
+const REGISTRY: &[(&str, Constructor)] = &[
+    ("dce", |_| Pass::Function(Box::new(DeadCodeElimination))),
+    ("globaldce", |_| Pass::Module(Box::new(GlobalDeadCodeElimination))),
+    ("gvn", |_| Pass::Function(Box::new(GlobalValueNumbering))),
+    ("inline", |_| Pass::Module(Box::new(Inliner))),
+    ("instcombine", |_| Pass::Function(Box::new(InstCombine))),
+    ("ipcp", |_| Pass::Module(Box::new(InterproceduralConstProp))),
+    ("licm", |_| Pass::Function(Box::new(LoopInvariantCodeMotion))),
+    ("localize", |_| Pass::Module(Box::new(LocalizeGlobals))),
+    ("lsr", |_| Pass::Function(Box::new(LoopStrengthReduction))),
+    ("mem2reg", |_| Pass::Function(Box::new(Mem2Reg))),
+    ("memopt", |_| Pass::Function(Box::new(MemoryOptimization))),
+    ("rce", |_| Pass::Module(Box::new(RedundantCallElimination))),
+    ("rotate", |_| Pass::Function(Box::new(LoopRotation))),
+    ("sccp", |_| Pass::Function(Box::new(SparseConditionalConstantPropagation))),
+    ("tailrec", |_| Pass::Function(Box::new(TailRecursionElimination))),
+    ("unroll", |o| Pass::Function(Box::new(LoopUnroll::new(o.unroll_factor.unwrap_or(4))))),
+];
+
+/// Creates the pass registered as `name`, configured by `options`.
+pub fn create(name: &str, options: &Options) -> Option<Pass> {
+    REGISTRY.iter().find(|(n, _)| *n == name).map(|(_, new)| new(options))
+}
+
+/// Names of every registered pass.
+pub fn pass_names() -> impl Iterator<Item = &'static str> {
+    REGISTRY.iter().map(|(name, _)| *name)
+}
+
+/// The passes run at optimization level `level`, in order.
+pub fn pipeline(level: u32) -> Vec<&'static str> {
+    match level {
+        0 => vec![],
+        1 => vec!["mem2reg", "sccp", "instcombine", "dce"],
+        _ => vec![
+            "mem2reg",
+            "sccp",
+            "instcombine",
+            "tailrec",
+            "inline",
+            "ipcp",
+            "globaldce",
+            "localize",
+            "mem2reg",
+            "sccp",
+            "instcombine",
+            "gvn",
+            "rce",
+            "memopt",
+            "licm",
+            "unroll",
+            "sccp",
+            "instcombine",
+            "gvn",
+            "lsr",
+            "rotate",
+            "rce",
+            "licm",
+            "dce",
+            "globaldce",
+        ],
+    }
+}
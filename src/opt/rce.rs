@@ -0,0 +1,193 @@
+use crate::analysis::alias::{AliasAnalysis, Base};
+use crate::analysis::cfg::{predecessors, successors};
+use crate::analysis::dominators::DomTree;
+use crate::analysis::effects::{Effect, Effects};
+use crate::analysis::induction::is_invariant;
+use crate::analysis::loops::LoopInfo;
+use crate::opt::utils::{ensure_preheaders, integer, remove_inst, replace_all_uses};
+use crate::opt::ModulePass;
+use koopa::ir::{BasicBlock, Function, FunctionData, Program, Value, ValueKind};
+use std::collections::HashMap;
+
+/// Redundant call elimination, knowing what each function may do: calls
+/// without side effects whose results go unused are removed, a call to a
+/// pure function with the same arguments as one dominating it is replaced
+/// by that one, and pure calls with arguments that do not change in a loop
+/// move to its preheader.
+///
+/// Calls to read-only functions are only reused within an extended basic
+/// block, as loads are by value numbering, and not past anything that may
+/// write what they read.
+pub struct RedundantCallElimination;
+
+impl ModulePass for RedundantCallElimination {
+    fn run_on(&mut self, program: &mut Program) -> bool {
+        let effects = Effects::new(program);
+        let mut changed = false;
+        for func in program.func_layout().to_vec() {
+            let data = program.func_mut(func);
+            if data.layout().entry_bb().is_none() {
+                continue;
+            }
+            changed |= remove_unused_calls(data, &effects);
+            changed |= reuse_calls(data, &effects);
+            changed |= hoist_calls(data, &effects);
+        }
+        changed
+    }
+}
+
+/// The callee of `inst`, if it is a call.
+fn callee(func: &FunctionData, inst: Value) -> Option<Function> {
+    match func.dfg().value(inst).kind() {
+        ValueKind::Call(c) => Some(c.callee()),
+        _ => None,
+    }
+}
+
+fn remove_unused_calls(func: &mut FunctionData, effects: &Effects) -> bool {
+    let unused: Vec<Value> = func
+        .layout()
+        .bbs()
+        .nodes()
+        .flat_map(|node| node.insts().keys())
+        .copied()
+        .filter(|inst| {
+            callee(func, *inst).is_some_and(|f| effects.of(f) != Effect::SideEffects)
+                && func.dfg().value(*inst).used_by().is_empty()
+        })
+        .collect();
+    for inst in unused.iter() {
+        remove_inst(func, *inst);
+    }
+    !unused.is_empty()
+}
+
+/// A call, with constant arguments the same whatever value holds them.
+type Key = (Function, Vec<Result<i32, Value>>);
+
+fn key(func: &FunctionData, inst: Value) -> Key {
+    match func.dfg().value(inst).kind() {
+        ValueKind::Call(c) => (c.callee(), c.args().iter().map(|arg| integer(func, *arg).ok_or(*arg)).collect()),
+        _ => unreachable!(),
+    }
+}
+
+enum Visit {
+    /// a block, with the read-only calls available on entering it
+    Enter(BasicBlock, HashMap<Key, Value>),
+    /// the end of the subtree of a block, with the length of the undo log
+    /// before it
+    Exit(usize),
+}
+
+/// Replaces calls by earlier ones with the same callee and arguments.
+fn reuse_calls(func: &mut FunctionData, effects: &Effects) -> bool {
+    let entry = func.layout().entry_bb().unwrap();
+    let dom = DomTree::dominators(func);
+    let preds = predecessors(func);
+    let alias = AliasAnalysis::new(func);
+
+    let mut changed = false;
+    // pure calls made by the dominators of the current block, and the order
+    // they were made in, to drop them leaving a subtree
+    let mut available: HashMap<Key, Value> = HashMap::new();
+    let mut undo: Vec<Key> = Vec::new();
+    let mut stack = vec![Visit::Enter(entry, HashMap::new())];
+    while let Some(visit) = stack.pop() {
+        let (bb, mut reads) = match visit {
+            Visit::Enter(bb, reads) => (bb, reads),
+            Visit::Exit(len) => {
+                for key in undo.drain(len..) {
+                    available.remove(&key);
+                }
+                continue;
+            }
+        };
+        stack.push(Visit::Exit(undo.len()));
+
+        let insts: Vec<Value> = func.layout().bbs().node(&bb).unwrap().insts().keys().copied().collect();
+        for inst in insts {
+            let effect = match func.dfg().value(inst).kind() {
+                ValueKind::Call(c) => effects.of(c.callee()),
+                ValueKind::Store(s) => {
+                    match alias.base(func, s.dest()) {
+                        Base::Local(alloc) if !alias.escapes(alloc) => {}
+                        _ => reads.clear(),
+                    }
+                    continue;
+                }
+                _ => continue,
+            };
+            let calls = match effect {
+                Effect::Pure => &mut available,
+                Effect::ReadOnly => &mut reads,
+                Effect::SideEffects => {
+                    reads.clear();
+                    continue;
+                }
+            };
+            let key = key(func, inst);
+            match calls.get(&key) {
+                Some(leader) => {
+                    let leader = *leader;
+                    replace_all_uses(func.dfg_mut(), inst, leader);
+                    remove_inst(func, inst);
+                    changed = true;
+                }
+                None => {
+                    calls.insert(key.clone(), inst);
+                    if effect == Effect::Pure {
+                        undo.push(key);
+                    }
+                }
+            }
+        }
+
+        for child in dom.children(bb).iter().rev() {
+            let reads = if preds[child].as_slice() == [bb] { reads.clone() } else { HashMap::new() };
+            stack.push(Visit::Enter(*child, reads));
+        }
+    }
+    changed
+}
+
+/// Moves pure calls with invariant arguments out of loops, inner loops
+/// first. A call only moves if it runs on every iteration, as the callee
+/// may not return.
+fn hoist_calls(func: &mut FunctionData, effects: &Effects) -> bool {
+    let mut changed = ensure_preheaders(func);
+    let dom = DomTree::dominators(func);
+    let loops = LoopInfo::new(func, &dom);
+    for l in loops.loops.iter() {
+        let preheader = match l.preheader {
+            Some(preheader) => preheader,
+            None => continue,
+        };
+        let order: Vec<BasicBlock> = dom.preorder().into_iter().filter(|bb| l.contains(*bb)).collect();
+        let leaving: Vec<BasicBlock> = order
+            .iter()
+            .copied()
+            .filter(|bb| {
+                let succs = successors(func, *bb);
+                succs.is_empty() || succs.iter().any(|s| !l.contains(*s)) || l.latches.contains(bb)
+            })
+            .collect();
+        for bb in order {
+            if !leaving.iter().all(|b| dom.dominates(bb, *b)) {
+                continue;
+            }
+            let insts: Vec<Value> = func.layout().bbs().node(&bb).unwrap().insts().keys().copied().collect();
+            for inst in insts {
+                let pure = callee(func, inst).is_some_and(|f| effects.of(f) == Effect::Pure);
+                if pure && func.dfg().value(inst).kind().value_uses().all(|v| is_invariant(func, l, v)) {
+                    let term = *func.layout().bbs().node(&preheader).unwrap().insts().back_key().unwrap();
+                    func.layout_mut().bb_mut(bb).insts_mut().remove(&inst);
+                    func.layout_mut().bb_mut(preheader).insts_mut().cursor_mut(term).insert_key_before(inst).unwrap();
+                    changed = true;
+                }
+            }
+        }
+    }
+    changed
+}
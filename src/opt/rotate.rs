@@ -0,0 +1,149 @@
+use crate::analysis::cfg::{predecessors, successors};
+use crate::analysis::dominators::DomTree;
+use crate::analysis::loops::{Loop, LoopInfo};
+use crate::opt::utils::{add_bb_params, clone_blocks, ensure_preheaders, for_each_edge_args, map_operands, merge_blocks, retarget};
+use crate::opt::FunctionPass;
+use koopa::ir::builder_traits::*;
+use koopa::ir::{BasicBlock, Function, FunctionData, Value, ValueKind};
+use std::collections::{HashMap, HashSet};
+
+/// Headers longer than this are not copied.
+const MAX_HEADER_SIZE: usize = 16;
+
+/// Loop rotation: a loop testing its condition in the header, at the top,
+/// gets a copy of the header in front of it as a guard, and the header
+/// itself moves to the bottom, after the latch. Each iteration then ends in
+/// a single conditional branch back to the top, and the guard leaves the
+/// loop a preheader of its own.
+///
+/// Only loops entering the body and leaving from the header to blocks with
+/// no other predecessor are rotated, so that what the header computes
+/// reaches them through new parameters. Past the loop, what the header
+/// computes may only be used where the exit dominates: a join with a
+/// `break` out of the body would get no such parameter.
+pub struct LoopRotation;
+
+impl FunctionPass for LoopRotation {
+    fn run_on(&mut self, _func: Function, data: &mut FunctionData) -> bool {
+        if data.layout().entry_bb().is_none() {
+            return false;
+        }
+        // the headers of rotated loops, which are their former bodies, as
+        // they may look like they could be rotated again
+        let mut rotated: HashSet<BasicBlock> = HashSet::new();
+        let mut changed = false;
+        loop {
+            changed |= ensure_preheaders(data);
+            let dom = DomTree::dominators(data);
+            let loops = LoopInfo::new(data, &dom);
+            let preds = predecessors(data);
+            let found = loops
+                .loops
+                .iter()
+                .filter(|l| !rotated.contains(&l.header))
+                .find_map(|l| rotatable(data, &dom, l, &preds).map(|(body, exit)| (l, body, exit)));
+            let (l, body, exit) = match found {
+                Some(found) => found,
+                None => break,
+            };
+            rotate(data, &dom, l, body, exit);
+            rotated.insert(body);
+            changed = true;
+        }
+        if !rotated.is_empty() {
+            merge_blocks(data);
+        }
+        changed
+    }
+}
+
+/// The successors of the header of `l` in the loop and out of it, if the
+/// loop can be rotated.
+fn rotatable(
+    func: &FunctionData,
+    dom: &DomTree,
+    l: &Loop,
+    preds: &HashMap<BasicBlock, Vec<BasicBlock>>,
+) -> Option<(BasicBlock, BasicBlock)> {
+    let latch = match l.latches.as_slice() {
+        [latch] if *latch != l.header => *latch,
+        _ => return None,
+    };
+    let term = *func.layout().bbs().node(&latch).unwrap().insts().back_key().unwrap();
+    if !matches!(func.dfg().value(term).kind(), ValueKind::Jump(_)) {
+        return None;
+    }
+    let (body, exit) = match successors(func, l.header).as_slice() {
+        [t, f] if l.contains(*t) && !l.contains(*f) => (*t, *f),
+        [t, f] if !l.contains(*t) && l.contains(*f) => (*f, *t),
+        _ => return None,
+    };
+    let size = func.layout().bbs().node(&l.header).unwrap().insts().len();
+    if preds[&body].len() != 1 || preds[&exit].len() != 1 || size > MAX_HEADER_SIZE {
+        return None;
+    }
+    // the header no longer dominates anything once rotated: its values only
+    // reach the blocks `rotate` passes them to
+    let header = func.layout().bbs().node(&l.header).unwrap();
+    let reached = func.dfg().bb(l.header).params().iter().chain(header.insts().keys()).all(|value| {
+        func.dfg().value(*value).used_by().iter().all(|user| {
+            let bb = func.layout().parent_bb(*user).unwrap();
+            bb == l.header || dom.dominates(body, bb) || dom.dominates(exit, bb)
+        })
+    });
+    reached.then_some((body, exit))
+}
+
+/// Rotates `l`, whose header goes to `body` in the loop and `exit` out of
+/// it, both entered from the header only.
+fn rotate(func: &mut FunctionData, dom: &DomTree, l: &Loop, body: BasicBlock, exit: BasicBlock) {
+    let header = l.header;
+    let preheader = l.preheader.unwrap();
+    let (bbs, copies) = clone_blocks(func, &[header], header);
+    let guard = bbs[&header];
+    let term = *func.layout().bbs().node(&preheader).unwrap().insts().back_key().unwrap();
+    retarget(func, term, header, guard);
+
+    // values of the header used past it now come from the guard on entering
+    // the loop or leaving it straight away, so the blocks after it take
+    // them as parameters
+    let defined: Vec<Value> = func
+        .dfg()
+        .bb(header)
+        .params()
+        .iter()
+        .chain(func.layout().bbs().node(&header).unwrap().insts().keys())
+        .copied()
+        .collect();
+    for value in defined {
+        let (mut in_loop, mut after) = (Vec::new(), Vec::new());
+        for user in func.dfg().value(value).used_by().iter() {
+            let bb = func.layout().parent_bb(*user).unwrap();
+            if bb != header {
+                if dom.dominates(body, bb) { &mut in_loop } else { &mut after }.push(*user);
+            }
+        }
+        for (target, users) in [(body, in_loop), (exit, after)] {
+            if users.is_empty() {
+                continue;
+            }
+            let ty = func.dfg().value(value).ty().clone();
+            let param = add_bb_params(func, target, vec![ty])[0];
+            pass_arg(func, header, target, value);
+            pass_arg(func, guard, target, copies[&value]);
+            for user in users {
+                let mut data = func.dfg().value(user).clone();
+                map_operands(data.kind_mut(), |v| if v == value { param } else { v });
+                func.dfg_mut().replace_value_with(user).raw(data);
+            }
+        }
+    }
+}
+
+/// Passes `arg` on to the last parameter of `target` from `bb`.
+fn pass_arg(func: &mut FunctionData, bb: BasicBlock, target: BasicBlock, arg: Value) {
+    let term = *func.layout().bbs().node(&bb).unwrap().insts().back_key().unwrap();
+    let mut data = func.dfg().value(term).clone();
+    for_each_edge_args(data.kind_mut(), target, |args| args.push(arg));
+    func.dfg_mut().replace_value_with(term).raw(data);
+}
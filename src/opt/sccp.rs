@@ -0,0 +1,252 @@
+use crate::opt::utils::{
+    fold_binary, integer, merge_blocks, remove_bb_param, remove_inst, remove_unreachable_bbs, replace_all_uses,
+};
+use crate::opt::FunctionPass;
+use koopa::ir::builder_traits::*;
+use koopa::ir::{BasicBlock, Function, FunctionData, Value, ValueKind};
+use std::collections::{HashMap, HashSet};
+
+/// Sparse conditional constant propagation, after Wegman and Zadeck.
+///
+/// Values are assumed undefined until shown constant or not, and blocks
+/// unreachable until a reachable branch may go to them, so constants
+/// flowing around loops and through block parameters are found as long
+/// as the paths that would spoil them are never taken. Constant results
+/// are then substituted, branches on constants become jumps, and the
+/// blocks that can no longer be reached are removed.
+pub struct SparseConditionalConstantPropagation;
+
+impl FunctionPass for SparseConditionalConstantPropagation {
+    fn run_on(&mut self, _func: Function, data: &mut FunctionData) -> bool {
+        let entry = match data.layout().entry_bb() {
+            Some(entry) => entry,
+            None => return false,
+        };
+        let mut solver = Solver::new(data);
+        solver.solve(entry);
+        let Solver { values, blocks, .. } = solver;
+
+        let mut changed = false;
+        // constant block parameters, then instructions
+        let bbs: Vec<BasicBlock> = data.layout().bbs().keys().copied().filter(|bb| blocks.contains(bb)).collect();
+        for bb in bbs.iter() {
+            let params = data.dfg().bb(*bb).params().to_vec();
+            for (i, param) in params.into_iter().enumerate().rev() {
+                if let Some(Lattice::Const(n)) = values.get(&param) {
+                    let n = data.dfg_mut().new_value().integer(*n);
+                    replace_all_uses(data.dfg_mut(), param, n);
+                    remove_bb_param(data, *bb, i);
+                    changed = true;
+                }
+            }
+        }
+        for bb in bbs {
+            let insts: Vec<Value> = data.layout().bbs().node(&bb).unwrap().insts().keys().copied().collect();
+            for inst in insts {
+                match data.dfg().value(inst).kind() {
+                    ValueKind::Binary(_) => {
+                        if let Some(Lattice::Const(n)) = values.get(&inst) {
+                            let n = data.dfg_mut().new_value().integer(*n);
+                            replace_all_uses(data.dfg_mut(), inst, n);
+                            remove_inst(data, inst);
+                            changed = true;
+                        }
+                    }
+                    ValueKind::Branch(b) => {
+                        let (target, args) = match integer(data, b.cond()) {
+                            Some(0) => (b.false_bb(), b.false_args().to_vec()),
+                            Some(_) => (b.true_bb(), b.true_args().to_vec()),
+                            None => continue,
+                        };
+                        data.dfg_mut().replace_value_with(inst).jump_with_args(target, args);
+                        changed = true;
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        if changed {
+            remove_unreachable_bbs(data);
+            merge_blocks(data);
+        }
+        changed
+    }
+}
+
+/// What is known of a value: nothing yet, a constant, or that it may take
+/// several values.
+#[derive(Clone, Copy, PartialEq)]
+enum Lattice {
+    Top,
+    Const(i32),
+    Bottom,
+}
+
+impl Lattice {
+    fn meet(self, other: Lattice) -> Lattice {
+        match (self, other) {
+            (Lattice::Top, x) | (x, Lattice::Top) => x,
+            (Lattice::Const(a), Lattice::Const(b)) if a == b => Lattice::Const(a),
+            _ => Lattice::Bottom,
+        }
+    }
+}
+
+struct Solver<'a> {
+    func: &'a FunctionData,
+    /// the lattice value of every parameter and instruction seen so far
+    values: HashMap<Value, Lattice>,
+    /// blocks found reachable
+    blocks: HashSet<BasicBlock>,
+    /// control flow edges found executable
+    edges: HashSet<(BasicBlock, BasicBlock)>,
+    flow_worklist: Vec<(BasicBlock, BasicBlock)>,
+    /// values whose lattice value went down, so their users need a revisit
+    ssa_worklist: Vec<Value>,
+}
+
+impl<'a> Solver<'a> {
+    fn new(func: &'a FunctionData) -> Self {
+        Solver {
+            func,
+            values: HashMap::new(),
+            blocks: HashSet::new(),
+            edges: HashSet::new(),
+            flow_worklist: Vec::new(),
+            ssa_worklist: Vec::new(),
+        }
+    }
+
+    fn solve(&mut self, entry: BasicBlock) {
+        self.visit_block(entry);
+        loop {
+            if let Some((from, to)) = self.flow_worklist.pop() {
+                if !self.edges.insert((from, to)) {
+                    continue;
+                }
+                if self.blocks.contains(&to) {
+                    self.visit_params(to);
+                } else {
+                    self.visit_block(to);
+                }
+            } else if let Some(value) = self.ssa_worklist.pop() {
+                let users: Vec<Value> = self.func.dfg().value(value).used_by().iter().copied().collect();
+                for user in users {
+                    match self.func.layout().parent_bb(user) {
+                        Some(bb) if self.blocks.contains(&bb) => self.visit_inst(bb, user),
+                        _ => {}
+                    }
+                }
+            } else {
+                break;
+            }
+        }
+    }
+
+    fn visit_block(&mut self, bb: BasicBlock) {
+        self.blocks.insert(bb);
+        self.visit_params(bb);
+        let insts: Vec<Value> = self.func.layout().bbs().node(&bb).unwrap().insts().keys().copied().collect();
+        for inst in insts {
+            self.visit_inst(bb, inst);
+        }
+    }
+
+    /// Meets each parameter of `bb` over the arguments of its executable
+    /// incoming edges.
+    fn visit_params(&mut self, bb: BasicBlock) {
+        let params = self.func.dfg().bb(bb).params();
+        let mut new = vec![Lattice::Top; params.len()];
+        for user in self.func.dfg().bb(bb).used_by() {
+            match self.func.layout().parent_bb(*user) {
+                Some(from) if self.edges.contains(&(from, bb)) => {}
+                _ => continue,
+            }
+            let mut edges = Vec::new();
+            match self.func.dfg().value(*user).kind() {
+                ValueKind::Jump(j) => edges.push(j.args()),
+                ValueKind::Branch(b) => {
+                    if b.true_bb() == bb {
+                        edges.push(b.true_args());
+                    }
+                    if b.false_bb() == bb {
+                        edges.push(b.false_args());
+                    }
+                }
+                _ => {}
+            }
+            for args in edges {
+                for (lattice, arg) in new.iter_mut().zip(args) {
+                    *lattice = lattice.meet(self.lattice(*arg));
+                }
+            }
+        }
+        for (param, lattice) in params.iter().zip(new) {
+            self.set(*param, lattice);
+        }
+    }
+
+    fn visit_inst(&mut self, bb: BasicBlock, inst: Value) {
+        let data = self.func.dfg().value(inst);
+        let lattice = match data.kind() {
+            ValueKind::Binary(b) => match (self.lattice(b.lhs()), self.lattice(b.rhs())) {
+                (Lattice::Const(l), Lattice::Const(r)) => Lattice::Const(fold_binary(b.op(), l, r)),
+                (Lattice::Bottom, _) | (_, Lattice::Bottom) => Lattice::Bottom,
+                _ => Lattice::Top,
+            },
+            ValueKind::Branch(b) => {
+                match self.lattice(b.cond()) {
+                    Lattice::Top => {}
+                    Lattice::Const(0) => self.add_edge(bb, b.false_bb()),
+                    Lattice::Const(_) => self.add_edge(bb, b.true_bb()),
+                    Lattice::Bottom => {
+                        self.add_edge(bb, b.true_bb());
+                        self.add_edge(bb, b.false_bb());
+                    }
+                }
+                return;
+            }
+            ValueKind::Jump(j) => {
+                self.add_edge(bb, j.target());
+                return;
+            }
+            _ if data.ty().is_unit() => return,
+            _ => Lattice::Bottom,
+        };
+        self.set(inst, lattice);
+    }
+
+    /// Marks the edge from `from` to `to` executable, or updates the
+    /// parameters of `to` with new arguments if it already is.
+    fn add_edge(&mut self, from: BasicBlock, to: BasicBlock) {
+        if self.edges.contains(&(from, to)) {
+            self.visit_params(to);
+        } else {
+            self.flow_worklist.push((from, to));
+        }
+    }
+
+    fn set(&mut self, value: Value, lattice: Lattice) {
+        let old = self.lattice(value);
+        // values only go down, so a revisit cannot raise them
+        let new = old.meet(lattice);
+        if new != old {
+            self.values.insert(value, new);
+            self.ssa_worklist.push(value);
+        }
+    }
+
+    fn lattice(&self, value: Value) -> Lattice {
+        if value.is_global() {
+            return Lattice::Bottom;
+        }
+        match self.func.dfg().value(value).kind() {
+            ValueKind::Integer(n) => Lattice::Const(n.value()),
+            // the generated code reads zero from undefined values
+            ValueKind::ZeroInit(_) | ValueKind::Undef(_) => Lattice::Const(0),
+            ValueKind::FuncArgRef(_) => Lattice::Bottom,
+            _ => self.values.get(&value).copied().unwrap_or(Lattice::Top),
+        }
+    }
+}
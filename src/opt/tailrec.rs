@@ -0,0 +1,102 @@
+use crate::analysis::alias::AliasAnalysis;
+use crate::opt::utils::{add_bb_params, fresh_bb_name, remove_inst, replace_all_uses};
+use crate::opt::FunctionPass;
+use koopa::ir::builder_traits::*;
+use koopa::ir::{Function, FunctionData, Type, Value, ValueKind};
+
+/// Tail recursion elimination: a function returning what a call to itself
+/// returns jumps back to its start with the arguments instead, so that the
+/// recursion becomes a loop and runs in constant stack space.
+///
+/// The old entry block becomes the loop header, taking the parameters as
+/// block parameters, and a new entry block enters it with the arguments of
+/// the function. Functions whose locals may be reached from a call are left
+/// alone, as the callee would have had locals of its own.
+pub struct TailRecursionElimination;
+
+impl FunctionPass for TailRecursionElimination {
+    fn run_on(&mut self, func: Function, data: &mut FunctionData) -> bool {
+        let calls = tail_calls(func, data);
+        if calls.is_empty() {
+            return false;
+        }
+        let alias = AliasAnalysis::new(data);
+        let escaping = data.layout().bbs().nodes().flat_map(|node| node.insts().keys()).any(|inst| {
+            matches!(data.dfg().value(*inst).kind(), ValueKind::Alloc(_)) && alias.escapes(*inst)
+        });
+        if escaping {
+            return false;
+        }
+
+        // the new entry holds the locals, allocated once, and enters the
+        // old one with the parameters
+        let header = data.layout().entry_bb().unwrap();
+        let params = data.params().to_vec();
+        let tys: Vec<Type> = params.iter().map(|p| data.dfg().value(*p).ty().clone()).collect();
+        let args = add_bb_params(data, header, tys);
+        for (param, arg) in params.iter().zip(args.iter()) {
+            replace_all_uses(data.dfg_mut(), *param, *arg);
+        }
+        let name = fresh_bb_name(data, "%entry");
+        let entry = data.dfg_mut().new_bb().basic_block(Some(name));
+        data.layout_mut().bbs_mut().push_key_front(entry).unwrap();
+        let allocs: Vec<Value> = data
+            .layout()
+            .bbs()
+            .node(&header)
+            .unwrap()
+            .insts()
+            .keys()
+            .copied()
+            .filter(|inst| matches!(data.dfg().value(*inst).kind(), ValueKind::Alloc(_)))
+            .collect();
+        for alloc in allocs {
+            data.layout_mut().bb_mut(header).insts_mut().remove(&alloc);
+            data.layout_mut().bb_mut(entry).insts_mut().push_key_back(alloc).unwrap();
+        }
+        let jump = data.dfg_mut().new_value().jump_with_args(header, params);
+        data.layout_mut().bb_mut(entry).insts_mut().push_key_back(jump).unwrap();
+
+        for (call, ret) in calls {
+            let bb = data.layout().parent_bb(call).unwrap();
+            let args = match data.dfg().value(call).kind() {
+                ValueKind::Call(c) => c.args().to_vec(),
+                _ => unreachable!(),
+            };
+            remove_inst(data, ret);
+            remove_inst(data, call);
+            let jump = data.dfg_mut().new_value().jump_with_args(header, args);
+            data.layout_mut().bb_mut(bb).insts_mut().push_key_back(jump).unwrap();
+        }
+        true
+    }
+}
+
+/// The calls of `func` to itself followed by a return of what they return,
+/// with those returns.
+fn tail_calls(func: Function, data: &FunctionData) -> Vec<(Value, Value)> {
+    let mut calls = Vec::new();
+    for node in data.layout().bbs().nodes() {
+        let insts: Vec<Value> = node.insts().keys().copied().collect();
+        let (call, ret) = match insts.as_slice() {
+            [.., call, ret] => (*call, *ret),
+            _ => continue,
+        };
+        let returned = match data.dfg().value(ret).kind() {
+            ValueKind::Return(r) => r.value(),
+            _ => continue,
+        };
+        match data.dfg().value(call).kind() {
+            ValueKind::Call(c) if c.callee() == func => {}
+            _ => continue,
+        }
+        let tail = match returned {
+            Some(value) => value == call && data.dfg().value(call).used_by().len() == 1,
+            None => true,
+        };
+        if tail {
+            calls.push((call, ret));
+        }
+    }
+    calls
+}
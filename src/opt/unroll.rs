@@ -0,0 +1,196 @@
+use crate::analysis::dominators::DomTree;
+use crate::analysis::induction::{header_exit, trip_count, TripCount};
+use crate::analysis::loops::{Loop, LoopInfo};
+use crate::opt::utils::{clone_blocks, ensure_preheaders, remove_unreachable_bbs, retarget};
+use crate::opt::FunctionPass;
+use koopa::ir::builder_traits::*;
+use koopa::ir::{BasicBlock, BinaryOp, Function, FunctionData, Value, ValueKind};
+
+/// Loops are not unrolled into more instructions than this.
+const MAX_UNROLLED_SIZE: usize = 256;
+
+/// Loop unrolling for innermost counted loops, whose trip count is known.
+///
+/// A loop small enough is fully unrolled: the body is copied once per
+/// iteration and the copies run one after the other, without testing the
+/// exit condition. A larger one is partially unrolled: `factor` copies of
+/// the body run per iteration as long as `factor` iterations remain, and
+/// the original loop runs the remaining ones.
+pub struct LoopUnroll {
+    factor: u32,
+}
+
+impl LoopUnroll {
+    pub fn new(factor: u32) -> Self {
+        LoopUnroll { factor }
+    }
+}
+
+impl FunctionPass for LoopUnroll {
+    fn run_on(&mut self, _func: Function, data: &mut FunctionData) -> bool {
+        if data.layout().entry_bb().is_none() {
+            return false;
+        }
+        let changed = ensure_preheaders(data);
+        let dom = DomTree::dominators(data);
+        let loops = LoopInfo::new(data, &dom);
+
+        // innermost loops are disjoint, so unrolling one leaves the others
+        // as they were found
+        let mut unrolled = false;
+        for l in loops.loops.iter().filter(|l| l.children.is_empty()) {
+            let (preheader, latch) = match (l.preheader, l.latches.as_slice()) {
+                (Some(preheader), [latch]) => (preheader, *latch),
+                _ => continue,
+            };
+            let trip = match trip_count(data, l) {
+                Some(trip) => trip,
+                None => continue,
+            };
+            let size: usize = l.blocks.iter().map(|bb| data.layout().bbs().node(bb).unwrap().insts().len()).sum();
+            let order: Vec<BasicBlock> = dom.preorder().into_iter().filter(|bb| l.contains(*bb)).collect();
+            let mut unroller = Unroller {
+                func: data,
+                l,
+                order,
+                preheader,
+                latch,
+            };
+            if trip.count as usize * size <= MAX_UNROLLED_SIZE {
+                unroller.unroll_fully(trip.count);
+            } else if self.factor > 1
+                && self.factor as usize * size <= MAX_UNROLLED_SIZE
+                && trip.count >= self.factor
+                && (trip.count as i64) * (trip.iv.step as i64).abs() < 1 << 31
+            {
+                unroller.unroll_partially(&trip, self.factor);
+            } else {
+                continue;
+            }
+            unrolled = true;
+        }
+        if unrolled {
+            remove_unreachable_bbs(data);
+        }
+        changed || unrolled
+    }
+}
+
+struct Unroller<'a> {
+    func: &'a mut FunctionData,
+    l: &'a Loop,
+    /// blocks of the loop in dominator order
+    order: Vec<BasicBlock>,
+    preheader: BasicBlock,
+    latch: BasicBlock,
+}
+
+/// A copy of the loop.
+struct Copy {
+    header: BasicBlock,
+    latch: BasicBlock,
+    /// the terminator of the header
+    branch: Value,
+}
+
+impl Unroller<'_> {
+    /// Runs `count` copies of the loop one after the other, each going
+    /// straight from its header into its body, and then the original
+    /// header, which now jumps to the exit. The original body is left
+    /// unreachable.
+    fn unroll_fully(&mut self, count: u32) {
+        let copies: Vec<Copy> = (0..count).map(|_| self.copy()).collect();
+        for copy in copies.iter() {
+            self.enter_body(copy.branch);
+        }
+        self.chain(&copies, self.l.header);
+        let branch = self.terminator(self.l.header);
+        let (_, exit) = header_exit(self.func, self.l).unwrap();
+        let args = self.edge_args(branch, exit);
+        self.func.dfg_mut().replace_value_with(branch).jump_with_args(exit, args);
+    }
+
+    /// Runs `factor` copies of the loop per iteration of a new loop, which
+    /// tests whether `factor` iterations remain only in its first header
+    /// and otherwise goes on to the original loop for the rest.
+    fn unroll_partially(&mut self, trip: &TripCount, factor: u32) {
+        let copies: Vec<Copy> = (0..factor).map(|_| self.copy()).collect();
+        for copy in copies.iter().skip(1) {
+            self.enter_body(copy.branch);
+        }
+        self.chain(&copies, copies[0].header);
+
+        // the variable goes through init + i * factor * step in the first
+        // header, and the loop ends when no further round fits
+        let first = &copies[0];
+        let rounds = trip.count / factor;
+        let init = match self.func.dfg().value(trip.iv.init).kind() {
+            ValueKind::Integer(n) => n.value(),
+            _ => unreachable!(),
+        };
+        let end = init.wrapping_add((rounds * factor) as i32 * trip.iv.step);
+        let params = self.func.dfg().bb(first.header).params().to_vec();
+        let end = self.func.dfg_mut().new_value().integer(end);
+        let cond = self.func.dfg_mut().new_value().binary(BinaryOp::NotEq, params[trip.iv.index], end);
+        self.func.layout_mut().bb_mut(first.header).insts_mut().cursor_mut(first.branch).insert_key_before(cond).unwrap();
+        let (in_loop, _) = header_exit(self.func, self.l).unwrap();
+        let body = self.target(first.branch, in_loop);
+        let args = self.edge_args(first.branch, body);
+        self.func.dfg_mut().replace_value_with(first.branch).branch_with_args(cond, body, self.l.header, args, params);
+    }
+
+    /// Copies the loop before its header, entered from nowhere yet.
+    fn copy(&mut self) -> Copy {
+        let (bbs, _) = clone_blocks(self.func, &self.order, self.l.header);
+        let header = bbs[&self.l.header];
+        Copy {
+            header,
+            latch: bbs[&self.latch],
+            branch: self.terminator(header),
+        }
+    }
+
+    /// Makes the header branch of a copy always go into its body.
+    fn enter_body(&mut self, branch: Value) {
+        let (in_loop, _) = header_exit(self.func, self.l).unwrap();
+        let body = self.target(branch, in_loop);
+        let args = self.edge_args(branch, body);
+        self.func.dfg_mut().replace_value_with(branch).jump_with_args(body, args);
+    }
+
+    /// Enters the first copy from the preheader, and makes the latch of
+    /// each copy go to the header of the next one, the last one to `last`.
+    fn chain(&mut self, copies: &[Copy], last: BasicBlock) {
+        let term = self.terminator(self.preheader);
+        let first = copies.first().map_or(self.l.header, |c| c.header);
+        retarget(self.func, term, self.l.header, first);
+        for (i, copy) in copies.iter().enumerate() {
+            let next = copies.get(i + 1).map_or(last, |c| c.header);
+            let term = self.terminator(copy.latch);
+            retarget(self.func, term, copy.header, next);
+        }
+    }
+
+    /// The successor of the header of a copy in the loop, given `in_loop`,
+    /// that of the original header.
+    fn target(&self, branch: Value, in_loop: BasicBlock) -> BasicBlock {
+        let original = self.terminator(self.l.header);
+        match (self.func.dfg().value(original).kind(), self.func.dfg().value(branch).kind()) {
+            (ValueKind::Branch(o), ValueKind::Branch(b)) if o.true_bb() == in_loop => b.true_bb(),
+            (ValueKind::Branch(_), ValueKind::Branch(b)) => b.false_bb(),
+            _ => unreachable!(),
+        }
+    }
+
+    fn edge_args(&self, branch: Value, to: BasicBlock) -> Vec<Value> {
+        match self.func.dfg().value(branch).kind() {
+            ValueKind::Branch(b) if b.true_bb() == to => b.true_args().to_vec(),
+            ValueKind::Branch(b) => b.false_args().to_vec(),
+            _ => unreachable!(),
+        }
+    }
+
+    fn terminator(&self, bb: BasicBlock) -> Value {
+        *self.func.layout().bbs().node(&bb).unwrap().insts().back_key().unwrap()
+    }
+}
@@ -0,0 +1,388 @@
+//! Edits of Koopa IR shared by the passes.
+
+use crate::analysis::callgraph::CallGraph;
+use crate::analysis::cfg::{predecessors, reverse_postorder};
+use crate::analysis::dominators::DomTree;
+use crate::analysis::loops::LoopInfo;
+use koopa::ir::builder_traits::*;
+use koopa::ir::dfg::DataFlowGraph;
+use koopa::ir::{BasicBlock, BinaryOp, Function, FunctionData, Program, Type, Value, ValueKind};
+use std::collections::{HashMap, HashSet};
+
+/// Rewrites every operand `v` of `kind` to `f(v)`.
+pub fn map_operands(kind: &mut ValueKind, mut f: impl FnMut(Value) -> Value) {
+    let mut map = |v: &mut Value| *v = f(*v);
+    match kind {
+        ValueKind::Load(l) => map(l.src_mut()),
+        ValueKind::Store(s) => {
+            map(s.value_mut());
+            map(s.dest_mut());
+        }
+        ValueKind::GetPtr(p) => {
+            map(p.src_mut());
+            map(p.index_mut());
+        }
+        ValueKind::GetElemPtr(p) => {
+            map(p.src_mut());
+            map(p.index_mut());
+        }
+        ValueKind::Binary(b) => {
+            map(b.lhs_mut());
+            map(b.rhs_mut());
+        }
+        ValueKind::Branch(b) => {
+            map(b.cond_mut());
+            b.true_args_mut().iter_mut().for_each(&mut map);
+            b.false_args_mut().iter_mut().for_each(&mut map);
+        }
+        ValueKind::Jump(j) => j.args_mut().iter_mut().for_each(map),
+        ValueKind::Call(c) => c.args_mut().iter_mut().for_each(map),
+        ValueKind::Return(r) => r.value_mut().iter_mut().for_each(map),
+        _ => {}
+    }
+}
+
+/// Makes every user of `old` use `new` instead.
+pub fn replace_all_uses(dfg: &mut DataFlowGraph, old: Value, new: Value) {
+    let users: Vec<Value> = dfg.value(old).used_by().iter().copied().collect();
+    for user in users {
+        let mut data = dfg.value(user).clone();
+        map_operands(data.kind_mut(), |v| if v == old { new } else { v });
+        dfg.replace_value_with(user).raw(data);
+    }
+}
+
+/// Removes the unused instruction `inst` from the layout and the data flow
+/// graph.
+pub fn remove_inst(func: &mut FunctionData, inst: Value) {
+    let bb = func.layout().parent_bb(inst).unwrap();
+    func.layout_mut().bb_mut(bb).insts_mut().remove(&inst);
+    func.dfg_mut().remove_value(inst);
+}
+
+/// Whether `kind` only computes its result, so that it can go when the
+/// result is unused.
+pub fn is_pure(kind: &ValueKind) -> bool {
+    matches!(
+        kind,
+        ValueKind::Alloc(_) | ValueKind::Load(_) | ValueKind::GetPtr(_) | ValueKind::GetElemPtr(_) | ValueKind::Binary(_)
+    )
+}
+
+/// Removes the basic blocks that cannot be reached from the entry,
+/// returning whether there were any.
+pub fn remove_unreachable_bbs(func: &mut FunctionData) -> bool {
+    let reachable: HashSet<BasicBlock> = reverse_postorder(func).into_iter().collect();
+    let dead: Vec<BasicBlock> = func.layout().bbs().keys().copied().filter(|bb| !reachable.contains(bb)).collect();
+    if dead.is_empty() {
+        return false;
+    }
+    let mut insts = Vec::new();
+    for bb in dead.iter() {
+        let (_, node) = func.layout_mut().bbs_mut().remove(bb).unwrap();
+        insts.extend(node.insts().keys().copied());
+    }
+    // dead code only feeds dead code, so each round frees at least one value
+    while !insts.is_empty() {
+        let (unused, used): (Vec<Value>, Vec<Value>) =
+            insts.into_iter().partition(|v| func.dfg().value(*v).used_by().is_empty());
+        assert!(!unused.is_empty(), "unreachable value used by reachable code");
+        for v in unused {
+            func.dfg_mut().remove_value(v);
+        }
+        insts = used;
+    }
+    for bb in dead {
+        func.dfg_mut().remove_bb(bb);
+    }
+    true
+}
+
+/// The value of `value` if it is an integer constant.
+pub fn integer(func: &FunctionData, value: Value) -> Option<i32> {
+    if value.is_global() {
+        return None;
+    }
+    match func.dfg().value(value).kind() {
+        ValueKind::Integer(n) => Some(n.value()),
+        _ => None,
+    }
+}
+
+/// Evaluates `lhs op rhs` the way the generated RISC-V code does: with
+/// wrapping arithmetic, shift amounts taken modulo 32, and the results
+/// `div` and `rem` give for a zero divisor.
+pub fn fold_binary(op: BinaryOp, lhs: i32, rhs: i32) -> i32 {
+    match op {
+        BinaryOp::NotEq => (lhs != rhs) as i32,
+        BinaryOp::Eq => (lhs == rhs) as i32,
+        BinaryOp::Gt => (lhs > rhs) as i32,
+        BinaryOp::Lt => (lhs < rhs) as i32,
+        BinaryOp::Ge => (lhs >= rhs) as i32,
+        BinaryOp::Le => (lhs <= rhs) as i32,
+        BinaryOp::Add => lhs.wrapping_add(rhs),
+        BinaryOp::Sub => lhs.wrapping_sub(rhs),
+        BinaryOp::Mul => lhs.wrapping_mul(rhs),
+        BinaryOp::Div if rhs == 0 => -1,
+        BinaryOp::Div => lhs.wrapping_div(rhs),
+        BinaryOp::Mod if rhs == 0 => lhs,
+        BinaryOp::Mod => lhs.wrapping_rem(rhs),
+        BinaryOp::And => lhs & rhs,
+        BinaryOp::Or => lhs | rhs,
+        BinaryOp::Xor => lhs ^ rhs,
+        BinaryOp::Shl => lhs.wrapping_shl(rhs as u32),
+        BinaryOp::Shr => ((lhs as u32).wrapping_shr(rhs as u32)) as i32,
+        BinaryOp::Sar => lhs.wrapping_shr(rhs as u32),
+    }
+}
+
+/// Appends parameters of types `tys` to `bb`, returning them. Every jump
+/// to `bb` must then be given arguments for them.
+pub fn add_bb_params(func: &mut FunctionData, bb: BasicBlock, tys: Vec<Type>) -> Vec<Value> {
+    // parameters can only be created along with a block, so borrow a new one's
+    let first = func.dfg().bb(bb).params().len();
+    let tmp = func.dfg_mut().new_bb().basic_block_with_params(None, tys);
+    let params = std::mem::take(func.dfg_mut().bb_mut(tmp).params_mut());
+    func.dfg_mut().remove_bb(tmp);
+    for (i, param) in params.iter().enumerate() {
+        let mut data = func.dfg().value(*param).clone();
+        if let ValueKind::BlockArgRef(arg) = data.kind_mut() {
+            *arg.index_mut() = first + i;
+        }
+        func.dfg_mut().replace_value_with(*param).raw(data);
+    }
+    func.dfg_mut().bb_mut(bb).params_mut().extend(params.iter().copied());
+    params
+}
+
+/// Removes parameter `index` of `bb`, which must be unused, along with the
+/// argument every jump to `bb` passes for it.
+pub fn remove_bb_param(func: &mut FunctionData, bb: BasicBlock, index: usize) {
+    let users: Vec<Value> = func.dfg().bb(bb).used_by().iter().copied().collect();
+    for user in users {
+        let mut data = func.dfg().value(user).clone();
+        for_each_edge_args(data.kind_mut(), bb, |args| {
+            args.remove(index);
+        });
+        func.dfg_mut().replace_value_with(user).raw(data);
+    }
+    let param = func.dfg_mut().bb_mut(bb).params_mut().remove(index);
+    func.dfg_mut().remove_value(param);
+    let params = func.dfg().bb(bb).params().to_vec();
+    for (i, param) in params.into_iter().enumerate().skip(index) {
+        let mut data = func.dfg().value(param).clone();
+        if let ValueKind::BlockArgRef(arg) = data.kind_mut() {
+            *arg.index_mut() = i;
+        }
+        func.dfg_mut().replace_value_with(param).raw(data);
+    }
+}
+
+/// Calls `f` on each argument list the terminator `kind` passes along an
+/// edge to `target`.
+pub fn for_each_edge_args(kind: &mut ValueKind, target: BasicBlock, mut f: impl FnMut(&mut Vec<Value>)) {
+    match kind {
+        ValueKind::Jump(j) if j.target() == target => f(j.args_mut()),
+        ValueKind::Branch(b) => {
+            if b.true_bb() == target {
+                f(b.true_args_mut());
+            }
+            if b.false_bb() == target {
+                f(b.false_args_mut());
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Merges every block into its predecessor when that predecessor is the
+/// only one and does nothing but jump to it at the end, returning whether
+/// any block was merged.
+pub fn merge_blocks(func: &mut FunctionData) -> bool {
+    let mut changed = false;
+    let bbs: Vec<BasicBlock> = func.layout().bbs().keys().copied().collect();
+    for bb in bbs {
+        // `bb` may have been merged into an earlier block already
+        if func.layout().bbs().node(&bb).is_none() {
+            continue;
+        }
+        loop {
+            let jump = *func.layout().bbs().node(&bb).unwrap().insts().back_key().unwrap();
+            let (target, args) = match func.dfg().value(jump).kind() {
+                ValueKind::Jump(j) => (j.target(), j.args().to_vec()),
+                _ => break,
+            };
+            if target == bb
+                || Some(target) == func.layout().entry_bb()
+                || func.dfg().bb(target).used_by().len() != 1
+            {
+                break;
+            }
+            remove_inst(func, jump);
+            let params = func.dfg().bb(target).params().to_vec();
+            for (param, arg) in params.into_iter().zip(args) {
+                replace_all_uses(func.dfg_mut(), param, arg);
+            }
+            while let Some((inst, _)) = func.layout_mut().bb_mut(target).insts_mut().pop_front() {
+                func.layout_mut().bb_mut(bb).insts_mut().push_key_back(inst).unwrap();
+            }
+            func.layout_mut().bbs_mut().remove(&target);
+            func.dfg_mut().remove_bb(target);
+            changed = true;
+        }
+    }
+    changed
+}
+
+/// A block name starting with `name` that no block of `func` has yet.
+pub fn fresh_bb_name(func: &FunctionData, name: &str) -> String {
+    let taken: HashSet<&String> = func.dfg().bbs().values().filter_map(|bb| bb.name().as_ref()).collect();
+    let mut candidate = name.to_string();
+    let mut i = 1;
+    while taken.contains(&candidate) {
+        candidate = format!("{}_{}", name, i);
+        i += 1;
+    }
+    candidate
+}
+
+/// Makes every edge of the terminator `term` to `from` go to `to` instead,
+/// with the same arguments.
+pub fn retarget(func: &mut FunctionData, term: Value, from: BasicBlock, to: BasicBlock) {
+    let mut data = func.dfg().value(term).clone();
+    match data.kind_mut() {
+        ValueKind::Jump(j) if j.target() == from => *j.target_mut() = to,
+        ValueKind::Branch(b) => {
+            if b.true_bb() == from {
+                *b.true_bb_mut() = to;
+            }
+            if b.false_bb() == from {
+                *b.false_bb_mut() = to;
+            }
+        }
+        _ => {}
+    }
+    func.dfg_mut().replace_value_with(term).raw(data);
+}
+
+/// Inserts a block that `entering`, the predecessors of `header` from
+/// outside its loop, go through instead of jumping to `header` directly,
+/// returning the new block. It takes the parameters of `header` and passes
+/// them on.
+pub fn insert_preheader(func: &mut FunctionData, header: BasicBlock, entering: &[BasicBlock]) -> BasicBlock {
+    let header_name = func.dfg().bb(header).name().clone().unwrap_or_else(|| "%loop".to_string());
+    let name = fresh_bb_name(func, &format!("{}_preheader", header_name));
+    let tys: Vec<Type> = func.dfg().bb(header).params().iter().map(|p| func.dfg().value(*p).ty().clone()).collect();
+    let preheader = func.dfg_mut().new_bb().basic_block_with_params(Some(name), tys);
+    let params = func.dfg().bb(preheader).params().to_vec();
+    let jump = func.dfg_mut().new_value().jump_with_args(header, params);
+    func.layout_mut().bbs_mut().cursor_mut(header).insert_key_before(preheader).unwrap();
+    func.layout_mut().bb_mut(preheader).insts_mut().push_key_back(jump).unwrap();
+    for p in entering {
+        let term = *func.layout().bbs().node(p).unwrap().insts().back_key().unwrap();
+        retarget(func, term, header, preheader);
+    }
+    preheader
+}
+
+/// Gives every loop of `func` a preheader, returning whether any lacked
+/// one.
+pub fn ensure_preheaders(func: &mut FunctionData) -> bool {
+    let dom = DomTree::dominators(func);
+    let loops = LoopInfo::new(func, &dom);
+    let preds = predecessors(func);
+    let mut changed = false;
+    for l in loops.loops.iter().filter(|l| l.preheader.is_none()) {
+        let entering: Vec<BasicBlock> = preds[&l.header].iter().copied().filter(|p| !l.contains(*p)).collect();
+        insert_preheader(func, l.header, &entering);
+        changed = true;
+    }
+    changed
+}
+
+/// Copies `blocks`, laying the copies out before `before`, and returns the
+/// copy of each block and of each value defined in them. Branches between
+/// the blocks go between their copies, and branches out of them leave from
+/// the copies too.
+///
+/// Every value must come after its definition in `blocks`, as it does in
+/// the preorder of the dominator tree.
+pub fn clone_blocks(
+    func: &mut FunctionData,
+    blocks: &[BasicBlock],
+    before: BasicBlock,
+) -> (HashMap<BasicBlock, BasicBlock>, HashMap<Value, Value>) {
+    let mut bbs = HashMap::new();
+    let mut values = HashMap::new();
+    for bb in blocks {
+        let data = func.dfg().bb(*bb);
+        let name = data.name().clone().map(|name| fresh_bb_name(func, &name));
+        let tys: Vec<Type> = data.params().iter().map(|p| func.dfg().value(*p).ty().clone()).collect();
+        let copy = func.dfg_mut().new_bb().basic_block_with_params(name, tys);
+        func.layout_mut().bbs_mut().cursor_mut(before).insert_key_before(copy).unwrap();
+        for (param, new) in func.dfg().bb(*bb).params().iter().zip(func.dfg().bb(copy).params()) {
+            values.insert(*param, *new);
+        }
+        bbs.insert(*bb, copy);
+    }
+    for bb in blocks {
+        let insts: Vec<Value> = func.layout().bbs().node(bb).unwrap().insts().keys().copied().collect();
+        for inst in insts {
+            let mut data = func.dfg().value(inst).clone();
+            map_operands(data.kind_mut(), |v| values.get(&v).copied().unwrap_or(v));
+            let map = |bb: &mut BasicBlock| *bb = bbs.get(bb).copied().unwrap_or(*bb);
+            match data.kind_mut() {
+                ValueKind::Jump(j) => map(j.target_mut()),
+                ValueKind::Branch(b) => {
+                    map(b.true_bb_mut());
+                    map(b.false_bb_mut());
+                }
+                _ => {}
+            }
+            let copy = func.dfg_mut().new_value().raw(data);
+            func.dfg_mut().set_value_name(copy, None);
+            func.layout_mut().bb_mut(bbs[bb]).insts_mut().push_key_back(copy).unwrap();
+            values.insert(inst, copy);
+        }
+    }
+    (bbs, values)
+}
+
+/// Lays the functions of `program` out so that each comes after the ones it
+/// calls, but for calls within a cycle of recursion, as the text form of
+/// the IR wants functions defined before they are called. Functions already
+/// in order keep their place.
+pub fn order_functions(program: &mut Program) {
+    let order: Vec<Function> = CallGraph::new(program).sccs().iter().flatten().copied().collect();
+    let layout = program.func_layout().to_vec();
+    let first = match layout.iter().zip(order.iter()).position(|(a, b)| a != b) {
+        Some(first) => first,
+        None => return,
+    };
+    // functions cannot move in the layout, so those from the first out of
+    // place on are taken out and added back in order, under new handles
+    let mut data: HashMap<Function, FunctionData> =
+        layout[first..].iter().map(|func| (*func, program.remove_func(*func).unwrap())).collect();
+    let mut moved = HashMap::new();
+    for func in order[first..].iter() {
+        moved.insert(*func, program.new_func(data.remove(func).unwrap()));
+    }
+    for func in program.func_layout().to_vec() {
+        let data = program.func_mut(func);
+        let calls: Vec<Value> = data
+            .layout()
+            .bbs()
+            .nodes()
+            .flat_map(|node| node.insts().keys())
+            .copied()
+            .filter(|inst| matches!(data.dfg().value(*inst).kind(), ValueKind::Call(c) if moved.contains_key(&c.callee())))
+            .collect();
+        for call in calls {
+            let mut call_data = data.dfg().value(call).clone();
+            if let ValueKind::Call(c) = call_data.kind_mut() {
+                *c.callee_mut() = moved[&c.callee()];
+            }
+            data.dfg_mut().replace_value_with(call).raw(call_data);
+        }
+    }
+}
@@ -0,0 +1,277 @@
+use crate::analysis::dominators::DomTree;
+use koopa::ir::{BasicBlock, FunctionData, Program, Type, TypeKind, Value, ValueKind};
+use std::collections::{HashMap, HashSet};
+
+/// Checks that `program` is well formed, returning a description of every
+/// problem found.
+///
+/// Every basic block must end in its only terminator, block names must be
+/// unique within a function, operands must have the types their
+/// instruction expects, every use must be dominated by its definition and
+/// every branch must target a block of its own function.
+pub fn verify(program: &Program) -> Result<(), Vec<String>> {
+    let mut errors = Vec::new();
+    for func in program.func_layout() {
+        let data = program.func(*func);
+        if data.layout().entry_bb().is_some() {
+            let mut verifier = Verifier::new(program, data);
+            verifier.verify();
+            errors.append(&mut verifier.errors);
+        }
+    }
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors)
+    }
+}
+
+struct Verifier<'a> {
+    program: &'a Program,
+    func: &'a FunctionData,
+    /// `None` when a branch leaves the layout, as there is no control flow
+    /// graph to check dominance on then
+    dom: Option<DomTree>,
+    /// the block and index within it of every instruction and block parameter;
+    /// parameters come before every instruction
+    defs: HashMap<Value, (BasicBlock, i64)>,
+    errors: Vec<String>,
+}
+
+impl<'a> Verifier<'a> {
+    fn new(program: &'a Program, func: &'a FunctionData) -> Self {
+        let mut defs = HashMap::new();
+        for (bb, node) in func.layout().bbs() {
+            for param in func.dfg().bb(*bb).params() {
+                defs.insert(*param, (*bb, -1));
+            }
+            for (i, inst) in node.insts().keys().enumerate() {
+                defs.insert(*inst, (*bb, i as i64));
+            }
+        }
+        let closed = func.layout().bbs().nodes().all(|node| {
+            node.insts().back_key().is_none_or(|inst| {
+                func.dfg().value(*inst).kind().bb_uses().all(|target| func.layout().bbs().node(&target).is_some())
+            })
+        });
+        Verifier {
+            program,
+            func,
+            dom: closed.then(|| DomTree::dominators(func)),
+            defs,
+            errors: Vec::new(),
+        }
+    }
+
+    fn error(&mut self, bb: Option<BasicBlock>, message: String) {
+        let location = match bb {
+            Some(bb) => format!("{}, {}", self.func.name(), self.bb_name(bb)),
+            None => self.func.name().to_string(),
+        };
+        self.errors.push(format!("{}: {}", location, message));
+    }
+
+    fn bb_name(&self, bb: BasicBlock) -> String {
+        match self.func.dfg().bbs().get(&bb).and_then(|data| data.name().clone()) {
+            Some(name) => name,
+            None => format!("{:?}", bb),
+        }
+    }
+
+    fn value_name(&self, value: Value) -> String {
+        let name = if value.is_global() {
+            self.program.borrow_value(value).name().clone()
+        } else {
+            self.func.dfg().values().get(&value).and_then(|data| data.name().clone())
+        };
+        name.unwrap_or_else(|| format!("{:?}", value))
+    }
+
+    /// The type of `value`, `None` if it exists nowhere.
+    fn ty(&self, value: Value) -> Option<Type> {
+        if value.is_global() {
+            self.program.borrow_values().get(&value).map(|data| data.ty().clone())
+        } else {
+            self.func.dfg().values().get(&value).map(|data| data.ty().clone())
+        }
+    }
+
+    fn verify(&mut self) {
+        let mut names = HashSet::new();
+        let bbs: Vec<BasicBlock> = self.func.layout().bbs().keys().copied().collect();
+        for bb in bbs {
+            if let Some(name) = self.func.dfg().bb(bb).name().clone() {
+                if !names.insert(name.clone()) {
+                    self.error(Some(bb), format!("block name `{}` is not unique", name));
+                }
+            }
+            let insts: Vec<Value> = self.func.layout().bbs().node(&bb).unwrap().insts().keys().copied().collect();
+            match insts.last() {
+                None => self.error(Some(bb), "empty basic block".to_string()),
+                Some(last) if !is_terminator(self.func, *last) => {
+                    self.error(Some(bb), "basic block does not end in a terminator".to_string())
+                }
+                _ => {}
+            }
+            for (i, inst) in insts.iter().enumerate() {
+                if i + 1 < insts.len() && is_terminator(self.func, *inst) {
+                    self.error(Some(bb), "instructions after a terminator".to_string());
+                }
+                self.verify_inst(bb, i as i64, *inst);
+            }
+        }
+    }
+
+    fn verify_inst(&mut self, bb: BasicBlock, index: i64, inst: Value) {
+        let data = self.func.dfg().value(inst);
+        for operand in data.kind().value_uses() {
+            self.verify_operand(bb, index, operand);
+        }
+        for target in data.kind().bb_uses() {
+            if self.func.layout().bbs().node(&target).is_none() {
+                self.error(Some(bb), "branch to a block outside the function".to_string());
+            }
+        }
+
+        let i32_ty = Type::get_i32();
+        let unit = Type::get_unit();
+        let problem = match data.kind() {
+            ValueKind::Alloc(_) => match data.ty().kind() {
+                TypeKind::Pointer(_) => None,
+                _ => Some("alloc of a non-pointer type".to_string()),
+            },
+            ValueKind::Load(l) => match self.ty(l.src()).map(|t| t.kind().clone()) {
+                Some(TypeKind::Pointer(base)) if base == *data.ty() => None,
+                _ => Some("load from a value that does not point to its type".to_string()),
+            },
+            ValueKind::Store(s) => match (self.ty(s.value()), self.ty(s.dest()).map(|t| t.kind().clone())) {
+                (Some(value), Some(TypeKind::Pointer(base))) if base == value => None,
+                _ => Some(format!(
+                    "store of {} into {}, which does not point to its type",
+                    self.value_name(s.value()),
+                    self.value_name(s.dest())
+                )),
+            },
+            ValueKind::GetPtr(p) => match self.ty(p.src()).map(|t| t.kind().clone()) {
+                Some(TypeKind::Pointer(_)) if self.ty(p.index()) == Some(i32_ty) => None,
+                _ => Some("getptr needs a pointer and an i32 index".to_string()),
+            },
+            ValueKind::GetElemPtr(p) => match self.ty(p.src()).map(|t| t.kind().clone()) {
+                Some(TypeKind::Pointer(base))
+                    if matches!(base.kind(), TypeKind::Array(..)) && self.ty(p.index()) == Some(i32_ty) =>
+                {
+                    None
+                }
+                _ => Some("getelemptr needs a pointer to an array and an i32 index".to_string()),
+            },
+            ValueKind::Binary(b) => {
+                if self.ty(b.lhs()) == Some(i32_ty.clone()) && self.ty(b.rhs()) == Some(i32_ty.clone()) && *data.ty() == i32_ty
+                {
+                    None
+                } else {
+                    Some("binary operands must be i32".to_string())
+                }
+            }
+            ValueKind::Branch(br) => {
+                if self.ty(br.cond()) != Some(i32_ty) {
+                    Some("branch condition must be i32".to_string())
+                } else {
+                    self.check_args(br.true_bb(), br.true_args())
+                        .or_else(|| self.check_args(br.false_bb(), br.false_args()))
+                }
+            }
+            ValueKind::Jump(j) => self.check_args(j.target(), j.args()),
+            ValueKind::Call(c) => match self.program.funcs().get(&c.callee()).map(|f| f.ty().kind().clone()) {
+                Some(TypeKind::Function(params, ret)) => {
+                    let args: Vec<Option<Type>> = c.args().iter().map(|a| self.ty(*a)).collect();
+                    if args.len() != params.len() || args.iter().zip(params.iter()).any(|(a, p)| a.as_ref() != Some(p)) {
+                        Some("call arguments do not match the parameters".to_string())
+                    } else if ret != *data.ty() {
+                        Some("call result does not match the return type".to_string())
+                    } else {
+                        None
+                    }
+                }
+                _ => Some("call of an unknown function".to_string()),
+            },
+            ValueKind::Return(r) => {
+                let ret = match self.func.ty().kind() {
+                    TypeKind::Function(_, ret) => ret.clone(),
+                    _ => unreachable!(),
+                };
+                let value = r.value().map_or(Some(unit), |v| self.ty(v));
+                if value != Some(ret) {
+                    Some("return value does not match the return type".to_string())
+                } else {
+                    None
+                }
+            }
+            _ => Some("not an instruction".to_string()),
+        };
+        if let Some(problem) = problem {
+            let name = self.value_name(inst);
+            self.error(Some(bb), format!("{}: {}", name, problem));
+        }
+    }
+
+    fn check_args(&self, target: BasicBlock, args: &[Value]) -> Option<String> {
+        let params = self.func.dfg().bbs().get(&target)?.params();
+        let matches = params.len() == args.len()
+            && params.iter().zip(args).all(|(p, a)| self.ty(*a).is_some() && self.ty(*a) == self.ty(*p));
+        if matches {
+            None
+        } else {
+            Some(format!("arguments do not match the parameters of {}", self.bb_name(target)))
+        }
+    }
+
+    /// Checks that `operand`, used by the instruction at `index` in `bb`,
+    /// exists and is defined before the use on every path.
+    fn verify_operand(&mut self, bb: BasicBlock, index: i64, operand: Value) {
+        if operand.is_global() {
+            if !self.program.borrow_values().contains_key(&operand) {
+                self.error(Some(bb), format!("use of an unknown global {:?}", operand));
+            }
+            return;
+        }
+        let data = match self.func.dfg().values().get(&operand) {
+            Some(data) => data,
+            None => {
+                self.error(Some(bb), format!("use of an unknown value {:?}", operand));
+                return;
+            }
+        };
+        match data.kind() {
+            ValueKind::FuncArgRef(_) | ValueKind::Integer(_) | ValueKind::ZeroInit(_) | ValueKind::Undef(_) => {}
+            ValueKind::Aggregate(_) => {}
+            _ => match self.defs.get(&operand) {
+                None => {
+                    let name = self.value_name(operand);
+                    self.error(Some(bb), format!("use of {}, which is not in the layout", name));
+                }
+                Some((def_bb, def_index)) => {
+                    // uses in unreachable code are not constrained
+                    let dominated = if *def_bb == bb {
+                        *def_index < index
+                    } else {
+                        match &self.dom {
+                            Some(dom) => !dom.contains(bb) || dom.strictly_dominates(*def_bb, bb),
+                            None => true,
+                        }
+                    };
+                    if !dominated {
+                        let name = self.value_name(operand);
+                        self.error(Some(bb), format!("use of {} is not dominated by its definition", name));
+                    }
+                }
+            },
+        }
+    }
+}
+
+fn is_terminator(func: &FunctionData, inst: Value) -> bool {
+    matches!(
+        func.dfg().value(inst).kind(),
+        ValueKind::Branch(_) | ValueKind::Jump(_) | ValueKind::Return(_)
+    )
+}
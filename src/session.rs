@@ -0,0 +1,51 @@
+use koopa::ir::Function;
+use std::collections::HashMap;
+
+/// Options given on the command line.
+#[derive(Default)]
+pub struct Options {
+    /// `-O<n>`, 0 when absent
+    pub opt_level: u32,
+    /// `-passes=a,b,c`, replacing the pipeline of the optimization level
+    pub passes: Option<Vec<String>>,
+    /// `-print-after-all`
+    pub print_after_all: bool,
+    /// `-print-after=<pass>`, may be given more than once
+    pub print_after: Vec<String>,
+    /// `-print-changed`: only print when the pass changed the program
+    pub print_changed: bool,
+    /// `-opt-bisect-limit=N`: run only the first N pass applications
+    pub opt_bisect_limit: Option<usize>,
+    /// `-verify`: check the IR after generation and after every pass
+    pub verify: bool,
+    /// `-unroll-factor=N`: copies of the body in a partially unrolled loop
+    pub unroll_factor: Option<u32>,
+}
+
+/// State of one compilation, shared by the stages that need to agree on it.
+///
+/// Everything that used to live in process globals belongs here, so that
+/// compiling the same input twice yields byte-identical output.
+#[derive(Default)]
+pub struct Session {
+    pub options: Options,
+    /// next basic block number of each function
+    bb_ids: HashMap<Function, usize>,
+}
+
+impl Session {
+    pub fn new(options: Options) -> Self {
+        Session {
+            options,
+            ..Self::default()
+        }
+    }
+
+    /// Returns a fresh basic block name `{prefix}_{n}`, numbered per function.
+    pub fn next_bb_name(&mut self, func: Function, prefix: &str) -> String {
+        let id = self.bb_ids.entry(func).or_insert(0);
+        let name = format!("{}_{}", prefix, id);
+        *id += 1;
+        name
+    }
+}
@@ -0,0 +1,62 @@
+mod common;
+
+use common::{driver, parse, print, run, SAMPLE};
+use compiler::opt::manager::PassManager;
+use compiler::opt::pipeline;
+use compiler::session::Options;
+use koopa::ir::Program;
+
+const SOURCE: &str = "int main() { int a = 3; int b = a * 2; return a + b; }";
+
+/// `SAMPLE` through the `-O2` pipeline, stopped after `limit` applications.
+fn bisect(limit: Option<usize>) -> Program {
+    let options = Options {
+        passes: Some(pipeline(2).iter().map(|p| p.to_string()).collect()),
+        opt_bisect_limit: limit,
+        verify: true,
+        ..Options::default()
+    };
+    let mut program = parse(SAMPLE);
+    PassManager::from_options(&options).unwrap().run(&mut program).unwrap();
+    program
+}
+
+#[test]
+fn logs_every_application() {
+    let result = driver(SOURCE, &["-koopa", "-passes=mem2reg,globaldce,dce", "-opt-bisect-limit=1"]);
+    assert!(result.success, "{}", result.stderr);
+    let log: Vec<&str> = result.stderr.lines().filter(|l| l.starts_with("BISECT: ")).collect();
+    assert_eq!(
+        log,
+        [
+            "BISECT: running pass (1) mem2reg on @main",
+            "BISECT: NOT running pass (2) globaldce on module",
+            "BISECT: NOT running pass (3) dce on @main",
+        ]
+    );
+    // only mem2reg ran
+    assert!(!result.output.contains("alloc"));
+    assert!(!driver(SOURCE, &["-koopa", "-O1"]).stderr.contains("BISECT"));
+}
+
+#[test]
+fn limit_zero_runs_nothing() {
+    assert_eq!(print(&bisect(Some(0))), print(&parse(SAMPLE)));
+}
+
+#[test]
+fn every_prefix_of_the_pipeline_works() {
+    let full = print(&bisect(None));
+    let mut limit = 0;
+    loop {
+        let program = bisect(Some(limit));
+        for n in [0, 4, 30] {
+            assert_eq!(run(&program, &[n]), run(&parse(SAMPLE), &[n]), "limit {}", limit);
+        }
+        if print(&program) == full {
+            break;
+        }
+        limit += 1;
+    }
+    assert!(limit > pipeline(2).len());
+}
@@ -0,0 +1,87 @@
+mod common;
+
+use common::{check, compile, func, parse};
+use compiler::asm::regalloc::{Allocator, Loc};
+use compiler::asm::visitor::Visitor;
+use compiler::session::{Options, Session};
+use koopa::ir::Program;
+
+/// Passes its argument straight through to the return value.
+const FORWARD: &str = r#"
+decl @getint(): i32
+
+fun @inc(%a: i32): i32 {
+%entry:
+  %r = add %a, 1
+  ret %r
+}
+
+fun @main(): i32 {
+%entry:
+  %x = call @getint()
+  %y = call @inc(%x)
+  %z = call @inc(%y)
+  ret %z
+}
+"#;
+
+/// `main` reading 30 values that are only needed after a loop counting to
+/// the first of them, so that they crowd the registers the loop wants.
+fn crowded() -> String {
+    let mut text = String::from("decl @getint(): i32\n\nfun @main(): i32 {\n%entry:\n");
+    for i in 0..30 {
+        text += &format!("  %v{} = call @getint()\n", i);
+    }
+    text += "  jump %loop(0, 0)\n\n%loop(%i: i32, %s: i32):\n  %more = lt %i, %v0\n  br %more, %body, %end\n\n%body:\n";
+    text += "  %t = mul %i, %i\n  %s1 = add %s, %t\n  %i1 = add %i, 1\n  jump %loop(%i1, %s1)\n\n";
+    text += "%end:\n  %r0 = add %s, 0\n";
+    for i in 0..30 {
+        text += &format!("  %r{} = add %r{}, %v{}\n", i + 1, i, i);
+    }
+    text += "  ret %r30\n}\n";
+    text
+}
+
+/// Where `allocator` puts the value called `name` in `@main`.
+fn location(program: &Program, allocator: Allocator, name: &str) -> Loc {
+    let data = program.func(func(program, "@main"));
+    allocator.allocate(data).locs[&common::value(data, name)]
+}
+
+#[test]
+fn coalesces_arguments_and_results() {
+    check(FORWARD, &[], &[41]);
+    let asm = compile(&parse(FORWARD), Allocator::GraphColoring);
+    let inc = &asm[asm.find("inc:").unwrap()..asm.find("main:").unwrap()];
+    assert!(!inc.contains("mv"), "moves left in:\n{}", inc);
+}
+
+#[test]
+fn spills_values_outside_loops_first() {
+    let text = crowded();
+    let input: Vec<i32> = (0..30).map(|i| if i == 0 { 50 } else { i * 7 - 100 }).collect();
+    check(&text, &[], &input);
+    let program = parse(&text);
+    for name in ["%i", "%s", "%t", "%i1", "%s1"] {
+        assert!(matches!(location(&program, Allocator::GraphColoring, name), Loc::Reg(_)), "{} spilled", name);
+    }
+    let spilled = |i: i32| matches!(location(&program, Allocator::GraphColoring, &format!("%v{}", i)), Loc::Spill(_));
+    assert!((1..30).any(spilled));
+}
+
+#[test]
+fn runs_programs_with_calls_globals_and_arrays() {
+    for n in [0, 1, 5, 40] {
+        check(common::SAMPLE, &[], &[n]);
+    }
+}
+
+#[test]
+fn chosen_at_o2() {
+    let program = parse(FORWARD);
+    let mut code = Vec::new();
+    let session = Session::new(Options { opt_level: 2, ..Options::default() });
+    Visitor::new(&session).visit(&mut code, &program).unwrap();
+    assert_eq!(String::from_utf8(code).unwrap(), compile(&program, Allocator::GraphColoring));
+    assert_eq!(Visitor::new(&Session::default()).allocator, Allocator::LinearScan);
+}
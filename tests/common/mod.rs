@@ -0,0 +1,711 @@
+//! Helpers shared by the integration tests: building programs from Koopa
+//! text, running them through passes and the backend, and running the
+//! results to compare what they do.
+#![allow(dead_code)]
+
+use compiler::asm::regalloc::Allocator;
+use compiler::asm::visitor::Visitor;
+use compiler::opt::manager::PassManager;
+use compiler::opt::verifier::verify;
+use compiler::session::Options;
+use koopa::back::KoopaGenerator;
+use koopa::front::Driver;
+use koopa::ir::{BasicBlock, BinaryOp, Function, FunctionData, Program, Type, TypeKind, Value, ValueKind};
+use std::collections::HashMap;
+use std::process::Command;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// What running a program did: the value `main` returned and what it wrote.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Outcome {
+    pub ret: i32,
+    pub output: String,
+}
+
+/// A program with a bit of everything: a loop left through a `break` with
+/// the sum so far, calls, a global counter and a global table, and a local
+/// array. Reads the trip count, prints and returns a checksum.
+pub const SAMPLE: &str = r#"
+decl @getint(): i32
+decl @putint(i32)
+decl @putch(i32)
+
+global @count = alloc i32, zeroinit
+global @table = alloc [i32, 8], {3, 1, 4, 1, 5, 9, 2, 6}
+
+fun @weight(%x: i32): i32 {
+%entry:
+  %c = load @count
+  %c1 = add %c, 1
+  store %c1, @count
+  %k = mod %x, 8
+  %p = getelemptr @table, %k
+  %v = load %p
+  %w = mul %v, %x
+  ret %w
+}
+
+fun @main(): i32 {
+%entry:
+  %buf = alloc [i32, 4]
+  %b0 = getelemptr %buf, 0
+  store 0, %b0
+  %n = call @getint()
+  jump %loop(0, 0)
+
+%loop(%i: i32, %s: i32):
+  %more = lt %i, %n
+  br %more, %body, %done(%s)
+
+%body:
+  %w = call @weight(%i)
+  %s1 = add %s, %w
+  %slot = and %i, 3
+  %p = getelemptr %buf, %slot
+  store %s1, %p
+  %big = gt %s1, 100
+  br %big, %done(%s1), %next
+
+%next:
+  %i1 = add %i, 1
+  jump %loop(%i1, %s1)
+
+%done(%r: i32):
+  call @putint(%r)
+  call @putch(10)
+  %q = getelemptr %buf, 0
+  %first = load %q
+  %c = load @count
+  %t = add %r, %c
+  %u = add %t, %first
+  ret %u
+}
+"#;
+
+/// A program the way the frontend lowers one: every variable lives in
+/// memory, read and written around a loop with a `break`, a call to a
+/// function adding to a global, and a local array.
+pub const FRONTEND: &str = r#"
+decl @getint(): i32
+decl @putint(i32)
+decl @putch(i32)
+
+global @total = alloc i32, zeroinit
+
+fun @add_to(%v: i32) {
+%entry:
+  %t = load @total
+  %t1 = add %t, %v
+  store %t1, @total
+  ret
+}
+
+fun @main(): i32 {
+%entry:
+  %a = alloc [i32, 10]
+  %i = alloc i32
+  %n = alloc i32
+  %a0 = getelemptr %a, 0
+  store 0, %a0
+  %x = call @getint()
+  store %x, %n
+  store 0, %i
+  jump %cond
+
+%cond:
+  %iv = load %i
+  %nv = load %n
+  %c = lt %iv, %nv
+  br %c, %body, %end
+
+%body:
+  %iv2 = load %i
+  %sq = mul %iv2, %iv2
+  %k = mod %iv2, 10
+  %p = getelemptr %a, %k
+  store %sq, %p
+  call @add_to(%sq)
+  %tv = load @total
+  %stop = gt %tv, 500
+  br %stop, %end, %step
+
+%step:
+  %iv3 = load %i
+  %iv4 = add %iv3, 1
+  store %iv4, %i
+  jump %cond
+
+%end:
+  %p0 = getelemptr %a, 0
+  %first = load %p0
+  %tv2 = load @total
+  call @putint(%tv2)
+  call @putch(10)
+  %iv5 = load %i
+  %r = add %first, %iv5
+  ret %r
+}
+"#;
+
+/// Parses `text` and checks that it is well formed.
+pub fn parse(text: &str) -> Program {
+    let program = Driver::from(text).generate_program().expect("invalid Koopa text");
+    verify(&program).unwrap_or_else(|errors| panic!("broken input:\n{}", errors.join("\n")));
+    program
+}
+
+/// Runs the registered passes `passes` over `program`, verifying the IR
+/// after each one.
+pub fn run_passes(program: &mut Program, passes: &[&str]) {
+    let options = Options {
+        passes: Some(passes.iter().map(|p| p.to_string()).collect()),
+        verify: true,
+        ..Options::default()
+    };
+    let mut manager = PassManager::from_options(&options).unwrap();
+    manager.run(program).unwrap_or_else(|e| panic!("{}", e));
+}
+
+/// Parses `text` and runs `passes` over it.
+pub fn optimize(text: &str, passes: &[&str]) -> Program {
+    let mut program = parse(text);
+    run_passes(&mut program, passes);
+    program
+}
+
+/// The function called `name`.
+pub fn func(program: &Program, name: &str) -> Function {
+    *program
+        .func_layout()
+        .iter()
+        .find(|f| program.func(**f).name() == name)
+        .unwrap_or_else(|| panic!("no function {}", name))
+}
+
+/// The basic block called `name` in `func`.
+pub fn block(func: &FunctionData, name: &str) -> BasicBlock {
+    *func
+        .dfg()
+        .bbs()
+        .iter()
+        .find(|(_, bb)| bb.name().as_deref() == Some(name))
+        .unwrap_or_else(|| panic!("no block {}", name))
+        .0
+}
+
+/// The value called `name` in `func`.
+pub fn value(func: &FunctionData, name: &str) -> Value {
+    *func
+        .dfg()
+        .values()
+        .iter()
+        .find(|(_, v)| v.name().as_deref() == Some(name))
+        .unwrap_or_else(|| panic!("no value {}", name))
+        .0
+}
+
+/// The Koopa text of `program`.
+pub fn print(program: &Program) -> String {
+    let mut gen = KoopaGenerator::new(Vec::new());
+    gen.generate_on(program).unwrap();
+    String::from_utf8(gen.writer()).unwrap()
+}
+
+/// The instructions of the function called `name`, one per line, as Koopa
+/// text prints them.
+pub fn body(program: &Program, name: &str) -> Vec<String> {
+    let text = print(program);
+    let start = text.find(&format!("fun {}(", name)).unwrap_or_else(|| panic!("no function {}", name));
+    text[start..]
+        .lines()
+        .skip(1)
+        .take_while(|line| *line != "}")
+        .filter(|line| line.starts_with("  "))
+        .map(|line| line.trim().to_string())
+        .collect()
+}
+
+/// How many instructions of the function called `name` start with `op`,
+/// or assign the result of `op`.
+pub fn count(program: &Program, name: &str, op: &str) -> usize {
+    body(program, name)
+        .iter()
+        .filter(|inst| {
+            let rhs = inst.split_once(" = ").map_or(inst.as_str(), |(_, rhs)| rhs);
+            rhs == op || rhs.starts_with(&format!("{} ", op))
+        })
+        .count()
+}
+
+/// The RISC-V code generated for `program` with `allocator`.
+pub fn compile(program: &Program, allocator: Allocator) -> String {
+    let mut visitor = Visitor { allocator };
+    let mut code = Vec::new();
+    visitor.visit(&mut code, program).unwrap();
+    String::from_utf8(code).unwrap()
+}
+
+/// What a run of the compiler binary did.
+pub struct Run {
+    pub success: bool,
+    /// the output file
+    pub output: String,
+    pub stderr: String,
+}
+
+/// Runs the compiler binary on the SysY `source` with `args`, such as
+/// `-koopa`, then the input and output files.
+pub fn driver(source: &str, args: &[&str]) -> Run {
+    static RUNS: AtomicUsize = AtomicUsize::new(0);
+    let id = RUNS.fetch_add(1, Ordering::Relaxed);
+    let dir = std::env::temp_dir();
+    let input = dir.join(format!("compiler-test-{}-{}.c", std::process::id(), id));
+    let output = dir.join(format!("compiler-test-{}-{}.out", std::process::id(), id));
+    std::fs::write(&input, source).unwrap();
+    let result = Command::new(env!("CARGO_BIN_EXE_compiler"))
+        .args(args)
+        .arg(&input)
+        .arg("-o")
+        .arg(&output)
+        .output()
+        .unwrap();
+    let run = Run {
+        success: result.status.success(),
+        output: std::fs::read_to_string(&output).unwrap_or_default(),
+        stderr: String::from_utf8(result.stderr).unwrap(),
+    };
+    std::fs::remove_file(&input).ok();
+    std::fs::remove_file(&output).ok();
+    run
+}
+
+/// Checks that `passes` keep what the program in `text` does on `input`,
+/// run as IR and compiled by both allocators, and returns the optimized
+/// program.
+pub fn check(text: &str, passes: &[&str], input: &[i32]) -> Program {
+    let expected = run(&parse(text), input);
+    let program = optimize(text, passes);
+    assert_eq!(run(&program, input), expected, "after {:?}:\n{}", passes, print(&program));
+    for allocator in [Allocator::LinearScan, Allocator::GraphColoring] {
+        let asm = compile(&program, allocator);
+        assert_eq!(simulate(&asm, input), expected, "{:?} code:\n{}", allocator, asm);
+    }
+    program
+}
+
+/// Runs the library function `name`, reading from `input` and writing to
+/// `output`, with memory accessed through `mem`.
+fn runtime(
+    name: &str,
+    args: &[i32],
+    input: &mut impl Iterator<Item = i32>,
+    output: &mut String,
+    mem: &mut HashMap<u32, i32>,
+) -> i32 {
+    match name {
+        "getint" | "getch" => input.next().unwrap_or(-1),
+        "getarray" => {
+            let n = input.next().unwrap_or(0);
+            for i in 0..n {
+                mem.insert(args[0] as u32 + 4 * i as u32, input.next().unwrap_or(0));
+            }
+            n
+        }
+        "putint" => {
+            output.push_str(&args[0].to_string());
+            0
+        }
+        "putch" => {
+            output.push(args[0] as u8 as char);
+            0
+        }
+        "putarray" => {
+            output.push_str(&format!("{}:", args[0]));
+            for i in 0..args[0] {
+                output.push_str(&format!(" {}", mem.get(&(args[1] as u32 + 4 * i as u32)).unwrap_or(&0)));
+            }
+            output.push('\n');
+            0
+        }
+        "starttime" | "stoptime" => 0,
+        _ => panic!("unknown library function {}", name),
+    }
+}
+
+/// Evaluates a binary operation the way RV32IM does.
+fn binary(op: BinaryOp, l: i32, r: i32) -> i32 {
+    match op {
+        BinaryOp::NotEq => (l != r) as i32,
+        BinaryOp::Eq => (l == r) as i32,
+        BinaryOp::Gt => (l > r) as i32,
+        BinaryOp::Lt => (l < r) as i32,
+        BinaryOp::Ge => (l >= r) as i32,
+        BinaryOp::Le => (l <= r) as i32,
+        BinaryOp::Add => l.wrapping_add(r),
+        BinaryOp::Sub => l.wrapping_sub(r),
+        BinaryOp::Mul => l.wrapping_mul(r),
+        BinaryOp::Div if r == 0 => -1,
+        BinaryOp::Div => l.wrapping_div(r),
+        BinaryOp::Mod if r == 0 => l,
+        BinaryOp::Mod => l.wrapping_rem(r),
+        BinaryOp::And => l & r,
+        BinaryOp::Or => l | r,
+        BinaryOp::Xor => l ^ r,
+        BinaryOp::Shl => l.wrapping_shl(r as u32),
+        BinaryOp::Shr => (l as u32).wrapping_shr(r as u32) as i32,
+        BinaryOp::Sar => l.wrapping_shr(r as u32),
+    }
+}
+
+/// Runs the `main` of `program` as IR on `input`.
+pub fn run(program: &Program, input: &[i32]) -> Outcome {
+    let mut interpreter = Interpreter {
+        program,
+        mem: HashMap::new(),
+        top: 0x1000,
+        globals: HashMap::new(),
+        input: input.iter().copied(),
+        output: String::new(),
+        steps: 0,
+    };
+    for global in program.inst_layout() {
+        let init = match program.borrow_value(*global).kind() {
+            ValueKind::GlobalAlloc(alloc) => alloc.init(),
+            _ => unreachable!(),
+        };
+        let addr = interpreter.top;
+        let mut words = Vec::new();
+        flatten(&|v| program.borrow_value(v).clone(), init, &mut words);
+        interpreter.top += 4 * words.len().max(1) as u32;
+        interpreter.store_words(addr, &words);
+        interpreter.globals.insert(*global, addr);
+    }
+    let main = *program.func_layout().iter().find(|f| program.func(**f).name() == "@main").expect("no main");
+    let ret = interpreter.call(main, Vec::new());
+    Outcome {
+        ret,
+        output: interpreter.output,
+    }
+}
+
+/// Appends the words of the constant `value` to `words`.
+fn flatten(data: &impl Fn(Value) -> koopa::ir::entities::ValueData, value: Value, words: &mut Vec<i32>) {
+    let value = data(value);
+    match value.kind() {
+        ValueKind::Integer(n) => words.push(n.value()),
+        ValueKind::ZeroInit(_) | ValueKind::Undef(_) => words.extend(std::iter::repeat_n(0, value.ty().size() / 4)),
+        ValueKind::Aggregate(a) => a.elems().iter().for_each(|e| flatten(data, *e, words)),
+        kind => panic!("not a constant: {:?}", kind),
+    }
+}
+
+struct Interpreter<'a> {
+    program: &'a Program,
+    mem: HashMap<u32, i32>,
+    /// the next free address, growing as functions allocate
+    top: u32,
+    globals: HashMap<Value, u32>,
+    input: std::iter::Copied<std::slice::Iter<'a, i32>>,
+    output: String,
+    steps: usize,
+}
+
+impl Interpreter<'_> {
+    fn store_words(&mut self, addr: u32, words: &[i32]) {
+        for (i, word) in words.iter().enumerate() {
+            self.mem.insert(addr + 4 * i as u32, *word);
+        }
+    }
+
+    fn call(&mut self, func: Function, args: Vec<i32>) -> i32 {
+        let data = self.program.func(func);
+        if data.layout().entry_bb().is_none() {
+            return runtime(&data.name()[1..], &args, &mut self.input, &mut self.output, &mut self.mem);
+        }
+        let frame = self.top;
+        let mut values: HashMap<Value, i32> = data.params().iter().copied().zip(args).collect();
+        let mut bb = data.layout().entry_bb().unwrap();
+        let ret = 'blocks: loop {
+            for inst in data.layout().bbs().node(&bb).unwrap().insts().keys() {
+                self.steps += 1;
+                assert!(self.steps < 50_000_000, "step limit");
+                let value = data.dfg().value(*inst);
+                let result = match value.kind() {
+                    ValueKind::Alloc(_) => {
+                        let size = match value.ty().kind() {
+                            TypeKind::Pointer(base) => base.size(),
+                            _ => unreachable!(),
+                        };
+                        let addr = self.top;
+                        self.top += size.max(4) as u32;
+                        addr as i32
+                    }
+                    ValueKind::Load(l) => *self.mem.get(&(self.eval(data, &values, l.src()) as u32)).unwrap_or(&0),
+                    ValueKind::Store(s) => {
+                        let addr = self.eval(data, &values, s.dest()) as u32;
+                        let mut words = Vec::new();
+                        if s.value().is_global() {
+                            flatten(&|v| self.program.borrow_value(v).clone(), s.value(), &mut words);
+                        } else {
+                            match data.dfg().value(s.value()).kind() {
+                                ValueKind::ZeroInit(_) | ValueKind::Aggregate(_) => {
+                                    flatten(&|v| data.dfg().value(v).clone(), s.value(), &mut words)
+                                }
+                                _ => words.push(self.eval(data, &values, s.value())),
+                            }
+                        }
+                        self.store_words(addr, &words);
+                        continue;
+                    }
+                    ValueKind::GetPtr(p) => {
+                        let ty = self.pointee(data, p.src());
+                        self.eval(data, &values, p.src()) + self.eval(data, &values, p.index()) * ty.size() as i32
+                    }
+                    ValueKind::GetElemPtr(p) => {
+                        let elem = match self.pointee(data, p.src()).kind() {
+                            TypeKind::Array(elem, _) => elem.size(),
+                            _ => unreachable!(),
+                        };
+                        self.eval(data, &values, p.src()) + self.eval(data, &values, p.index()) * elem as i32
+                    }
+                    ValueKind::Binary(b) => {
+                        binary(b.op(), self.eval(data, &values, b.lhs()), self.eval(data, &values, b.rhs()))
+                    }
+                    ValueKind::Call(c) => {
+                        let args = c.args().iter().map(|a| self.eval(data, &values, *a)).collect();
+                        self.call(c.callee(), args)
+                    }
+                    ValueKind::Branch(b) => {
+                        let (target, args) = if self.eval(data, &values, b.cond()) != 0 {
+                            (b.true_bb(), b.true_args())
+                        } else {
+                            (b.false_bb(), b.false_args())
+                        };
+                        let args: Vec<i32> = args.iter().map(|a| self.eval(data, &values, *a)).collect();
+                        values.extend(data.dfg().bb(target).params().iter().copied().zip(args));
+                        bb = target;
+                        continue 'blocks;
+                    }
+                    ValueKind::Jump(j) => {
+                        let args: Vec<i32> = j.args().iter().map(|a| self.eval(data, &values, *a)).collect();
+                        values.extend(data.dfg().bb(j.target()).params().iter().copied().zip(args));
+                        bb = j.target();
+                        continue 'blocks;
+                    }
+                    ValueKind::Return(r) => break 'blocks r.value().map_or(0, |v| self.eval(data, &values, v)),
+                    kind => panic!("cannot run {:?}", kind),
+                };
+                values.insert(*inst, result);
+            }
+            panic!("{}: block without a terminator", data.name());
+        };
+        self.top = frame;
+        ret
+    }
+
+    fn eval(&self, func: &FunctionData, values: &HashMap<Value, i32>, value: Value) -> i32 {
+        if value.is_global() {
+            return self.globals[&value] as i32;
+        }
+        match func.dfg().value(value).kind() {
+            ValueKind::Integer(n) => n.value(),
+            _ => values[&value],
+        }
+    }
+
+    /// The type `ptr` points to.
+    fn pointee(&self, func: &FunctionData, ptr: Value) -> Type {
+        let ty = if ptr.is_global() {
+            self.program.borrow_value(ptr).ty().clone()
+        } else {
+            func.dfg().value(ptr).ty().clone()
+        };
+        match ty.kind() {
+            TypeKind::Pointer(base) => base.clone(),
+            _ => unreachable!(),
+        }
+    }
+}
+
+const REGS: [&str; 32] = [
+    "x0", "ra", "sp", "gp", "tp", "t0", "t1", "t2", "s0", "s1", "a0", "a1", "a2", "a3", "a4", "a5", "a6", "a7",
+    "s2", "s3", "s4", "s5", "s6", "s7", "s8", "s9", "s10", "s11", "t3", "t4", "t5", "t6",
+];
+
+const STACK_TOP: u32 = 0x7fff_f000;
+
+/// The return address of `main`, stopping the simulation.
+const EXIT: u32 = u32::MAX;
+
+/// Runs RV32IM assembly as the backend generates it, calling into the
+/// runtime library by name. Checks that immediates fit their fields, that
+/// conditional branches reach their targets and that every function leaves
+/// `sp` and the callee-saved registers as it found them, while calls into
+/// the library trash the caller-saved ones.
+pub fn simulate(asm: &str, input: &[i32]) -> Outcome {
+    let mut insts: Vec<Vec<String>> = Vec::new();
+    let mut labels: HashMap<String, u32> = HashMap::new();
+    let mut mem: HashMap<u32, i32> = HashMap::new();
+    let mut data = 0x1000_0000;
+    let mut in_text = true;
+    for line in asm.lines() {
+        let line = line.trim();
+        if let Some(label) = line.strip_suffix(':') {
+            labels.insert(label.to_string(), if in_text { insts.len() as u32 } else { data });
+            continue;
+        }
+        let parts: Vec<String> = line.replace(',', " ").split_whitespace().map(String::from).collect();
+        match parts.first().map(String::as_str) {
+            None | Some(".global") | Some(".globl") => {}
+            Some(".text") => in_text = true,
+            Some(".data") => in_text = false,
+            Some(".word") => {
+                mem.insert(data, parts[1].parse().unwrap());
+                data += 4;
+            }
+            Some(".zero") => data += parts[1].parse::<u32>().unwrap(),
+            Some(_) => insts.push(parts),
+        }
+    }
+
+    let reg = |name: &str| REGS.iter().position(|r| *r == name).unwrap_or_else(|| panic!("no register {}", name));
+    let imm12 = |text: &str| {
+        let imm: i32 = text.parse().unwrap();
+        assert!((-2048..2048).contains(&imm), "immediate {} out of range", imm);
+        imm
+    };
+    let write = |regs: &mut [i32; 32], r: &str, v: i32| {
+        let r = reg(r);
+        if r != 0 {
+            regs[r] = v;
+        }
+    };
+    let mut regs = [0i32; 32];
+    regs[2] = STACK_TOP as i32;
+    regs[1] = EXIT as i32;
+    let callee_saved = |regs: &[i32; 32]| -> Vec<i32> {
+        std::iter::once(2).chain(8..10).chain(18..28).map(|r| regs[r]).collect()
+    };
+    // the library is free to use the caller-saved registers
+    let clobber = |regs: &mut [i32; 32]| {
+        for r in (5..8).chain(11..18).chain(28..32) {
+            regs[r] = 0xdead_beef_u32 as i32;
+        }
+    };
+    // what each active call must find on returning
+    let mut frames = vec![(EXIT, callee_saved(&regs))];
+    let mut pc = labels["main"];
+    let mut input = input.iter().copied();
+    let mut output = String::new();
+    let mut steps = 0;
+    while pc != EXIT {
+        steps += 1;
+        assert!(steps < 100_000_000, "step limit");
+        let inst = &insts[pc as usize];
+        let a: Vec<&str> = inst[1..].iter().map(String::as_str).collect();
+        pc += 1;
+        let read = |regs: &[i32; 32], r: &str| regs[reg(r)];
+        let addr = |regs: &[i32; 32], operand: &str| {
+            let (offset, base) = operand.trim_end_matches(')').split_once('(').unwrap();
+            let addr = read(regs, base).wrapping_add(imm12(offset)) as u32;
+            assert_eq!(addr % 4, 0, "misaligned access");
+            addr
+        };
+        match inst[0].as_str() {
+            "li" => write(&mut regs, a[0], a[1].parse().unwrap()),
+            "la" => write(&mut regs, a[0], labels[a[1]] as i32),
+            "mv" => {
+                let v = read(&regs, a[1]);
+                write(&mut regs, a[0], v)
+            }
+            "lw" => {
+                let v = *mem.get(&addr(&regs, a[1])).unwrap_or(&0);
+                write(&mut regs, a[0], v)
+            }
+            "sw" => {
+                mem.insert(addr(&regs, a[1]), read(&regs, a[0]));
+            }
+            op @ ("seqz" | "snez") => {
+                let v = (read(&regs, a[1]) == 0) == (op == "seqz");
+                write(&mut regs, a[0], v as i32)
+            }
+            op @ ("addi" | "andi" | "ori" | "xori" | "slti") => {
+                let (l, r) = (read(&regs, a[1]), imm12(a[2]));
+                let v = match op {
+                    "addi" => l.wrapping_add(r),
+                    "andi" => l & r,
+                    "ori" => l | r,
+                    "xori" => l ^ r,
+                    _ => (l < r) as i32,
+                };
+                write(&mut regs, a[0], v)
+            }
+            op @ ("slli" | "srli" | "srai") => {
+                let (l, r) = (read(&regs, a[1]), a[2].parse::<u32>().unwrap());
+                assert!(r < 32, "shift amount {} out of range", r);
+                let v = match op {
+                    "slli" => binary(BinaryOp::Shl, l, r as i32),
+                    "srli" => binary(BinaryOp::Shr, l, r as i32),
+                    _ => binary(BinaryOp::Sar, l, r as i32),
+                };
+                write(&mut regs, a[0], v)
+            }
+            op @ ("add" | "sub" | "mul" | "div" | "rem" | "and" | "or" | "xor" | "sll" | "srl" | "sra" | "slt") => {
+                let op = match op {
+                    "add" => BinaryOp::Add,
+                    "sub" => BinaryOp::Sub,
+                    "mul" => BinaryOp::Mul,
+                    "div" => BinaryOp::Div,
+                    "rem" => BinaryOp::Mod,
+                    "and" => BinaryOp::And,
+                    "or" => BinaryOp::Or,
+                    "xor" => BinaryOp::Xor,
+                    "sll" => BinaryOp::Shl,
+                    "srl" => BinaryOp::Shr,
+                    "sra" => BinaryOp::Sar,
+                    _ => BinaryOp::Lt,
+                };
+                let v = binary(op, read(&regs, a[1]), read(&regs, a[2]));
+                write(&mut regs, a[0], v)
+            }
+            "j" => match labels.get(a[0]) {
+                Some(target) => pc = *target,
+                // a tail call into the runtime
+                None => {
+                    let v = runtime(a[0], &regs[10..18], &mut input, &mut output, &mut mem);
+                    clobber(&mut regs);
+                    write(&mut regs, "a0", v);
+                    pc = regs[1] as u32;
+                }
+            },
+            op @ ("beqz" | "bnez") => {
+                // even at one instruction a line, the target must be in reach
+                let target = labels[a[1]];
+                assert!(4 * (target as i64 - pc as i64 + 1).abs() < 4096, "{} {} out of range", op, a[1]);
+                if (read(&regs, a[0]) == 0) == (op == "beqz") {
+                    pc = target;
+                }
+            }
+            "call" => match labels.get(a[0]) {
+                Some(target) => {
+                    write(&mut regs, "ra", pc as i32);
+                    frames.push((pc, callee_saved(&regs)));
+                    pc = *target;
+                }
+                None => {
+                    let v = runtime(a[0], &regs[10..18], &mut input, &mut output, &mut mem);
+                    clobber(&mut regs);
+                    write(&mut regs, "a0", v);
+                }
+            },
+            "ret" => {
+                pc = read(&regs, "ra") as u32;
+                let (back, saved) = frames.pop().expect("return without a call");
+                assert_eq!(pc, back, "returning to the wrong place");
+                assert_eq!(callee_saved(&regs), saved, "sp or callee-saved registers clobbered");
+            }
+            op => panic!("unknown instruction {}", op),
+        }
+    }
+    Outcome { ret: regs[10], output }
+}
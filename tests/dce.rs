@@ -0,0 +1,71 @@
+mod common;
+
+use common::{body, check, driver, optimize};
+
+/// Dead values hanging off a loop with a `break`: a chain of arithmetic, a
+/// load, an address and a whole array nothing reads, next to a call and
+/// stores that must stay.
+const DEAD: &str = r#"
+decl @getint(): i32
+decl @putint(i32)
+
+global @g = alloc i32, zeroinit
+
+fun @main(): i32 {
+%entry:
+  %unused = alloc [i32, 16]
+  %n = call @getint()
+  %ignored = call @getint()
+  jump %head(0)
+
+%head(%i: i32):
+  %c = lt %i, %n
+  br %c, %body, %exit
+
+%body:
+  %a = mul %i, 7
+  %b = add %a, 3
+  %v = load @g
+  %p = getelemptr %unused, 2
+  store %i, @g
+  %stop = eq %i, 4
+  br %stop, %exit, %next
+
+%next:
+  %i1 = add %i, 1
+  jump %head(%i1)
+
+%exit:
+  %r = load @g
+  call @putint(%r)
+  ret %r
+}
+"#;
+
+#[test]
+fn removes_dead_instructions_and_blocks() {
+    for n in [0, 3, 10] {
+        check(DEAD, &["dce"], &[n, 9]);
+    }
+    let program = optimize(DEAD, &["dce"]);
+    let main = body(&program, "@main");
+    for gone in ["%unused", "%a =", "%b =", "%v =", "%p ="] {
+        assert!(main.iter().all(|inst| !inst.starts_with(gone)), "{} left in {:#?}", gone, main);
+    }
+    // calls and stores have effects
+    assert!(main.contains(&"%ignored = call @getint()".to_string()));
+    assert_eq!(common::count(&program, "@main", "store"), 1);
+    let text = common::print(&program);
+    assert_eq!(common::print(&optimize(&text, &["dce"])), text);
+}
+
+#[test]
+fn removes_blocks_after_returns() {
+    let source = "int main() { int a = 2; if (a) { return 1; } else { return a; } return 3; }";
+    let before = driver(source, &["-koopa"]);
+    let after = driver(source, &["-koopa", "-passes=dce", "-verify"]);
+    assert!(after.success, "{}", after.stderr);
+    let blocks = |text: &str| text.lines().filter(|line| line.starts_with('%')).count();
+    assert!(blocks(&after.output) < blocks(&before.output), "{}", after.output);
+    assert!(!after.output.contains("ret 3"));
+}
@@ -0,0 +1,52 @@
+mod common;
+
+use common::{compile, parse, SAMPLE};
+use compiler::asm::regalloc::Allocator;
+use compiler::session::Session;
+
+/// A second program to compile in between, with labels of its own.
+const OTHER: &str = r#"
+fun @main(): i32 {
+%entry:
+  jump %a(1)
+
+%a(%x: i32):
+  %c = lt %x, 100
+  br %c, %b, %end
+
+%b:
+  %y = mul %x, 3
+  jump %a(%y)
+
+%end:
+  ret %x
+}
+"#;
+
+#[test]
+fn compiling_twice_gives_the_same_code() {
+    for allocator in [Allocator::LinearScan, Allocator::GraphColoring] {
+        let first = compile(&parse(SAMPLE), allocator);
+        compile(&parse(OTHER), allocator);
+        assert_eq!(compile(&parse(SAMPLE), allocator), first, "{:?}", allocator);
+    }
+}
+
+#[test]
+fn compiled_code_runs() {
+    for n in [0, 3, 20] {
+        common::check(SAMPLE, &[], &[n]);
+    }
+}
+
+#[test]
+fn labels_are_numbered_per_function() {
+    let program = parse(SAMPLE);
+    let (weight, main) = (common::func(&program, "@weight"), common::func(&program, "@main"));
+    let mut session = Session::default();
+    assert_eq!(session.next_bb_name(weight, "%then"), "%then_0");
+    assert_eq!(session.next_bb_name(main, "%then"), "%then_0");
+    assert_eq!(session.next_bb_name(weight, "%else"), "%else_1");
+    assert_eq!(session.next_bb_name(main, "%then"), "%then_1");
+    assert_eq!(Session::default().next_bb_name(weight, "%then"), "%then_0");
+}
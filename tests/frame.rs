@@ -0,0 +1,70 @@
+mod common;
+
+use common::{check, compile};
+use compiler::asm::regalloc::Allocator;
+
+/// A local array of 1000 words in a function called from `main`, written
+/// and read at both ends, so that both the frame and the offsets into it
+/// exceed the 12-bit immediates.
+const LARGE_ARRAY: &str = r#"
+decl @getint(): i32
+decl @putint(i32)
+
+fun @fill(%n: i32): i32 {
+%entry:
+  %a = alloc [i32, 1000]
+  %first = getelemptr %a, 0
+  %last = getelemptr %a, 999
+  store %n, %first
+  %m = mul %n, 3
+  store %m, %last
+  %x = load %first
+  %y = load %last
+  %r = add %x, %y
+  ret %r
+}
+
+fun @main(): i32 {
+%entry:
+  %big = alloc [i32, 1200]
+  %n = call @getint()
+  %p = getelemptr %big, 1100
+  store %n, %p
+  %r = call @fill(%n)
+  call @putint(%r)
+  %q = getelemptr %big, 1100
+  %v = load %q
+  ret %v
+}
+"#;
+
+/// An array indexed by a value only known at run time.
+const INDEXED: &str = r#"
+decl @getint(): i32
+
+fun @main(): i32 {
+%entry:
+  %a = alloc [i32, 800]
+  %i = call @getint()
+  %p = getelemptr %a, %i
+  store 42, %p
+  %j = call @getint()
+  %q = getelemptr %a, %j
+  %v = load %q
+  ret %v
+}
+"#;
+
+#[test]
+fn frames_beyond_the_immediate_range() {
+    check(LARGE_ARRAY, &[], &[7]);
+    let asm = compile(&common::parse(LARGE_ARRAY), Allocator::LinearScan);
+    assert!(asm.contains("add sp, sp, t0"), "frame size should be materialized:\n{}", asm);
+}
+
+#[test]
+fn offsets_beyond_the_immediate_range() {
+    assert_eq!(common::run(&common::parse(INDEXED), &[799, 799]).ret, 42);
+    check(INDEXED, &[], &[799, 799]);
+    check(INDEXED, &[], &[700, 3]);
+}
@@ -0,0 +1,98 @@
+mod common;
+
+use common::{check, optimize, print};
+
+/// Functions `main` reaches and ones it does not: `@unused` runs a loop
+/// over a global table only it reads and calls `@helper`, which nothing
+/// else calls, and most of the library goes unused.
+const PROGRAM: &str = r#"
+decl @getint(): i32
+decl @getch(): i32
+decl @putint(i32)
+decl @putch(i32)
+decl @putarray(i32, *i32)
+
+global @live = alloc i32, zeroinit
+global @table = alloc [i32, 4], {1, 2, 3, 4}
+global @grid = alloc [[i32, 2], 2], {{5, 6}, {7, 8}}
+
+fun @helper(%x: i32): i32 {
+%entry:
+  %c = call @getch()
+  %r = add %x, %c
+  ret %r
+}
+
+fun @unused(%n: i32): i32 {
+%entry:
+  jump %loop(0, 0)
+
+%loop(%i: i32, %s: i32):
+  %c = lt %i, %n
+  br %c, %body, %done(%s)
+
+%body:
+  %p = getelemptr @table, %i
+  %v = load %p
+  %s1 = add %s, %v
+  %big = gt %s1, 5
+  br %big, %done(%s1), %next
+
+%next:
+  %i1 = add %i, 1
+  jump %loop(%i1, %s1)
+
+%done(%r: i32):
+  %h = call @helper(%r)
+  %t = getelemptr @table, 0
+  call @putarray(4, %t)
+  ret %h
+}
+
+fun @add(%x: i32): i32 {
+%entry:
+  %l = load @live
+  %l1 = add %l, %x
+  store %l1, @live
+  %row = getelemptr @grid, 1
+  %cell = getelemptr %row, 0
+  %g = load %cell
+  %r = add %l1, %g
+  ret %r
+}
+
+fun @main(): i32 {
+%entry:
+  %x = call @getint()
+  %a = call @add(%x)
+  %b = call @add(%a)
+  call @putint(%b)
+  ret %b
+}
+"#;
+
+#[test]
+fn removes_what_main_does_not_reach() {
+    for x in [0, 5, -3] {
+        check(PROGRAM, &["globaldce"], &[x]);
+    }
+    let text = print(&optimize(PROGRAM, &["globaldce"]));
+    for gone in ["@helper", "@unused", "@getch", "@putch", "@putarray", "@table", "{1, 2, 3, 4}"] {
+        assert!(!text.contains(gone), "{} in\n{}", gone, text);
+    }
+    let kept = ["decl @getint(): i32", "decl @putint(i32)", "global @live", "global @grid", "fun @add(", "fun @main("];
+    for kept in kept {
+        assert!(text.contains(kept), "{} not in\n{}", kept, text);
+    }
+
+    // nothing calls a function inlined everywhere
+    let program = check(PROGRAM, &["inline", "globaldce"], &[4]);
+    assert!(!print(&program).contains("@add"));
+}
+
+#[test]
+fn leaves_programs_without_main_alone() {
+    let text = PROGRAM.replace("@main", "@start");
+    let program = optimize(&text, &["globaldce"]);
+    assert_eq!(print(&program), print(&common::parse(&text)));
+}
@@ -0,0 +1,96 @@
+mod common;
+
+use common::{body, check, count, optimize};
+
+/// `a*b + a*b` and `b*a` in the entry, the same element of a global array
+/// addressed and loaded twice on either side of a call, which may write it,
+/// and of a store to another array, which cannot; a loop with a `break`
+/// recomputing what its header already has; and two arms computing the
+/// same product, which neither may take from the other.
+const REDUNDANT: &str = r#"
+decl @getint(): i32
+decl @putint(i32)
+
+global @t = alloc [i32, 8], zeroinit
+
+fun @touch(%v: i32) {
+%entry:
+  %p = getelemptr @t, 1
+  store %v, %p
+  ret
+}
+
+fun @main(): i32 {
+%entry:
+  %local = alloc [i32, 8]
+  %a = call @getint()
+  %b = call @getint()
+  %m1 = mul %a, %b
+  %m2 = mul %a, %b
+  %m3 = mul %b, %a
+  %s = add %m1, %m2
+  %s1 = add %s, %m3
+  %k = and %a, 7
+  %p1 = getelemptr @t, %k
+  store %s1, %p1
+  %p2 = getelemptr @t, %k
+  %v1 = load %p2
+  %q = getelemptr %local, 0
+  store %v1, %q
+  %v2 = load %p2
+  call @touch(%v2)
+  %v3 = load %p2
+  %sum = add %v1, %v2
+  %sum1 = add %sum, %v3
+  jump %head(0)
+
+%head(%i: i32):
+  %h = mul %a, %b
+  %c = lt %i, %h
+  br %c, %body, %exit
+
+%body:
+  %h2 = mul %a, %b
+  %stop = gt %i, %h2
+  br %stop, %exit, %next
+
+%next:
+  %i1 = add %i, 1
+  %big = gt %i1, 50
+  br %big, %exit, %head(%i1)
+
+%exit:
+  %c2 = gt %a, 0
+  br %c2, %then, %else
+
+%then:
+  %x = add %a, 100
+  call @putint(%x)
+  jump %end
+
+%else:
+  %y = add %a, 100
+  call @putint(%y)
+  jump %end
+
+%end:
+  ret %sum1
+}
+"#;
+
+#[test]
+fn reuses_equivalent_values() {
+    for input in [[3, 4], [-2, 5], [9, 0], [1, 200]] {
+        check(REDUNDANT, &["gvn"], &input);
+    }
+    let program = optimize(REDUNDANT, &["gvn", "dce"]);
+    let main = body(&program, "@main");
+    // one product in the entry serves the loop too
+    assert_eq!(count(&program, "@main", "mul"), 1, "{:#?}", main);
+    assert_eq!(count(&program, "@main", "getelemptr"), 2, "{:#?}", main);
+    // the load after the store to the other array is the same, the one
+    // after the call is not
+    assert_eq!(count(&program, "@main", "load"), 2, "{:#?}", main);
+    // sibling arms keep their own
+    assert_eq!(main.iter().filter(|inst| inst.ends_with("= add %a, 100")).count(), 2, "{:#?}", main);
+}
@@ -0,0 +1,169 @@
+mod common;
+
+use common::{body, check, func, parse, print};
+use compiler::analysis::callgraph::CallGraph;
+use koopa::ir::Program;
+
+/// Small helpers called in a loop with a `break`: one with two returns, one
+/// swapping array elements through a pointer with a local of its own, one
+/// adding to a global, and a recursive one that must stay a call.
+const HELPERS: &str = r#"
+decl @getint(): i32
+decl @putint(i32)
+
+global @total = alloc i32, zeroinit
+
+fun @max(%a: i32, %b: i32): i32 {
+%entry:
+  %c = gt %a, %b
+  br %c, %first, %second
+
+%first:
+  ret %a
+
+%second:
+  ret %b
+}
+
+fun @swap(%p: *i32, %i: i32, %j: i32) {
+%entry:
+  %tmp = alloc i32
+  %pi = getptr %p, %i
+  %pj = getptr %p, %j
+  %x = load %pi
+  store %x, %tmp
+  %y = load %pj
+  store %y, %pi
+  %t = load %tmp
+  store %t, %pj
+  ret
+}
+
+fun @add_to(%v: i32) {
+%entry:
+  %t = load @total
+  %t1 = add %t, %v
+  store %t1, @total
+  ret
+}
+
+fun @tri(%n: i32): i32 {
+%entry:
+  %z = le %n, 0
+  br %z, %yes, %no
+
+%yes:
+  ret 0
+
+%no:
+  %m = sub %n, 1
+  %r = call @tri(%m)
+  %t = add %r, %n
+  ret %t
+}
+
+fun @main(): i32 {
+%entry:
+  %a = alloc [i32, 4]
+  %a0 = getelemptr %a, 0
+  store 3, %a0
+  %a1 = getelemptr %a, 1
+  store 8, %a1
+  %n = call @getint()
+  jump %loop(0, 0)
+
+%loop(%i: i32, %s: i32):
+  %more = lt %i, %n
+  br %more, %body, %done(%s)
+
+%body:
+  %k = call @getint()
+  %m = call @max(%s, %k)
+  call @add_to(%m)
+  call @swap(%a0, 0, 1)
+  %big = gt %m, 50
+  br %big, %done(%m), %next
+
+%next:
+  %i1 = add %i, 1
+  jump %loop(%i1, %m)
+
+%done(%r: i32):
+  %w = mod %r, 20
+  %e = call @tri(%w)
+  call @putint(%e)
+  %f = load %a0
+  %t = load @total
+  %u = add %r, %f
+  %v = add %u, %t
+  ret %v
+}
+"#;
+
+/// The number of calls to `callee` in the function called `name`.
+fn calls(program: &Program, name: &str, callee: &str) -> usize {
+    let call = format!("call {}(", callee);
+    body(program, name).iter().filter(|inst| inst.contains(&call)).count()
+}
+
+/// `@big`, longer than small functions may be, called `n` times.
+fn big_callee(n: usize) -> String {
+    let mut text = String::from("decl @getint(): i32\n\nfun @big(%x: i32): i32 {\n%entry:\n  %v0 = add %x, 1\n");
+    for i in 1..40 {
+        text += &format!("  %v{} = mul %v{}, 3\n", i, i - 1);
+    }
+    text += "  ret %v39\n}\n\nfun @main(): i32 {\n%entry:\n  %s0 = call @getint()\n";
+    for i in 0..n {
+        text += &format!("  %s{} = call @big(%s{})\n", i + 1, i);
+    }
+    text += &format!("  ret %s{}\n}}\n", n);
+    text
+}
+
+#[test]
+fn inlines_small_helpers() {
+    for input in [vec![0], vec![3, 4, -2, 9], vec![5, 10, 60, 1, 2, 3], vec![2, -5, -9]] {
+        check(HELPERS, &["inline"], &input);
+    }
+    let program = check(HELPERS, &["inline"], &[1, 7]);
+    for helper in ["@max", "@swap", "@add_to"] {
+        assert_eq!(calls(&program, "@main", helper), 0, "{}", print(&program));
+    }
+    // the local of `@swap` is allocated once, in the entry of the caller
+    let main = body(&program, "@main");
+    let entry: Vec<&String> = main.iter().take_while(|inst| !inst.starts_with("jump")).collect();
+    assert_eq!(entry.iter().filter(|inst| inst.ends_with("= alloc i32")).count(), 1);
+}
+
+#[test]
+fn leaves_recursive_functions_alone() {
+    let program = check(HELPERS, &["inline"], &[2, 3, 4]);
+    assert_eq!(calls(&program, "@main", "@tri"), 1);
+    assert_eq!(calls(&program, "@tri", "@tri"), 1);
+}
+
+#[test]
+fn call_graph() {
+    let program = parse(HELPERS);
+    let graph = CallGraph::new(&program);
+    let f = |name| func(&program, name);
+    assert_eq!(graph.callees(f("@main")), [f("@getint"), f("@max"), f("@add_to"), f("@swap"), f("@tri"), f("@putint")]);
+    assert_eq!(graph.call_sites(f("@getint")), 2);
+    assert_eq!(graph.call_sites(f("@max")), 1);
+    assert!(graph.is_recursive(f("@tri")));
+    assert!(!graph.is_recursive(f("@main")) && !graph.is_recursive(f("@max")));
+
+    // callees first
+    let position = |func| graph.sccs().iter().position(|scc| scc.contains(&func)).unwrap();
+    for callee in ["@max", "@swap", "@add_to", "@tri"] {
+        assert!(position(f(callee)) < position(f("@main")), "{}", callee);
+    }
+}
+
+#[test]
+fn inlines_large_functions_called_once() {
+    let program = check(&big_callee(1), &["inline"], &[5]);
+    assert_eq!(calls(&program, "@main", "@big"), 0);
+    let program = check(&big_callee(2), &["inline"], &[5]);
+    assert_eq!(calls(&program, "@main", "@big"), 2);
+}
@@ -0,0 +1,83 @@
+mod common;
+
+use common::{body, check, count, optimize};
+
+/// The identities `instcombine` knows, in `@f`, and comparisons with zero
+/// feeding the branches of a loop with a `break` calling it.
+const IDENTITIES: &str = r#"
+decl @getint(): i32
+
+fun @f(%x: i32): i32 {
+%entry:
+  %a = add %x, 0
+  %b = mul %a, 1
+  %z = mul %b, 0
+  %d = sub %x, %x
+  %e = div %b, 1
+  %n1 = sub 0, %x
+  %n2 = sub 0, %n1
+  %c1 = eq %x, 0
+  %c2 = eq %c1, 0
+  %r1 = add %x, 1
+  %r2 = add %r1, 2
+  %s = add %z, %d
+  %t = add %s, %e
+  %u = add %t, %n2
+  %v = add %u, %c2
+  %w = add %v, %r2
+  ret %w
+}
+
+fun @main(): i32 {
+%entry:
+  %n = call @getint()
+  jump %head(0, 0)
+
+%head(%i: i32, %s: i32):
+  %c = lt %i, %n
+  %c0 = ne %c, 0
+  br %c0, %body, %exit(%s)
+
+%body:
+  %fx = call @f(%i)
+  %s1 = add %s, %fx
+  %neg = sub 0, %s1
+  %pos = sub 0, %neg
+  %stop = gt %pos, 300
+  %stop0 = eq %stop, 0
+  br %stop0, %next, %exit(%s1)
+
+%next:
+  %i1 = add %i, 1
+  %i2 = sub %i1, 0
+  jump %head(%i2, %s1)
+
+%exit(%r: i32):
+  ret %r
+}
+"#;
+
+#[test]
+fn simplifies_identities() {
+    for n in [0, 1, 5, 100] {
+        check(IDENTITIES, &["instcombine"], &[n]);
+    }
+    let program = optimize(IDENTITIES, &["instcombine", "dce"]);
+    for op in ["mul", "div", "sub", "eq"] {
+        assert_eq!(count(&program, "@f", op), 0, "{} in {:#?}", op, body(&program, "@f"));
+    }
+    // `(x == 0) == 0` is `x != 0`, and `(x + 1) + 2` is `x + 3`
+    assert_eq!(count(&program, "@f", "ne"), 1);
+    assert!(body(&program, "@f").iter().any(|inst| inst.ends_with("= add %x, 3")), "{:#?}", body(&program, "@f"));
+}
+
+#[test]
+fn folds_comparisons_with_zero() {
+    let program = optimize(IDENTITIES, &["instcombine", "dce"]);
+    let main = body(&program, "@main");
+    for op in ["eq", "ne", "sub"] {
+        assert_eq!(count(&program, "@main", op), 0, "{} in {:#?}", op, main);
+    }
+    // `(s > 300) == 0` is `s <= 300`
+    assert_eq!((count(&program, "@main", "gt"), count(&program, "@main", "le")), (0, 1));
+}
@@ -0,0 +1,168 @@
+mod common;
+
+use common::{body, check, optimize, print};
+use koopa::ir::Program;
+
+/// Constants passed down a chain of calls, and back by a recursive one:
+/// `@outer` always gets 3 and passes it on to `@scale`, `@pow` always gets
+/// the modulus 1000 and passes it back to itself, while the other
+/// arguments vary. `@store_at` writes through an array it is given.
+const CHAIN: &str = r#"
+decl @getint(): i32
+decl @putint(i32)
+
+global @hits = alloc i32, zeroinit
+
+fun @scale(%x: i32, %k: i32): i32 {
+%entry:
+  %h = load @hits
+  %h1 = add %h, %k
+  store %h1, @hits
+  %r = mul %x, %k
+  ret %r
+}
+
+fun @outer(%x: i32, %k: i32): i32 {
+%entry:
+  %y = add %x, %k
+  %r = call @scale(%y, %k)
+  ret %r
+}
+
+fun @pow(%b: i32, %e: i32, %m: i32): i32 {
+%entry:
+  %z = eq %e, 0
+  br %z, %one, %more
+
+%one:
+  ret 1
+
+%more:
+  %e1 = sub %e, 1
+  %p = call @pow(%b, %e1, %m)
+  %q = mul %p, %b
+  %r = mod %q, %m
+  ret %r
+}
+
+fun @store_at(%a: *i32, %i: i32, %v: i32) {
+%entry:
+  %p = getptr %a, %i
+  store %v, %p
+  ret
+}
+
+fun @main(): i32 {
+%entry:
+  %a = alloc [i32, 4]
+  %a0 = getelemptr %a, 0
+  %x = call @getint()
+  %s = call @outer(%x, 3)
+  %t = call @outer(7, 3)
+  %e = call @getint()
+  %p = call @pow(%x, %e, 1000)
+  call @store_at(%a0, 2, %p)
+  call @store_at(%a0, 1, %s)
+  %a2 = getelemptr %a, 2
+  %v = load %a2
+  %u = add %s, %t
+  %w = add %u, %v
+  %h = load @hits
+  %r = add %w, %h
+  ret %r
+}
+"#;
+
+/// A function called with a constant mode in a loop with a `break`, and
+/// with another one read at run time after it.
+const HOT: &str = r#"
+decl @getint(): i32
+decl @putint(i32)
+
+fun @step(%x: i32, %mode: i32): i32 {
+%entry:
+  %z = eq %mode, 1
+  br %z, %twice, %other
+
+%twice:
+  %d = mul %x, 2
+  ret %d
+
+%other:
+  %s = sub %x, %mode
+  ret %s
+}
+
+fun @main(): i32 {
+%entry:
+  %n = call @getint()
+  jump %loop(0, 1)
+
+%loop(%i: i32, %v: i32):
+  %c = lt %i, %n
+  br %c, %body, %done(%v)
+
+%body:
+  %v1 = call @step(%v, 1)
+  %big = gt %v1, 1000
+  br %big, %done(%v1), %next
+
+%next:
+  %i1 = add %i, 1
+  jump %loop(%i1, %v1)
+
+%done(%r: i32):
+  %m = call @getint()
+  %r1 = call @step(%r, %m)
+  call @putint(%r1)
+  ret %r1
+}
+"#;
+
+/// Whether the function called `name` uses its parameter `param`.
+fn uses(program: &Program, name: &str, param: &str) -> bool {
+    let word = |c: char| c.is_alphanumeric() || c == '%' || c == '_';
+    body(program, name).iter().any(|inst| inst.split(|c| !word(c)).any(|w| w == param))
+}
+
+#[test]
+fn propagates_constant_arguments() {
+    for input in [[4, 3], [-2, 0], [11, 7]] {
+        check(CHAIN, &["ipcp"], &input);
+    }
+    let program = optimize(CHAIN, &["ipcp"]);
+    assert!(!uses(&program, "@outer", "%k"), "{}", print(&program));
+    // only constant once `@outer` passes the constant it is given
+    assert!(!uses(&program, "@scale", "%k"));
+    assert!(!uses(&program, "@pow", "%m"));
+    for (name, param) in [("@scale", "%x"), ("@pow", "%b"), ("@pow", "%e"), ("@store_at", "%i"), ("@store_at", "%v")] {
+        assert!(uses(&program, name, param), "{} {}", name, param);
+    }
+    // the signatures stay as they were
+    assert!(print(&program).contains("fun @scale(%x: i32, %k: i32): i32 {"));
+}
+
+#[test]
+fn specializes_calls_in_loops() {
+    for input in [[0, 5], [4, 1], [30, 2], [3, -1]] {
+        check(HOT, &["ipcp"], &input);
+    }
+    let program = optimize(HOT, &["ipcp"]);
+    let text = print(&program);
+    assert!(text.contains("fun @step_spec0(%x: i32): i32 {"), "{}", text);
+    assert!(!uses(&program, "@step_spec0", "%mode"));
+    let main = body(&program, "@main");
+    assert!(main.iter().any(|inst| inst == "%v1 = call @step_spec0(%v)"), "{:?}", main);
+    assert!(main.iter().any(|inst| inst == "%r1 = call @step(%r, %m)"));
+
+    // no more than four copies of any one function
+    let calls: String = (2..8).map(|m| format!("  %w{} = call @step(%v, {})\n", m, m)).collect();
+    let many = HOT.replace("  %v1 = call @step(%v, 1)\n", &format!("{}  %v1 = add %w7, %w2\n", calls));
+    let program = check(&many, &["ipcp"], &[2, 0]);
+    assert_eq!(print(&program).matches("fun @step_spec").count(), 4);
+
+    // a call outside of loops stays as it was
+    let cold = HOT.replace("%v1 = call @step(%v, 1)", "%v1 = add %v, 1");
+    let program = check(&cold, &["ipcp"], &[3, 1]);
+    assert!(!print(&program).contains("_spec"));
+}
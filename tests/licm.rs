@@ -0,0 +1,106 @@
+mod common;
+
+use common::{check, func, optimize, value};
+use compiler::analysis::dominators::DomTree;
+use compiler::analysis::loops::LoopInfo;
+use koopa::ir::Program;
+
+/// Fills a global matrix in nested loops, breaking out of both once the
+/// running total passes `4 * n`. The row address only depends on the outer
+/// loop, `4 * n` and `@scale` on neither; `@acc` is written in the loop.
+const NESTED: &str = r#"
+decl @getint(): i32
+
+global @m = alloc [[i32, 4], 4], zeroinit
+global @scale = alloc i32, zeroinit
+global @acc = alloc i32, zeroinit
+
+fun @main(): i32 {
+%entry:
+  %n = call @getint()
+  %sc = call @getint()
+  store %sc, @scale
+  jump %outer(0)
+
+%outer(%i: i32):
+  %oc = lt %i, 4
+  br %oc, %inner_pre, %exit
+
+%inner_pre:
+  jump %inner(0)
+
+%inner(%j: i32):
+  %ic = lt %j, 4
+  br %ic, %body, %olatch
+
+%body:
+  %row = getelemptr @m, %i
+  %cell = getelemptr %row, %j
+  %lim = mul %n, 4
+  %s = load @scale
+  %ij = mul %i, %j
+  %v = mul %ij, %s
+  store %v, %cell
+  %a = load @acc
+  %a1 = add %a, %v
+  store %a1, @acc
+  %stop = gt %a1, %lim
+  br %stop, %exit, %ilatch
+
+%ilatch:
+  %j1 = add %j, 1
+  jump %inner(%j1)
+
+%olatch:
+  %i1 = add %i, 1
+  jump %outer(%i1)
+
+%exit:
+  %r = load @acc
+  %c = getelemptr @m, 2
+  %c2 = getelemptr %c, 3
+  %x = load %c2
+  %r1 = add %r, %x
+  ret %r1
+}
+"#;
+
+/// The number of loops around the value called `name` in `@main`.
+fn depth(program: &Program, name: &str) -> u32 {
+    let data = program.func(func(program, "@main"));
+    let info = LoopInfo::new(data, &DomTree::dominators(data));
+    info.depth(data.layout().parent_bb(value(data, name)).unwrap())
+}
+
+#[test]
+fn hoists_invariants_as_far_as_they_go() {
+    for input in [[100, 1], [5, 2], [1000, -3], [0, 0]] {
+        check(NESTED, &["licm"], &input);
+    }
+    let program = optimize(NESTED, &["licm"]);
+    assert_eq!(depth(&program, "%lim"), 0);
+    assert_eq!(depth(&program, "%s"), 0);
+    assert_eq!(depth(&program, "%row"), 1);
+    for stays in ["%cell", "%ij", "%v", "%a", "%a1"] {
+        assert_eq!(depth(&program, stays), 2, "{}", stays);
+    }
+}
+
+#[test]
+fn keeps_loads_of_memory_the_loop_writes() {
+    let text = NESTED.replace("  store %sc, @scale\n", "").replace("  store %v, %cell\n", "  store %v, @scale\n");
+    for input in [[100, 1], [5, 2]] {
+        check(&text, &["licm"], &input);
+    }
+    let program = optimize(&text, &["licm"]);
+    assert_eq!(depth(&program, "%s"), 2);
+    assert_eq!(depth(&program, "%lim"), 0);
+
+    // or may write through a call
+    let bump = "fun @bump(%v: i32) {\n%entry:\n  store %v, @scale\n  ret\n}\n\nfun @main";
+    let text = NESTED.replace("fun @main", bump).replace("  store %v, %cell\n", "  call @bump(%v)\n");
+    for input in [[100, 1], [5, 2]] {
+        check(&text, &["licm"], &input);
+    }
+    assert_eq!(depth(&optimize(&text, &["licm"]), "%s"), 2);
+}
@@ -0,0 +1,115 @@
+mod common;
+
+use common::{block, func, parse, value};
+use compiler::analysis::dataflow::{solve, Dataflow, Direction};
+use compiler::analysis::liveness::Liveness;
+use koopa::ir::{BasicBlock, FunctionData, Value};
+use std::collections::HashSet;
+
+/// Looks for a square root of `k` below `n`, breaking out of the loop when
+/// it finds one.
+const SEARCH: &str = r#"
+decl @getint(): i32
+decl @putint(i32)
+
+fun @main(): i32 {
+%entry:
+  %n = call @getint()
+  %k = call @getint()
+  jump %head(0)
+
+%head(%i: i32):
+  %c = lt %i, %n
+  br %c, %body, %exit
+
+%body:
+  %sq = mul %i, %i
+  %hit = eq %sq, %k
+  br %hit, %found, %next
+
+%next:
+  %i1 = add %i, 1
+  jump %head(%i1)
+
+%found:
+  call @putint(%i)
+  jump %exit
+
+%exit:
+  %r = add %n, %k
+  ret %r
+}
+"#;
+
+/// The names of `values`, sorted.
+fn names(data: &FunctionData, values: &HashSet<Value>) -> Vec<String> {
+    let mut names: Vec<String> = values.iter().map(|v| data.dfg().value(*v).name().clone().unwrap()).collect();
+    names.sort();
+    names
+}
+
+#[test]
+fn values_live_across_a_loop_with_a_break() {
+    let program = parse(SEARCH);
+    let data = program.func(func(&program, "@main"));
+    let liveness = Liveness::new(data);
+    let live_in = |bb: &str| names(data, &liveness.live_in[&block(data, bb)]);
+    let live_out = |bb: &str| names(data, &liveness.live_out[&block(data, bb)]);
+    assert!(live_in("%entry").is_empty());
+    // parameters are defined on entering the block taking them
+    assert_eq!(live_in("%head"), ["%k", "%n"]);
+    assert_eq!(live_out("%head"), ["%i", "%k", "%n"]);
+    assert_eq!(live_in("%body"), ["%i", "%k", "%n"]);
+    // arguments are used by the jump passing them, not past it
+    assert_eq!(live_out("%next"), ["%k", "%n"]);
+    assert_eq!(live_in("%found"), ["%i", "%k", "%n"]);
+    assert_eq!(live_in("%exit"), ["%k", "%n"]);
+    assert!(live_out("%exit").is_empty());
+    // dies in the block defining it
+    let sq = value(data, "%sq");
+    assert!(liveness.live_out.values().all(|live| !live.contains(&sq)));
+}
+
+/// The blocks every path from the entry to a block goes through, itself
+/// included: a forward problem meeting by intersection.
+struct Visited {
+    all: HashSet<BasicBlock>,
+}
+
+impl Dataflow for Visited {
+    type Fact = HashSet<BasicBlock>;
+    const DIRECTION: Direction = Direction::Forward;
+
+    fn boundary(&self, _: &FunctionData) -> Self::Fact {
+        HashSet::new()
+    }
+
+    fn initial(&self, _: &FunctionData) -> Self::Fact {
+        self.all.clone()
+    }
+
+    fn meet(&self, into: &mut Self::Fact, fact: &Self::Fact) {
+        into.retain(|bb| fact.contains(bb));
+    }
+
+    fn transfer(&self, _: &FunctionData, bb: BasicBlock, fact: &Self::Fact) -> Self::Fact {
+        let mut out = fact.clone();
+        out.insert(bb);
+        out
+    }
+}
+
+#[test]
+fn solves_forward_problems() {
+    let program = parse(SEARCH);
+    let data = program.func(func(&program, "@main"));
+    let all = data.layout().bbs().keys().copied().collect();
+    let solution = solve(data, &Visited { all });
+    let visited = |bb: &str| -> HashSet<BasicBlock> { solution.exit[&block(data, bb)].clone() };
+    let blocks = |names: &[&str]| -> HashSet<BasicBlock> { names.iter().map(|n| block(data, n)).collect() };
+    assert_eq!(visited("%entry"), blocks(&["%entry"]));
+    assert_eq!(visited("%next"), blocks(&["%entry", "%head", "%body", "%next"]));
+    assert_eq!(visited("%found"), blocks(&["%entry", "%head", "%body", "%found"]));
+    assert_eq!(visited("%exit"), blocks(&["%entry", "%head", "%exit"]));
+    assert!(solution.entry[&block(data, "%head")].contains(&block(data, "%entry")));
+}
@@ -0,0 +1,149 @@
+mod common;
+
+use common::{body, check, optimize, print};
+use koopa::ir::Program;
+
+/// Globals used in different ways: `@steps` and `@seed` only by `main`,
+/// around a loop with a `break`; `@once` by a function `main` calls once;
+/// `@scratch` by a function called in the loop, which writes it before
+/// reading it, and `@acc` by one reading what the last call left. `@shared`
+/// is used by two functions, `@passed` has its address taken and `@arr`
+/// is an array.
+const GLOBALS: &str = r#"
+decl @getint(): i32
+decl @putint(i32)
+
+global @steps = alloc i32, zeroinit
+global @seed = alloc i32, 5
+global @once = alloc i32, 2
+global @scratch = alloc i32, zeroinit
+global @acc = alloc i32, zeroinit
+global @shared = alloc i32, zeroinit
+global @passed = alloc i32, 1
+global @arr = alloc [i32, 2], zeroinit
+
+fun @peek(%p: *i32): i32 {
+%entry:
+  %v = load %p
+  ret %v
+}
+
+fun @setup(%x: i32): i32 {
+%entry:
+  %o = load @once
+  %o1 = mul %o, %x
+  store %o1, @once
+  %o2 = load @once
+  store %x, @shared
+  ret %o2
+}
+
+fun @square(%x: i32): i32 {
+%entry:
+  store %x, @scratch
+  %a = load @scratch
+  %b = mul %a, %a
+  store %b, @scratch
+  %c = load @scratch
+  ret %c
+}
+
+fun @total(%x: i32): i32 {
+%entry:
+  %a = load @acc
+  %a1 = add %a, %x
+  store %a1, @acc
+  ret %a1
+}
+
+fun @main(): i32 {
+%entry:
+  %n = call @getint()
+  %s = call @setup(%n)
+  %p = call @peek(@passed)
+  %q = getelemptr @arr, 1
+  store %n, %q
+  jump %loop(0)
+
+%loop(%i: i32):
+  %c = lt %i, %n
+  br %c, %body, %done
+
+%body:
+  %st = load @steps
+  %st1 = add %st, 1
+  store %st1, @steps
+  %sd = load @seed
+  %sq = call @square(%sd)
+  %m = mod %sq, 97
+  store %m, @seed
+  %t = call @total(%m)
+  %big = gt %t, 300
+  br %big, %done, %next
+
+%next:
+  %i1 = add %i, 1
+  jump %loop(%i1)
+
+%done:
+  %r0 = load @steps
+  %r1 = load @seed
+  %sh = load @shared
+  %a = load %q
+  %r2 = add %r0, %r1
+  %r3 = add %r2, %sh
+  %r4 = add %r3, %a
+  %r5 = add %r4, %s
+  %r6 = add %r5, %p
+  call @putint(%r6)
+  ret %r6
+}
+"#;
+
+/// Whether the function called `name` still refers to `global`.
+fn refers(program: &Program, name: &str, global: &str) -> bool {
+    body(program, name).iter().any(|inst| inst.contains(global))
+}
+
+#[test]
+fn localizes_globals_of_one_function() {
+    for n in [0, 1, 4, 30] {
+        check(GLOBALS, &["localize"], &[n]);
+    }
+    let program = optimize(GLOBALS, &["localize"]);
+    for (name, global) in [("@main", "@steps"), ("@main", "@seed"), ("@setup", "@once"), ("@square", "@scratch")] {
+        assert!(!refers(&program, name, global), "{} in {}:\n{}", global, name, print(&program));
+    }
+    // the local starts from the initial value
+    let main = body(&program, "@main");
+    assert!(main.iter().any(|inst| inst.starts_with("store 5, %")), "{:?}", main);
+}
+
+#[test]
+fn keeps_globals_calls_share() {
+    let program = optimize(GLOBALS, &["localize"]);
+    for (name, global) in [("@total", "@acc"), ("@setup", "@shared"), ("@main", "@shared"), ("@main", "@passed")] {
+        assert!(refers(&program, name, global), "{} not in {}", global, name);
+    }
+    assert!(refers(&program, "@main", "@arr"));
+
+    // called in the loop, `@setup` no longer runs once
+    let text = GLOBALS
+        .replace("  %s = call @setup(%n)\n", "  %s = add %n, 0\n")
+        .replace("  %t = call @total(%m)\n", "  %t = call @total(%m)\n  %u = call @setup(%m)\n");
+    let program = check(&text, &["localize"], &[3]);
+    assert!(refers(&program, "@setup", "@once"));
+}
+
+#[test]
+fn localized_globals_become_registers() {
+    let passes = ["localize", "mem2reg", "globaldce"];
+    for n in [0, 2, 9] {
+        check(GLOBALS, &passes, &[n]);
+    }
+    let text = print(&optimize(GLOBALS, &passes));
+    for gone in ["@steps", "@seed", "@once", "@scratch"] {
+        assert!(!text.contains(gone), "{} in\n{}", gone, text);
+    }
+    assert!(text.contains("global @acc"));
+}
@@ -0,0 +1,173 @@
+mod common;
+
+use common::{block, check, func, parse};
+use compiler::analysis::dominators::DomTree;
+use compiler::analysis::loops::LoopInfo;
+use koopa::ir::{BasicBlock, FunctionData};
+use std::collections::HashSet;
+
+/// Nested loops summing `j` for `j < i < n`, breaking out of both once the
+/// sum passes 1000, with an early return for negative `n`. `@spin` enters
+/// its loop from two blocks.
+const NEST: &str = r#"
+decl @getint(): i32
+
+fun @spin(%n: i32): i32 {
+%entry:
+  %odd = and %n, 1
+  br %odd, %h(%n), %even
+
+%even:
+  %m = add %n, 1
+  jump %h(%m)
+
+%h(%k: i32):
+  %c = gt %k, 0
+  %k1 = sub %k, 2
+  br %c, %h(%k1), %end
+
+%end:
+  ret %k
+}
+
+fun @main(): i32 {
+%entry:
+  %n = call @getint()
+  %neg = lt %n, 0
+  br %neg, %bail, %outer_pre
+
+%outer_pre:
+  jump %outer(0, 0)
+
+%outer(%i: i32, %s: i32):
+  %oc = lt %i, %n
+  br %oc, %inner_pre, %done(%s)
+
+%inner_pre:
+  jump %inner(0, %s)
+
+%inner(%j: i32, %t: i32):
+  %ic = lt %j, %i
+  br %ic, %inner_body, %outer_latch
+
+%inner_body:
+  %t1 = add %t, %j
+  %j1 = add %j, 1
+  %big = gt %t1, 1000
+  br %big, %done(%t1), %inner(%j1, %t1)
+
+%outer_latch:
+  %i1 = add %i, 1
+  jump %outer(%i1, %t)
+
+%done(%r: i32):
+  %x = call @spin(%r)
+  ret %x
+
+%bail:
+  ret -1
+}
+"#;
+
+/// The blocks called `names`.
+fn blocks(data: &FunctionData, names: &[&str]) -> HashSet<BasicBlock> {
+    names.iter().map(|name| block(data, name)).collect()
+}
+
+#[test]
+fn dominators_and_frontiers() {
+    let program = parse(NEST);
+    let data = program.func(func(&program, "@main"));
+    let bb = |name| block(data, name);
+    let dom = DomTree::dominators(data);
+    assert_eq!(dom.roots(), [bb("%entry")]);
+    assert_eq!(dom.idom(bb("%entry")), None);
+    for (child, parent) in [
+        ("%bail", "%entry"),
+        ("%outer", "%outer_pre"),
+        ("%done", "%outer"),
+        ("%inner", "%inner_pre"),
+        ("%inner_body", "%inner"),
+        ("%outer_latch", "%inner"),
+    ] {
+        assert_eq!(dom.idom(bb(child)), Some(bb(parent)), "{}", child);
+    }
+    assert!(dom.dominates(bb("%outer"), bb("%inner_body")));
+    assert!(dom.dominates(bb("%done"), bb("%done")));
+    assert!(!dom.strictly_dominates(bb("%done"), bb("%done")));
+    assert!(!dom.dominates(bb("%inner"), bb("%done")));
+
+    let frontiers = dom.frontiers();
+    let frontier = |name| frontiers[&bb(name)].iter().copied().collect::<HashSet<_>>();
+    assert_eq!(frontier("%outer_latch"), blocks(data, &["%outer"]));
+    assert_eq!(frontier("%inner_body"), blocks(data, &["%inner", "%done"]));
+    assert_eq!(frontier("%inner"), blocks(data, &["%inner", "%outer", "%done"]));
+    assert_eq!(frontier("%outer"), blocks(data, &["%outer"]));
+    assert!(frontier("%entry").is_empty());
+}
+
+#[test]
+fn post_dominators() {
+    let program = parse(NEST);
+    let data = program.func(func(&program, "@main"));
+    let bb = |name| block(data, name);
+    let post = DomTree::post_dominators(data);
+    // the two returns have nothing in common, so the entry hangs off the
+    // virtual root with them
+    let roots = blocks(data, &["%done", "%bail", "%entry"]);
+    assert_eq!(post.roots().iter().copied().collect::<HashSet<_>>(), roots);
+    assert_eq!(post.idom(bb("%entry")), None);
+    assert_eq!(post.idom(bb("%inner_body")), Some(bb("%done")));
+    assert_eq!(post.idom(bb("%outer_latch")), Some(bb("%outer")));
+    assert_eq!(post.idom(bb("%inner_pre")), Some(bb("%inner")));
+    // the branches `%inner_body` is control dependent on
+    let frontiers = post.frontiers();
+    assert_eq!(frontiers[&bb("%inner_body")].iter().copied().collect::<HashSet<_>>(), blocks(data, &["%inner"]));
+}
+
+#[test]
+fn nested_loops() {
+    let program = parse(NEST);
+    let data = program.func(func(&program, "@main"));
+    let bb = |name| block(data, name);
+    let info = LoopInfo::new(data, &DomTree::dominators(data));
+    assert_eq!(info.loops.len(), 2);
+    // inner loops come first
+    let (inner, outer) = (&info.loops[0], &info.loops[1]);
+    assert_eq!(inner.header, bb("%inner"));
+    assert_eq!(inner.blocks.iter().copied().collect::<HashSet<_>>(), blocks(data, &["%inner", "%inner_body"]));
+    assert_eq!(inner.latches, [bb("%inner_body")]);
+    assert_eq!(inner.exits.iter().copied().collect::<HashSet<_>>(), blocks(data, &["%outer_latch", "%done"]));
+    assert_eq!(inner.preheader, Some(bb("%inner_pre")));
+    assert_eq!((inner.parent, inner.depth), (Some(1), 2));
+
+    assert_eq!(outer.header, bb("%outer"));
+    assert_eq!(outer.blocks[0], bb("%outer"));
+    assert_eq!(
+        outer.blocks.iter().copied().collect::<HashSet<_>>(),
+        blocks(data, &["%outer", "%inner_pre", "%inner", "%inner_body", "%outer_latch"])
+    );
+    assert_eq!(outer.latches, [bb("%outer_latch")]);
+    assert_eq!(outer.exits, [bb("%done")]);
+    assert_eq!(outer.preheader, Some(bb("%outer_pre")));
+    assert_eq!((outer.parent, outer.children.as_slice(), outer.depth), (None, &[0][..], 1));
+
+    assert_eq!(info.depth(bb("%inner_body")), 2);
+    assert_eq!(info.depth(bb("%outer_latch")), 1);
+    assert_eq!(info.depth(bb("%done")), 0);
+    assert_eq!(info.innermost(bb("%inner_pre")), Some(1));
+}
+
+#[test]
+fn loops_entered_twice_have_no_preheader() {
+    let program = parse(NEST);
+    let data = program.func(func(&program, "@spin"));
+    let info = LoopInfo::new(data, &DomTree::dominators(data));
+    assert_eq!(info.loops.len(), 1);
+    let l = &info.loops[0];
+    assert_eq!((l.header, l.latches.as_slice()), (block(data, "%h"), &[block(data, "%h")][..]));
+    assert_eq!(l.preheader, None);
+    for n in [-3, 0, 7, 400] {
+        check(NEST, &[], &[n]);
+    }
+}
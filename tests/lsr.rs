@@ -0,0 +1,151 @@
+mod common;
+
+use common::{check, func, optimize, parse, print};
+use compiler::analysis::dominators::DomTree;
+use compiler::analysis::loops::LoopInfo;
+use koopa::ir::{BinaryOp, Program, ValueKind};
+
+/// Fills `a[i * n + j]` of a global array in nested loops, breaking out of
+/// both once the running total passes the limit read after `n`.
+const MATRIX: &str = r#"
+decl @getint(): i32
+decl @putint(i32)
+
+global @a = alloc [i32, 64], zeroinit
+
+fun @main(): i32 {
+%entry:
+  %n = call @getint()
+  %lim = call @getint()
+  jump %outer(0, 0)
+
+%outer(%i: i32, %s: i32):
+  %oc = lt %i, 8
+  br %oc, %inner_pre, %exit(%s)
+
+%inner_pre:
+  jump %inner(0, %s)
+
+%inner(%j: i32, %t: i32):
+  %ic = lt %j, %n
+  br %ic, %body, %olatch(%t)
+
+%body:
+  %row = mul %i, %n
+  %idx = add %row, %j
+  %p = getelemptr @a, %idx
+  %v = mul %j, 3
+  %w = add %v, %i
+  store %w, %p
+  %t1 = add %t, %w
+  %stop = gt %t1, %lim
+  br %stop, %exit(%t1), %ilatch
+
+%ilatch:
+  %j1 = add %j, 1
+  jump %inner(%j1, %t1)
+
+%olatch(%u: i32):
+  %i1 = add %i, 1
+  jump %outer(%i1, %u)
+
+%exit(%r: i32):
+  %q = getelemptr @a, 9
+  %x = load %q
+  %y = add %r, %x
+  call @putint(%y)
+  ret %y
+}
+"#;
+
+/// Writes a product by a value read at run time, with a variable stepping
+/// by 2, into a row of a local two-dimensional array, calling out on every
+/// iteration.
+const ROWS: &str = r#"
+decl @getint(): i32
+decl @putint(i32)
+
+fun @main(): i32 {
+%entry:
+  %a = alloc [[i32, 3], 6]
+  %k = call @getint()
+  jump %loop(0)
+
+%loop(%i: i32):
+  %c = lt %i, 12
+  br %c, %body, %exit
+
+%body:
+  %m = mul %i, %k
+  %h = div %i, 2
+  %row = getelemptr %a, %h
+  %cell = getelemptr %row, 2
+  store %m, %cell
+  call @putint(%m)
+  %i1 = add %i, 2
+  jump %loop(%i1)
+
+%exit:
+  %r = getelemptr %a, 5
+  %l = getelemptr %r, 2
+  %v = load %l
+  ret %v
+}
+"#;
+
+/// The deepest loop nesting of an instruction `pick` selects in `@main`.
+fn deepest(program: &Program, pick: impl Fn(&ValueKind) -> bool) -> u32 {
+    let data = program.func(func(program, "@main"));
+    let info = LoopInfo::new(data, &DomTree::dominators(data));
+    let mut depth = 0;
+    for (bb, node) in data.layout().bbs() {
+        if node.insts().keys().any(|inst| pick(data.dfg().value(*inst).kind())) {
+            depth = depth.max(info.depth(*bb));
+        }
+    }
+    depth
+}
+
+fn is_mul(kind: &ValueKind) -> bool {
+    matches!(kind, ValueKind::Binary(b) if b.op() == BinaryOp::Mul)
+}
+
+#[test]
+fn products_become_sums() {
+    for input in [[8, 1000], [3, 20], [1, 0], [0, 5], [5, 70]] {
+        check(MATRIX, &["lsr"], &input);
+        check(MATRIX, &["licm", "lsr"], &input);
+    }
+    let before = parse(MATRIX);
+    assert_eq!(deepest(&before, is_mul), 2);
+    // the inner loop only sees the row as invariant once it is hoisted,
+    // and then steps a pointer through it; what the loops start from is
+    // computed in their preheaders
+    let after = optimize(MATRIX, &["licm", "lsr"]);
+    assert!(deepest(&after, is_mul) < 2, "{}", print(&after));
+    assert_eq!(deepest(&after, |kind| matches!(kind, ValueKind::GetElemPtr(_))), 0, "{}", print(&after));
+}
+
+#[test]
+fn steps_by_invariant_values_and_rows() {
+    for k in [3, -7, 0, 100000] {
+        check(ROWS, &["lsr"], &[k]);
+    }
+    let program = optimize(ROWS, &["lsr"]);
+    assert_eq!(deepest(&program, is_mul), 0, "{}", print(&program));
+    // the division is no recurrence, so neither is the row it picks
+    assert_eq!(deepest(&program, |kind| matches!(kind, ValueKind::GetElemPtr(_))), 1);
+
+    let text = ROWS.replace("%h = div %i, 2", "%h = shr %i, 1").replace("%c = lt %i, 12", "%c = lt %i, 11");
+    check(&text, &["lsr"], &[9]);
+}
+
+#[test]
+fn leaves_sums_alone() {
+    let text = ROWS
+        .replace("%m = mul %i, %k", "%m = add %i, %k")
+        .replace("%row = getelemptr %a, %h", "%row = getelemptr %a, 1");
+    let program = optimize(&text, &["lsr"]);
+    assert_eq!(print(&program), print(&parse(&text)));
+    check(&text, &["lsr"], &[4]);
+}
@@ -0,0 +1,58 @@
+mod common;
+
+use common::{check, parse, print, FRONTEND, SAMPLE};
+use compiler::opt::manager::PassManager;
+use compiler::opt::{pass_names, pipeline};
+use compiler::session::Options;
+
+#[test]
+fn rejects_unknown_passes() {
+    let options = Options {
+        passes: Some(vec!["dce".to_string(), "nope".to_string()]),
+        ..Options::default()
+    };
+    assert_eq!(PassManager::from_options(&options).err().unwrap(), "unknown pass `nope`");
+}
+
+#[test]
+fn pipelines_name_registered_passes() {
+    for level in 0..=3 {
+        for name in pipeline(level) {
+            assert!(pass_names().any(|n| n == name), "{} at -O{}", name, level);
+        }
+    }
+    assert!(pipeline(0).is_empty());
+    assert_eq!(pipeline(3), pipeline(2));
+}
+
+#[test]
+fn o0_leaves_the_program_alone() {
+    let mut program = parse(FRONTEND);
+    let before = print(&program);
+    PassManager::from_options(&Options::default()).unwrap().run(&mut program).unwrap();
+    assert_eq!(print(&program), before);
+}
+
+#[test]
+fn passes_replace_the_pipeline() {
+    let options = Options {
+        opt_level: 2,
+        passes: Some(vec!["mem2reg".to_string()]),
+        ..Options::default()
+    };
+    let mut program = parse(FRONTEND);
+    PassManager::from_options(&options).unwrap().run(&mut program).unwrap();
+    // the loads of `%i` and `%n` are gone, the array and the global stay
+    assert_eq!(common::count(&program, "@main", "alloc"), 1);
+    assert_eq!(common::count(&program, "@main", "store"), 2);
+}
+
+#[test]
+fn optimization_levels_keep_behavior() {
+    for level in 1..=2 {
+        for n in [0, 1, 6, 30] {
+            check(FRONTEND, &pipeline(level), &[n]);
+            check(SAMPLE, &pipeline(level), &[n]);
+        }
+    }
+}
@@ -0,0 +1,124 @@
+mod common;
+
+use common::{body, check, optimize, print};
+
+/// Loads and stores of a local array and globals `mem2reg` cannot promote,
+/// around calls, a diamond, and a loop with a `break` writing the array
+/// through an index it computes.
+const MEMORY: &str = r#"
+decl @getint(): i32
+decl @putint(i32)
+
+global @g = alloc i32, zeroinit
+global @h = alloc i32, zeroinit
+
+fun @write_h(%v: i32) {
+%entry:
+  store %v, @h
+  ret
+}
+
+fun @main(): i32 {
+%entry:
+  %a = alloc [i32, 4]
+  %x = call @getint()
+  %p2 = getelemptr %a, 2
+  %p3 = getelemptr %a, 3
+  store 1, %p2
+  store %x, %p2
+  store 7, %p3
+  %v2 = load %p2
+  %v3 = load %p3
+  store %x, @g
+  %g1 = load @g
+  %h1 = load @h
+  %h2 = load @h
+  call @write_h(%v2)
+  %h3 = load @h
+  %c = gt %x, 0
+  br %c, %then, %else
+
+%then:
+  store %x, @g
+  jump %join
+
+%else:
+  store %x, @g
+  jump %join
+
+%join:
+  %g2 = load @g
+  jump %loop(0)
+
+%loop(%i: i32):
+  %k = lt %i, 4
+  br %k, %body, %done
+
+%body:
+  %pi = getelemptr %a, %i
+  store %i, %pi
+  %cur = load %p2
+  %s = load @g
+  %s1 = add %s, %cur
+  store %s1, @g
+  %big = gt %s1, 50
+  br %big, %done, %next
+
+%next:
+  %i1 = add %i, 1
+  jump %loop(%i1)
+
+%done:
+  %r = load @g
+  %f = load %p3
+  store 0, %p3
+  %t1 = add %r, %f
+  %t2 = add %t1, %v3
+  %t3 = add %t2, %g1
+  %t4 = add %t3, %h2
+  %t5 = add %t4, %h3
+  %t6 = add %t5, %g2
+  call @putint(%t6)
+  ret %t6
+}
+"#;
+
+#[test]
+fn forwards_what_memory_holds() {
+    for x in [0, 3, -8, 40] {
+        check(MEMORY, &["memopt"], &[x]);
+    }
+    let program = optimize(MEMORY, &["memopt"]);
+    let main = body(&program, "@main");
+    let defines = |name: &str| main.iter().any(|inst| inst.starts_with(&format!("{} = ", name)));
+    // known on every path to them
+    for gone in ["%v2", "%v3", "%g1", "%h2", "%g2"] {
+        assert!(!defines(gone), "{} in\n{}", gone, print(&program));
+    }
+    // after a call writing it, through a store to any element, and after
+    // the loop
+    for kept in ["%h1", "%h3", "%cur", "%s", "%r", "%f"] {
+        assert!(defines(kept), "{} not in\n{}", kept, print(&program));
+    }
+    assert!(main.iter().any(|inst| inst == "%t2 = add %t1, 7"));
+    assert!(main.iter().any(|inst| inst == "call @write_h(%x)"));
+}
+
+#[test]
+fn removes_stores_never_read() {
+    let program = optimize(MEMORY, &["memopt"]);
+    let main = body(&program, "@main");
+    // overwritten, and left in the frame on returning
+    assert!(!main.iter().any(|inst| inst == "store 1, %p2"));
+    assert!(!main.iter().any(|inst| inst == "store 0, %p3"), "{:?}", main);
+    // read by the loop, or still there once `main` returns
+    for kept in ["store %x, %p2", "store 7, %p3", "store %s1, @g", "store %i, %pi"] {
+        assert!(main.iter().any(|inst| inst == kept), "{} not in {:?}", kept, main);
+    }
+    assert_eq!(main.iter().filter(|inst| inst.as_str() == "store %x, @g").count(), 3);
+
+    // the last store to a global is there for the caller to read
+    let text = MEMORY.replace("  store %v, @h\n", "  store 1, @h\n  store %v, @h\n");
+    let program = check(&text, &["memopt"], &[2]);
+    assert_eq!(body(&program, "@write_h"), ["store %v, @h", "ret"]);
+}
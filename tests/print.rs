@@ -0,0 +1,52 @@
+mod common;
+
+use common::driver;
+use compiler::opt::manager::PassManager;
+use compiler::opt::pipeline;
+use compiler::session::Options;
+
+const SOURCE: &str = "int main() { int a = 3; int b = a * 2; if (b > 5) { a = a + b; } else { a = 0; } return a; }";
+
+/// The passes whose dumps `stderr` holds, in order.
+fn dumps(stderr: &str) -> Vec<&str> {
+    stderr.lines().filter_map(|line| line.strip_prefix("// *** IR Dump After ")?.strip_suffix(" ***")).collect()
+}
+
+#[test]
+fn prints_after_every_pass() {
+    let run = driver(SOURCE, &["-koopa", "-O1", "-print-after-all"]);
+    assert!(run.success, "{}", run.stderr);
+    assert_eq!(dumps(&run.stderr), pipeline(1));
+    // each dump is the program as it stands, the last one what is written
+    let last = run.stderr.rfind("// *** IR Dump After dce ***\n").unwrap();
+    assert!(run.stderr[last..].ends_with(&run.output));
+}
+
+#[test]
+fn prints_after_selected_passes() {
+    let run = driver(SOURCE, &["-koopa", "-O2", "-print-after=sccp", "-print-after=dce"]);
+    assert!(run.success, "{}", run.stderr);
+    assert_eq!(dumps(&run.stderr), ["sccp", "sccp", "sccp", "dce"]);
+    assert!(dumps(&driver(SOURCE, &["-koopa", "-O2"]).stderr).is_empty());
+}
+
+#[test]
+fn prints_only_changes() {
+    let run = driver(SOURCE, &["-koopa", "-passes=mem2reg,mem2reg,dce", "-print-changed"]);
+    assert!(run.success, "{}", run.stderr);
+    assert_eq!(dumps(&run.stderr), ["mem2reg"]);
+    let run = driver(SOURCE, &["-koopa", "-passes=mem2reg,mem2reg", "-print-changed", "-print-after=mem2reg"]);
+    assert_eq!(dumps(&run.stderr), ["mem2reg"]);
+}
+
+#[test]
+fn rejects_unknown_passes_to_print() {
+    let options = Options {
+        print_after: vec!["nope".to_string()],
+        ..Options::default()
+    };
+    assert_eq!(PassManager::from_options(&options).err().unwrap(), "unknown pass `nope` in -print-after");
+    let run = driver(SOURCE, &["-koopa", "-print-after=nope"]);
+    assert!(!run.success);
+    assert!(run.stderr.contains("error: unknown pass `nope` in -print-after"));
+}
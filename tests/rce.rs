@@ -0,0 +1,158 @@
+mod common;
+
+use common::{body, check, func, optimize, parse, print};
+use compiler::analysis::effects::{Effect, Effects};
+use koopa::ir::Program;
+
+/// Functions doing more and more besides computing their result, called
+/// from `main` again and again, and in a loop with a `break`.
+const CALLS: &str = r#"
+decl @getint(): i32
+decl @putint(i32)
+
+global @g = alloc i32, zeroinit
+
+fun @sq(%x: i32): i32 {
+%entry:
+  %r = mul %x, %x
+  ret %r
+}
+
+fun @fact(%n: i32): i32 {
+%entry:
+  %z = le %n, 1
+  br %z, %one, %more
+
+%one:
+  ret 1
+
+%more:
+  %m = sub %n, 1
+  %f = call @fact(%m)
+  %r = mul %f, %n
+  ret %r
+}
+
+fun @local_sum(%x: i32): i32 {
+%entry:
+  %a = alloc [i32, 2]
+  %a0 = getelemptr %a, 0
+  store %x, %a0
+  %a1 = getelemptr %a, 1
+  %y = add %x, 1
+  store %y, %a1
+  %v0 = load %a0
+  %v1 = load %a1
+  %r = add %v0, %v1
+  ret %r
+}
+
+fun @peek(): i32 {
+%entry:
+  %v = load @g
+  ret %v
+}
+
+fun @bump(%x: i32) {
+%entry:
+  store %x, @g
+  ret
+}
+
+fun @twice(%x: i32) {
+%entry:
+  call @bump(%x)
+  call @bump(%x)
+  ret
+}
+
+fun @main(): i32 {
+%entry:
+  %k = call @getint()
+  %unused = call @sq(%k)
+  %s1 = call @sq(%k)
+  %p1 = call @peek()
+  %p2 = call @peek()
+  call @twice(%s1)
+  %p3 = call @peek()
+  %f = call @fact(%k)
+  jump %loop(0, 0)
+
+%loop(%i: i32, %acc: i32):
+  %lim = call @local_sum(%k)
+  %c = lt %i, %lim
+  br %c, %body, %done(%acc)
+
+%body:
+  %q = call @sq(%i)
+  %acc1 = add %acc, %q
+  %big = gt %acc1, 1000
+  br %big, %done(%acc1), %next
+
+%next:
+  %i1 = add %i, 1
+  jump %loop(%i1, %acc1)
+
+%done(%r: i32):
+  %s2 = call @sq(%k)
+  %t1 = add %r, %s2
+  %t2 = add %t1, %p1
+  %t3 = add %t2, %p2
+  %t4 = add %t3, %p3
+  %t5 = add %t4, %f
+  call @putint(%t5)
+  ret %t5
+}
+"#;
+
+/// The calls to `callee` in `@main`, as Koopa text prints them.
+fn calls(program: &Program, callee: &str) -> Vec<String> {
+    let call = format!("call {}(", callee);
+    body(program, "@main").into_iter().filter(|inst| inst.contains(&call)).collect()
+}
+
+#[test]
+fn effects_of_functions() {
+    let program = parse(CALLS);
+    let effects = Effects::new(&program);
+    let of = |name| effects.of(func(&program, name));
+    for pure in ["@sq", "@fact", "@local_sum"] {
+        assert_eq!(of(pure), Effect::Pure, "{}", pure);
+    }
+    assert_eq!(of("@peek"), Effect::ReadOnly);
+    for name in ["@bump", "@twice", "@main", "@getint", "@putint"] {
+        assert_eq!(of(name), Effect::SideEffects, "{}", name);
+    }
+}
+
+#[test]
+fn removes_and_reuses_calls() {
+    for k in [0, 3, 7, 40] {
+        check(CALLS, &["rce"], &[k]);
+    }
+    let program = optimize(CALLS, &["rce"]);
+    // the unused call and the one repeating `%s1` are gone
+    assert_eq!(calls(&program, "@sq"), ["%s1 = call @sq(%k)", "%q = call @sq(%i)"], "{}", print(&program));
+    // the memory `@peek` reads changes between `%p2` and `%p3`
+    assert_eq!(calls(&program, "@peek"), ["%p1 = call @peek()", "%p3 = call @peek()"]);
+    assert_eq!(calls(&program, "@twice").len(), 1);
+}
+
+#[test]
+fn hoists_pure_calls_out_of_loops() {
+    let program = optimize(CALLS, &["rce"]);
+    let main = body(&program, "@main");
+    let entry: Vec<&String> = main.iter().take_while(|inst| !inst.starts_with("jump")).collect();
+    assert!(entry.iter().any(|inst| inst.as_str() == "%lim = call @local_sum(%k)"), "{:?}", main);
+    // past the `break`, not every iteration makes it
+    assert!(!entry.iter().any(|inst| inst.contains("call @sq(%i)")));
+
+    // a read-only call stays, as what it reads is written in the loop
+    let text = CALLS.replace("  %lim = call @local_sum(%k)\n", "  %lim = call @peek()\n  call @bump(%i)\n");
+    for k in [0, 3] {
+        check(&text, &["rce"], &[k]);
+    }
+    let main = body(&optimize(&text, &["rce"]), "@main");
+    let at = main.iter().position(|inst| inst == "%lim = call @peek()").unwrap();
+    assert!(main[..at].iter().any(|inst| inst.starts_with("jump %loop")), "{:?}", main);
+}
@@ -0,0 +1,134 @@
+mod common;
+
+use common::{check, func, parse};
+use compiler::asm::liveness::{intervals, liveness, Numbering};
+use compiler::asm::reg::is_callee_saved;
+use compiler::asm::regalloc::{Allocator, Loc};
+use koopa::ir::Program;
+
+/// `main` reading `n` values, printing in between so that they live across
+/// calls, then summing them weighted by their position on every one of
+/// three trips around a loop.
+fn pressure(n: usize) -> String {
+    let mut text = String::from("decl @getint(): i32\ndecl @putint(i32)\n\nfun @main(): i32 {\n%entry:\n");
+    for i in 0..n {
+        text += &format!("  %v{} = call @getint()\n", i);
+        if i % 8 == 7 {
+            text += &format!("  call @putint(%v{})\n", i);
+        }
+    }
+    text += "  jump %loop(0, 0)\n\n%loop(%k: i32, %acc: i32):\n  %more = lt %k, 3\n  br %more, %body, %end\n\n%body:\n";
+    text += "  %s0 = add %acc, %k\n";
+    for i in 0..n {
+        text += &format!("  %m{} = mul %v{}, {}\n  %s{} = add %s{}, %m{}\n", i, i, i + 1, i + 1, i, i);
+    }
+    text += &format!("  %k1 = add %k, 1\n  jump %loop(%k1, %s{})\n\n%end:\n  ret %acc\n}}\n", n);
+    text
+}
+
+/// Values live across calls to a function that wants many registers of its
+/// own, which it has to give back.
+const CALLS: &str = r#"
+decl @getint(): i32
+
+fun @mix(%a: i32, %b: i32, %c: i32): i32 {
+%entry:
+  %x = mul %a, %b
+  %y = add %x, %c
+  %z = sub %y, %a
+  %w = mul %z, %b
+  %u = add %w, %x
+  %t = add %u, %y
+  ret %t
+}
+
+fun @main(): i32 {
+%entry:
+  %a = call @getint()
+  %b = call @getint()
+  %c = call @getint()
+  %r1 = call @mix(%a, %b, %c)
+  %r2 = call @mix(%c, %a, %r1)
+  %r3 = call @mix(%r2, %c, %b)
+  %s = add %a, %b
+  %s1 = add %s, %c
+  %s2 = add %s1, %r1
+  %s3 = add %s2, %r2
+  %s4 = add %s3, %r3
+  ret %s4
+}
+"#;
+
+/// A branch passing arguments on both edges, whose false target comes
+/// right after it: the false arguments must still be written before
+/// falling through, and the two moves swap the values. Blocks are laid
+/// out in the order they are first named.
+const BOTH_EDGES: &str = r#"
+decl @getint(): i32
+
+fun @main(): i32 {
+%entry:
+  %x = call @getint()
+  %y = call @getint()
+  %z = ne %x, 0
+  br %z, %test, %large(0, 1)
+
+%test:
+  %c = lt %x, %y
+  br %c, %small(%x, %y), %large(%y, %x)
+
+%large(%p: i32, %q: i32):
+  %d = sub %q, %p
+  %r = mul %d, 100
+  ret %r
+
+%small(%u: i32, %v: i32):
+  %e = sub %v, %u
+  ret %e
+}
+"#;
+
+/// The number of spill slots and callee-saved registers `allocator` uses
+/// for `@main`, after checking that values live across calls are only
+/// given registers calls preserve.
+fn allocate(program: &Program, allocator: Allocator) -> (u32, usize) {
+    let data = program.func(func(program, "@main"));
+    let allocation = allocator.allocate(data);
+    for interval in intervals(data, &Numbering::new(data), &liveness(data)) {
+        if let Loc::Reg(reg) = allocation.locs[&interval.value] {
+            assert!(!interval.crosses_call || is_callee_saved(reg), "{:?} in a caller-saved register", interval);
+        }
+    }
+    (allocation.spill_slots, allocation.callee_saved.len())
+}
+
+#[test]
+fn spills_under_register_pressure() {
+    let text = pressure(40);
+    let input: Vec<i32> = (1..=40).collect();
+    check(&text, &[], &input);
+    let (spills, saved) = allocate(&parse(&text), Allocator::LinearScan);
+    assert!(spills > 0);
+    assert_eq!(saved, 11);
+}
+
+#[test]
+fn keeps_few_values_in_registers() {
+    let text = pressure(6);
+    check(&text, &[], &[5, -3, 8, 0, 2, 7]);
+    assert_eq!(allocate(&parse(&text), Allocator::LinearScan).0, 0);
+}
+
+#[test]
+fn values_survive_calls() {
+    check(CALLS, &[], &[3, 4, 5]);
+    check(CALLS, &[], &[-7, 11, 2]);
+    assert_eq!(allocate(&parse(CALLS), Allocator::LinearScan).0, 0);
+}
+
+#[test]
+fn both_edges_carry_arguments() {
+    for input in [[1, 9], [9, 1], [4, 4], [0, 5]] {
+        check(BOTH_EDGES, &[], &input);
+    }
+}
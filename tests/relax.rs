@@ -0,0 +1,43 @@
+mod common;
+
+use common::{check, compile, parse};
+use compiler::asm::regalloc::Allocator;
+use compiler::asm::relax::relax_branches;
+
+/// A loop with a `break`, whose body of `n` additions puts the exit out of
+/// reach of the test in the header.
+fn long_loop(n: usize) -> String {
+    let mut text = String::from("decl @getint(): i32\n\nglobal @sum = alloc i32, zeroinit\n\n");
+    text += "fun @main(): i32 {\n%entry:\n  %k = call @getint()\n  jump %head(0)\n\n";
+    text += "%head(%i: i32):\n  %c = lt %i, %k\n  br %c, %body, %exit\n\n";
+    text += "%body:\n  %s = load @sum\n  %x0 = add %s, %i\n";
+    for j in 1..n {
+        text += &format!("  %x{} = add %x{}, {}\n", j, j - 1, j % 7);
+    }
+    text += &format!("  store %x{}, @sum\n  %big = gt %x{}, 100000\n  br %big, %exit, %next\n\n", n - 1, n - 1);
+    text += "%exit:\n  %r = load @sum\n  ret %r\n\n%next:\n  %i1 = add %i, 1\n  jump %head(%i1)\n}\n";
+    text
+}
+
+#[test]
+fn relaxes_far_branches_only() {
+    let mut code = String::from("  beqz t0, far\n  bnez t1, near\nnear:\n");
+    code += &"  add t0, t0, t1\n".repeat(600);
+    code += "far:\n  ret\n";
+    let relaxed = relax_branches("f", &code);
+    assert!(relaxed.starts_with("  bnez t0, f_relax_0\n  j far\nf_relax_0:\n  bnez t1, near\nnear:\n"), "{}", relaxed);
+    assert_eq!(relax_branches("f", &relaxed), relaxed);
+    let near = "  beqz t0, l\n  li t0, 1\nl:\n  ret\n";
+    assert_eq!(relax_branches("f", near), near);
+}
+
+#[test]
+fn long_loops_run() {
+    let text = long_loop(1200);
+    for k in [0, 1, 5, 300] {
+        check(&text, &[], &[k]);
+    }
+    let asm = compile(&parse(&text), Allocator::LinearScan);
+    assert!(asm.contains("main_relax_0:"));
+    assert!(!compile(&parse(&long_loop(20)), Allocator::LinearScan).contains("_relax_"));
+}
@@ -0,0 +1,99 @@
+mod common;
+
+use common::{check, count, optimize, run, FRONTEND, SAMPLE};
+use compiler::opt::pipeline;
+
+/// `while (i < n) { s += i; i++; } return s * 100 + i;`
+const COUNTED: &str = r#"
+decl @getint(): i32
+
+fun @main(): i32 {
+%entry:
+  %n = call @getint()
+  jump %h(0, 0)
+
+%h(%i: i32, %s: i32):
+  %c = lt %i, %n
+  br %c, %body, %exit
+
+%body:
+  %s1 = add %s, %i
+  %i1 = add %i, 1
+  jump %h(%i1, %s1)
+
+%exit:
+  %t = mul %s, 100
+  %r = add %t, %i
+  ret %r
+}
+"#;
+
+/// A `while` loop with a `break`, joining the normal exit afterwards:
+/// `while (i < 9) { if (i == 5) break; i++; } return i * 10;`
+const BREAK: &str = r#"
+fun @main(): i32 {
+%entry:
+  jump %h(0)
+
+%h(%i: i32):
+  %c = lt %i, 9
+  br %c, %body, %exit
+
+%body:
+  %b = eq %i, 5
+  br %b, %brk, %latch
+
+%latch:
+  %n = add %i, 1
+  jump %h(%n)
+
+%brk:
+  jump %join
+
+%exit:
+  jump %join
+
+%join:
+  %r = mul %i, 10
+  ret %r
+}
+"#;
+
+#[test]
+fn moves_the_test_to_the_bottom() {
+    for n in [0, 1, 7] {
+        let program = check(COUNTED, &["rotate"], &[n]);
+        // the guard and the test at the bottom
+        assert_eq!(count(&program, "@main", "br"), 2);
+    }
+}
+
+#[test]
+fn leaves_loops_whose_header_values_reach_a_join() {
+    let program = check(BREAK, &["rotate"], &[]);
+    assert_eq!(count(&program, "@main", "br"), 2);
+    assert_eq!(run(&program, &[]).ret, 50);
+}
+
+#[test]
+fn breaks_passing_arguments() {
+    for text in [SAMPLE, FRONTEND] {
+        for input in [0, 2, 40] {
+            check(text, &["rotate"], &[input]);
+            check(text, &["mem2reg", "rotate"], &[input]);
+        }
+    }
+}
+
+#[test]
+fn break_to_join_survives_the_pipeline() {
+    let program = check(BREAK, &pipeline(2), &[]);
+    assert_eq!(run(&program, &[]).ret, 50);
+}
+
+#[test]
+fn rotated_loops_still_count() {
+    let program = optimize(COUNTED, &["rotate"]);
+    assert_eq!(run(&program, &[10]).ret, 4510);
+    assert_eq!(run(&program, &[0]).ret, 0);
+}
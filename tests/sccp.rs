@@ -0,0 +1,106 @@
+mod common;
+
+use common::{body, check, count, driver, optimize};
+
+/// `k` is 5 and only changes on a path that is never taken, so it stays 5
+/// around a loop with a `break`; `x` has its address taken by a call and
+/// stays in memory, and so does the array.
+const MEMORY: &str = r#"
+decl @getint(): i32
+
+fun @peek(%p: *i32): i32 {
+%entry:
+  %v = load %p
+  ret %v
+}
+
+fun @main(): i32 {
+%entry:
+  %k = alloc i32
+  %i = alloc i32
+  %x = alloc i32
+  %a = alloc [i32, 4]
+  %n = call @getint()
+  store 5, %k
+  store 0, %i
+  store 7, %x
+  %a1 = getelemptr %a, 1
+  store 9, %a1
+  jump %head
+
+%head:
+  %iv = load %i
+  %c = lt %iv, %n
+  br %c, %body, %exit
+
+%body:
+  %kv = load %k
+  %odd = ne %kv, 5
+  br %odd, %bump, %cont
+
+%bump:
+  %kv2 = load %k
+  %kv3 = add %kv2, 1
+  store %kv3, %k
+  jump %cont
+
+%cont:
+  %iv2 = load %i
+  %big = gt %iv2, 100
+  br %big, %exit, %step
+
+%step:
+  %iv3 = load %i
+  %kv4 = load %k
+  %iv4 = add %iv3, %kv4
+  store %iv4, %i
+  jump %head
+
+%exit:
+  %kv5 = load %k
+  %iv5 = load %i
+  %xv = call @peek(%x)
+  %av = load %a1
+  %r0 = mul %kv5, %iv5
+  %r1 = add %r0, %xv
+  %r = add %r1, %av
+  ret %r
+}
+"#;
+
+const PASSES: [&str; 3] = ["mem2reg", "sccp", "dce"];
+
+#[test]
+fn promotes_scalars_whose_address_stays_local() {
+    for n in [0, 3, 200] {
+        check(MEMORY, &["mem2reg"], &[n]);
+    }
+    let program = optimize(MEMORY, &["mem2reg"]);
+    let allocs: Vec<String> = body(&program, "@main").into_iter().filter(|i| i.contains("= alloc")).collect();
+    assert_eq!(allocs, ["%x = alloc i32", "%a = alloc [i32, 4]"]);
+    assert_eq!(count(&program, "@main", "load"), 1);
+}
+
+#[test]
+fn propagates_constants_around_loops() {
+    for n in [0, 3, 200] {
+        check(MEMORY, &PASSES, &[n]);
+    }
+    let program = optimize(MEMORY, &PASSES);
+    let main = body(&program, "@main");
+    // the branch on `k` and the block it never takes are gone
+    assert_eq!(count(&program, "@main", "ne"), 0);
+    assert!(!common::print(&program).contains("%bump"));
+    assert!(main.iter().any(|inst| inst.ends_with(", 5") && inst.contains("= add ")), "{:#?}", main);
+    assert_eq!(count(&program, "@main", "br"), 2);
+}
+
+#[test]
+fn collapses_constant_conditions() {
+    let source = "int main() { int a = 3; if (a > 2) { a = a + 1; } else { a = 0; } return a; }";
+    let run = driver(source, &["-koopa", "-passes=mem2reg,sccp,dce", "-verify"]);
+    assert!(run.success, "{}", run.stderr);
+    let blocks = run.output.lines().filter(|line| line.starts_with('%')).count();
+    assert_eq!(blocks, 1, "{}", run.output);
+    assert!(run.output.contains("  ret 4\n"), "{}", run.output);
+}
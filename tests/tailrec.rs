@@ -0,0 +1,202 @@
+mod common;
+
+use common::{body, check, compile, optimize, parse, print};
+use compiler::asm::regalloc::Allocator;
+use koopa::ir::Program;
+
+/// Tail-recursive functions: one summing with an accumulator and a `break`
+/// out of it, one counting down into a global, one using a local array it
+/// keeps to itself, and a wrapper returning what a call returns.
+const TAIL: &str = r#"
+decl @getint(): i32
+decl @putint(i32)
+
+global @total = alloc i32, zeroinit
+
+fun @sum(%n: i32, %acc: i32): i32 {
+%entry:
+  %z = le %n, 0
+  br %z, %done, %more
+
+%done:
+  ret %acc
+
+%more:
+  %big = gt %acc, 100000
+  br %big, %done, %next
+
+%next:
+  %m = sub %n, 1
+  %a = add %acc, %n
+  %r = call @sum(%m, %a)
+  ret %r
+}
+
+fun @count_down(%n: i32) {
+%entry:
+  %z = le %n, 0
+  br %z, %done, %more
+
+%done:
+  ret
+
+%more:
+  %t = load @total
+  %t1 = add %t, %n
+  store %t1, @total
+  %m = sub %n, 1
+  call @count_down(%m)
+  ret
+}
+
+fun @digits(%n: i32, %k: i32): i32 {
+%entry:
+  %buf = alloc [i32, 2]
+  %b0 = getelemptr %buf, 0
+  %q = div %n, 10
+  store %q, %b0
+  %b1 = getelemptr %buf, 1
+  %d = mod %n, 10
+  store %d, %b1
+  %z = eq %n, 0
+  br %z, %done, %more
+
+%done:
+  ret %k
+
+%more:
+  %x = load %b0
+  %k1 = add %k, 1
+  %r = call @digits(%x, %k1)
+  ret %r
+}
+
+fun @wrap(%x: i32): i32 {
+%entry:
+  %r = call @sum(%x, 0)
+  ret %r
+}
+
+fun @main(): i32 {
+%entry:
+  %n = call @getint()
+  %s = call @wrap(%n)
+  call @putint(%s)
+  call @count_down(%n)
+  %d = call @digits(%s, 0)
+  call @putint(%d)
+  %t = load @total
+  %r = add %t, %d
+  ret %r
+}
+"#;
+
+/// Recursion the pass has to leave alone: a call whose result is added to,
+/// and one reaching a local through its address.
+const NOT_TAIL: &str = r#"
+decl @getint(): i32
+
+fun @tri(%n: i32): i32 {
+%entry:
+  %z = le %n, 0
+  br %z, %yes, %no
+
+%yes:
+  ret 0
+
+%no:
+  %m = sub %n, 1
+  %r = call @tri(%m)
+  %t = add %r, %n
+  ret %t
+}
+
+fun @peek(%p: *i32): i32 {
+%entry:
+  %v = load %p
+  ret %v
+}
+
+fun @walk(%n: i32): i32 {
+%entry:
+  %cell = alloc i32
+  store %n, %cell
+  %v = call @peek(%cell)
+  %z = le %v, 0
+  br %z, %yes, %no
+
+%yes:
+  ret 0
+
+%no:
+  %m = sub %n, 1
+  %r = call @walk(%m)
+  ret %r
+}
+
+fun @main(): i32 {
+%entry:
+  %n = call @getint()
+  %t = call @tri(%n)
+  %w = call @walk(%n)
+  %r = add %t, %w
+  ret %r
+}
+"#;
+
+/// The number of calls to `callee` in the function called `name`.
+fn calls(program: &Program, name: &str, callee: &str) -> usize {
+    let call = format!("call {}(", callee);
+    body(program, name).iter().filter(|inst| inst.contains(&call)).count()
+}
+
+/// The code generated for the function called `name` in `asm`.
+fn code<'a>(asm: &'a str, name: &str) -> &'a str {
+    let start = asm.find(&format!("\n{}:\n", name)).unwrap_or_else(|| panic!("no function {}", name));
+    let rest = &asm[start + 1..];
+    rest.find(".global").map_or(rest, |end| &rest[..end])
+}
+
+#[test]
+fn recursion_becomes_a_loop() {
+    for n in [0, 1, 5, 100, 600] {
+        check(TAIL, &["tailrec"], &[n]);
+    }
+    let program = optimize(TAIL, &["tailrec"]);
+    for name in ["@sum", "@count_down", "@digits"] {
+        assert_eq!(calls(&program, name, name), 0, "{}", print(&program));
+    }
+    // the local stays allocated once, in the new entry
+    let digits = body(&program, "@digits");
+    assert!(digits[0].ends_with("= alloc [i32, 2]"), "{:?}", digits);
+}
+
+#[test]
+fn leaves_other_recursion_alone() {
+    for n in [0, 4, 30] {
+        check(NOT_TAIL, &["tailrec"], &[n]);
+    }
+    let program = optimize(NOT_TAIL, &["tailrec"]);
+    assert_eq!(calls(&program, "@tri", "@tri"), 1);
+    assert_eq!(calls(&program, "@walk", "@walk"), 1);
+}
+
+#[test]
+fn backend_jumps_to_tail_callees() {
+    for allocator in [Allocator::LinearScan, Allocator::GraphColoring] {
+        let asm = compile(&parse(TAIL), allocator);
+        let wrap = code(&asm, "wrap");
+        assert!(wrap.contains("  j sum\n") && !wrap.contains("call"), "{}", wrap);
+        assert!(code(&asm, "sum").contains("  j sum\n"));
+        assert!(code(&asm, "count_down").contains("  j count_down\n"));
+
+        // the callee would see a frame that is gone
+        let asm = compile(&parse(NOT_TAIL), allocator);
+        assert!(code(&asm, "walk").contains("  call walk\n"));
+        assert!(code(&asm, "tri").contains("  call tri\n"));
+    }
+    for n in [0, 7, 250] {
+        check(TAIL, &[], &[n]);
+        check(NOT_TAIL, &[], &[n]);
+    }
+}
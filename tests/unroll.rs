@@ -0,0 +1,118 @@
+mod common;
+
+use common::{body, check, func, parse, print, run, value};
+use compiler::analysis::dominators::DomTree;
+use compiler::analysis::induction::trip_count;
+use compiler::analysis::loops::LoopInfo;
+use compiler::opt::manager::PassManager;
+use compiler::session::Options;
+use koopa::ir::Program;
+
+/// `main` counting `%i` from `init` by `step` while `%i <op> bound`, adding
+/// `%i * %i` to a global and into a local array, and printing every value
+/// of `%i` once `pad` more additions made the body larger.
+fn counted(init: i32, op: &str, bound: &str, step: i32, pad: usize) -> String {
+    let mut text = String::from("decl @getint(): i32\ndecl @putint(i32)\n\nglobal @sum = alloc i32, zeroinit\n\n");
+    text += "fun @main(): i32 {\n%entry:\n  %a = alloc [i32, 4]\n  %a0 = getelemptr %a, 0\n  store 7, %a0\n";
+    text += &format!("  %n = call @getint()\n  jump %head({})\n\n", init);
+    text += &format!("%head(%i: i32):\n  %c = {} %i, {}\n  br %c, %body, %exit\n\n", op, bound);
+    text += "%body:\n  %sq = mul %i, %i\n  %s = load @sum\n  %s1 = add %s, %sq\n  store %s1, @sum\n";
+    text += "  %k = and %i, 3\n  %p = getelemptr %a, %k\n  store %sq, %p\n  %x0 = add %sq, %n\n";
+    for j in 1..=pad {
+        text += &format!("  %x{} = add %x{}, {}\n", j, j - 1, j);
+    }
+    text += &format!("  call @putint(%x{})\n  %i1 = add %i, {}\n  jump %head(%i1)\n\n", pad, step);
+    text += "%exit:\n  %r = load @sum\n  %q = getelemptr %a, 0\n  %f = load %q\n  %t = add %r, %f\n  ret %t\n}\n";
+    text
+}
+
+/// The number of loops in `@main`.
+fn loops(program: &Program) -> usize {
+    let data = program.func(func(program, "@main"));
+    LoopInfo::new(data, &DomTree::dominators(data)).loops.len()
+}
+
+/// The number of calls to `@putint` in `@main`, one per copy of the body.
+fn copies(program: &Program) -> usize {
+    body(program, "@main").iter().filter(|inst| inst.starts_with("call @putint(")).count()
+}
+
+/// The trip count found for the only loop of `@main` in `text`.
+fn trips(text: &str) -> Option<u32> {
+    let program = parse(text);
+    let data = program.func(func(&program, "@main"));
+    let info = LoopInfo::new(data, &DomTree::dominators(data));
+    assert_eq!(info.loops.len(), 1);
+    trip_count(data, &info.loops[0]).map(|trip| {
+        assert_eq!(trip.iv.param, value(data, "%i"));
+        trip.count
+    })
+}
+
+/// Parses `text` and unrolls by `factor`, verifying the result.
+fn unroll(text: &str, factor: Option<u32>) -> Program {
+    let mut program = parse(text);
+    let options = Options {
+        passes: Some(vec!["unroll".to_string()]),
+        verify: true,
+        unroll_factor: factor,
+        ..Options::default()
+    };
+    PassManager::from_options(&options).unwrap().run(&mut program).unwrap_or_else(|e| panic!("{}", e));
+    program
+}
+
+#[test]
+fn counts_trips() {
+    assert_eq!(trips(&counted(0, "lt", "10", 1, 0)), Some(10));
+    assert_eq!(trips(&counted(0, "le", "10", 3, 0)), Some(4));
+    assert_eq!(trips(&counted(10, "gt", "0", -2, 0)), Some(5));
+    assert_eq!(trips(&counted(5, "lt", "5", 1, 0)), Some(0));
+    assert_eq!(trips(&counted(0, "ne", "9", 3, 0)), Some(3));
+    assert_eq!(trips(&counted(0, "lt", "%n", 1, 0)), None);
+    // a `break` leaves from the body
+    assert_eq!(trips(common::SAMPLE), None);
+}
+
+#[test]
+fn fully_unrolls_small_loops() {
+    for text in [counted(0, "lt", "4", 1, 0), counted(10, "gt", "0", -3, 0), counted(3, "lt", "3", 1, 0)] {
+        check(&text, &["unroll"], &[5]);
+        let program = unroll(&text, None);
+        assert_eq!(loops(&program), 0, "{}", print(&program));
+    }
+    let program = unroll(&counted(0, "lt", "4", 1, 0), None);
+    assert_eq!(copies(&program), 4);
+}
+
+#[test]
+fn partially_unrolls_large_loops() {
+    // 102 trips of 43 instructions, 2 of them left to the original loop
+    let text = counted(0, "lt", "102", 1, 30);
+    let expected = run(&parse(&text), &[5]);
+    for (factor, n) in [(None, 4), (Some(3), 3), (Some(5), 5)] {
+        let program = unroll(&text, factor);
+        assert_eq!(run(&program, &[5]), expected, "{}", print(&program));
+        assert_eq!(loops(&program), 2, "{}", print(&program));
+        assert_eq!(copies(&program), n + 1);
+    }
+    check(&text, &["unroll"], &[-4]);
+    check(&counted(-7, "le", "200", 5, 30), &["unroll"], &[1]);
+
+    // no copies, or too many for the size limit
+    for factor in [1, 7] {
+        let program = unroll(&text, Some(factor));
+        assert_eq!(loops(&program), 1);
+        assert_eq!(copies(&program), 1);
+    }
+}
+
+#[test]
+fn leaves_other_loops_alone() {
+    for text in [counted(0, "lt", "%n", 1, 0), common::SAMPLE.to_string(), common::FRONTEND.to_string()] {
+        for input in [0, 3, 20] {
+            check(&text, &["unroll"], &[input]);
+        }
+        assert_eq!(loops(&unroll(&text, None)), 1);
+    }
+}
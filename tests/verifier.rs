@@ -0,0 +1,182 @@
+mod common;
+
+use common::{block, func, parse, value, FRONTEND, SAMPLE};
+use compiler::opt::manager::PassManager;
+use compiler::opt::verifier::verify;
+use compiler::opt::{FunctionPass, Pass};
+use compiler::session::Options;
+use koopa::ir::builder_traits::*;
+use koopa::ir::{BinaryOp, Function, FunctionData, Program, Type, Value, ValueKind};
+
+const DIAMOND: &str = r#"
+decl @getint(): i32
+
+fun @other(): i32 {
+%entry:
+  ret 1
+}
+
+fun @main(): i32 {
+%entry:
+  %x = call @getint()
+  br %x, %then, %else
+
+%then:
+  %y = add %x, 1
+  jump %join(%y)
+
+%else:
+  %z = sub %x, 1
+  jump %join(%z)
+
+%join(%r: i32):
+  ret %r
+}
+"#;
+
+/// The problems the verifier finds in `DIAMOND` once `edit` changed `@main`.
+fn broken(edit: impl FnOnce(&mut FunctionData)) -> Vec<String> {
+    let mut program = parse(DIAMOND);
+    let main = func(&program, "@main");
+    edit(program.func_mut(main));
+    verify(&program).expect_err("broken IR went unnoticed")
+}
+
+/// Appends `inst` to the block called `bb`.
+fn push(data: &mut FunctionData, bb: &str, inst: Value) {
+    let bb = block(data, bb);
+    data.layout_mut().bb_mut(bb).insts_mut().push_key_back(inst).unwrap();
+}
+
+/// Removes the last instruction of the block called `bb`.
+fn pop(data: &mut FunctionData, bb: &str) {
+    let bb = block(data, bb);
+    let last = *data.layout().bbs().node(&bb).unwrap().insts().back_key().unwrap();
+    data.layout_mut().bb_mut(bb).insts_mut().remove(&last);
+}
+
+#[test]
+fn accepts_well_formed_programs() {
+    for text in [DIAMOND, SAMPLE, FRONTEND] {
+        assert_eq!(verify(&parse(text)), Ok(()));
+    }
+}
+
+#[test]
+fn instructions_after_a_terminator() {
+    let errors = broken(|data| {
+        let x = value(data, "%x");
+        let dead = data.dfg_mut().new_value().binary(BinaryOp::Add, x, x);
+        push(data, "%join", dead);
+    });
+    assert!(errors.contains(&"@main, %join: instructions after a terminator".to_string()), "{:?}", errors);
+    assert!(errors.contains(&"@main, %join: basic block does not end in a terminator".to_string()));
+}
+
+#[test]
+fn blocks_without_a_terminator() {
+    let errors = broken(|data| pop(data, "%else"));
+    assert_eq!(errors, ["@main, %else: basic block does not end in a terminator"]);
+    let errors = broken(|data| {
+        let bb = data.dfg_mut().new_bb().basic_block(Some("%empty".into()));
+        data.layout_mut().bbs_mut().push_key_back(bb).unwrap();
+    });
+    assert_eq!(errors, ["@main, %empty: empty basic block"]);
+}
+
+#[test]
+fn duplicate_block_names() {
+    let errors = broken(|data| {
+        let bb = data.dfg_mut().new_bb().basic_block(Some("%then".into()));
+        data.layout_mut().bbs_mut().push_key_back(bb).unwrap();
+        let zero = data.dfg_mut().new_value().integer(0);
+        let ret = data.dfg_mut().new_value().ret(Some(zero));
+        data.layout_mut().bb_mut(bb).insts_mut().push_key_back(ret).unwrap();
+    });
+    assert_eq!(errors, ["@main, %then: block name `%then` is not unique"]);
+}
+
+#[test]
+fn operand_types() {
+    let errors = broken(|data| {
+        let (x, y) = (value(data, "%x"), value(data, "%y"));
+        let slot = data.dfg_mut().new_value().alloc(Type::get_i32());
+        let store = data.dfg_mut().new_value().store(x, slot);
+        let entry = data.layout().entry_bb().unwrap();
+        data.layout_mut().bb_mut(entry).insts_mut().push_key_front(slot).unwrap();
+        let jump = *data.layout().bbs().node(&block(data, "%then")).unwrap().insts().back_key().unwrap();
+        pop(data, "%then");
+        push(data, "%then", store);
+        push(data, "%then", jump);
+        // the builder refuses to make it point anywhere else
+        let mut bad = data.dfg().value(store).clone();
+        if let ValueKind::Store(s) = bad.kind_mut() {
+            *s.dest_mut() = y;
+        }
+        data.dfg_mut().replace_value_with(store).raw(bad);
+    });
+    assert_eq!(errors.len(), 1);
+    assert!(errors[0].starts_with("@main, %then: ") && errors[0].ends_with("into %y, which does not point to its type"));
+
+    let errors = broken(|data| {
+        let jump = *data.layout().bbs().node(&block(data, "%then")).unwrap().insts().back_key().unwrap();
+        let mut bad = data.dfg().value(jump).clone();
+        if let ValueKind::Jump(j) = bad.kind_mut() {
+            j.args_mut().clear();
+        }
+        data.dfg_mut().replace_value_with(jump).raw(bad);
+    });
+    assert_eq!(errors.len(), 1);
+    assert!(errors[0].ends_with(": arguments do not match the parameters of %join"), "{:?}", errors);
+}
+
+#[test]
+fn uses_not_dominated_by_their_definition() {
+    let errors = broken(|data| {
+        pop(data, "%else");
+        let (y, join) = (value(data, "%y"), block(data, "%join"));
+        let jump = data.dfg_mut().new_value().jump_with_args(join, vec![y]);
+        push(data, "%else", jump);
+    });
+    assert_eq!(errors, ["@main, %else: use of %y is not dominated by its definition"]);
+}
+
+#[test]
+fn branches_out_of_the_function() {
+    let errors = broken(|data| {
+        // a block of the function's own data flow graph, laid out nowhere
+        let stray = data.dfg_mut().new_bb().basic_block(Some("%stray".into()));
+        pop(data, "%else");
+        let jump = data.dfg_mut().new_value().jump(stray);
+        push(data, "%else", jump);
+    });
+    assert_eq!(errors, ["@main, %else: branch to a block outside the function"]);
+}
+
+/// Leaves an instruction after the `ret` of every function it runs on.
+struct Breaker;
+
+impl FunctionPass for Breaker {
+    fn run_on(&mut self, _: Function, data: &mut FunctionData) -> bool {
+        let one = data.dfg_mut().new_value().integer(1);
+        let dead = data.dfg_mut().new_value().binary(BinaryOp::Add, one, one);
+        let last = *data.layout().bbs().back_key().unwrap();
+        data.layout_mut().bb_mut(last).insts_mut().push_key_back(dead).unwrap();
+        true
+    }
+}
+
+#[test]
+fn manager_stops_at_the_pass_breaking_the_program() {
+    let mut program: Program = parse(DIAMOND);
+    let options = Options {
+        passes: Some(vec!["dce".to_string()]),
+        verify: true,
+        ..Options::default()
+    };
+    let mut manager = PassManager::from_options(&options).unwrap();
+    manager.add("breaker", Pass::Function(Box::new(Breaker)));
+    let error = manager.run(&mut program).unwrap_err();
+    assert!(error.starts_with("broken IR after breaker:\n"), "{}", error);
+    assert!(error.contains("\n@other, %entry: instructions after a terminator"), "{}", error);
+}